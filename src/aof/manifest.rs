@@ -0,0 +1,187 @@
+//! The manifest of a multi-part append-only file, in the format of Redis 7:
+//!
+//! ```text
+//! file appendonly.aof.2.base.rdb seq 2 type b
+//! file appendonly.aof.5.incr.aof seq 5 type i
+//! ```
+//!
+//! The dataset is the base file, written by the last rewrite, followed by the incremental
+//! files in order, which log the writes made since.
+
+use std::fmt::Display;
+
+/// A file listed by the manifest, with its sequence number among the files of its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofFile {
+    pub name: String,
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub base: Option<AofFile>,
+    pub incrs: Vec<AofFile>,
+}
+impl Manifest {
+    /// Parses a manifest, ignoring the history files that Redis lists until it deleted them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ManifestError` if a line is not a `file <name> seq <seq> type <b|i|h>`
+    /// description, or if there are several base files.
+    pub fn parse(manifest: &str) -> Result<Self, ManifestError> {
+        let mut parsed = Self::default();
+        for (index, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() % 2 != 0 {
+                return Err(ManifestError::Invalid(index + 1));
+            }
+            let field = |name: &str| {
+                fields
+                    .chunks(2)
+                    .find(|pair| pair[0] == name)
+                    .map(|pair| pair[1])
+            };
+            let (Some(name), Some(seq), Some(kind)) = (
+                field("file"),
+                field("seq").and_then(|seq| seq.parse().ok()),
+                field("type"),
+            ) else {
+                return Err(ManifestError::Invalid(index + 1));
+            };
+            let file = AofFile {
+                name: name.to_string(),
+                seq,
+            };
+            match kind {
+                "b" if parsed.base.is_none() => parsed.base = Some(file),
+                "b" => return Err(ManifestError::SeveralBases),
+                "i" => parsed.incrs.push(file),
+                "h" => {}
+                _ => return Err(ManifestError::Invalid(index + 1)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the base file a rewrite writes after `previous`, in the RDB format or as
+    /// commands.
+    #[must_use]
+    pub fn next_base(previous: Option<&AofFile>, prefix: &str, rdb: bool) -> AofFile {
+        let seq = previous.map_or(1, |base| base.seq + 1);
+        let extension = if rdb { "rdb" } else { "aof" };
+        AofFile {
+            name: format!("{prefix}.{seq}.base.{extension}"),
+            seq,
+        }
+    }
+
+    /// Returns the incremental file that follows the last one.
+    #[must_use]
+    pub fn next_incr(&self, prefix: &str) -> AofFile {
+        let seq = self.incrs.last().map_or(1, |incr| incr.seq + 1);
+        AofFile {
+            name: format!("{prefix}.{seq}.incr.aof"),
+            seq,
+        }
+    }
+
+    /// Returns the files of the manifest, the base first.
+    pub fn files(&self) -> impl Iterator<Item = &AofFile> {
+        self.base.iter().chain(&self.incrs)
+    }
+}
+impl Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(base) = &self.base {
+            writeln!(f, "file {} seq {} type b", base.name, base.seq)?;
+        }
+        for incr in &self.incrs {
+            writeln!(f, "file {} seq {} type i", incr.name, incr.seq)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ManifestError {
+    /// The line at the given number does not describe a file.
+    Invalid(usize),
+    SeveralBases,
+}
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(line) => write!(f, "Invalid manifest line {line}"),
+            Self::SeveralBases => write!(f, "Invalid manifest: found several base files"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = Manifest::parse(
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type h\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n\
+             file appendonly.aof.3.incr.aof type i seq 3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.base,
+            Some(AofFile {
+                name: "appendonly.aof.1.base.rdb".to_string(),
+                seq: 1
+            })
+        );
+        assert_eq!(manifest.incrs.len(), 2);
+        assert_eq!(
+            manifest.to_string(),
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n\
+             file appendonly.aof.3.incr.aof seq 3 type i\n"
+        );
+        assert_eq!(Manifest::parse(&manifest.to_string()).unwrap(), manifest);
+        assert_eq!(manifest.files().count(), 3);
+
+        assert_eq!(
+            Manifest::parse("file a seq 1 type b\nfile a seq"),
+            Err(ManifestError::Invalid(2))
+        );
+        assert_eq!(
+            Manifest::parse("file a seq x type b"),
+            Err(ManifestError::Invalid(1))
+        );
+        assert_eq!(
+            Manifest::parse("file a seq 1 type b\nfile b seq 2 type b"),
+            Err(ManifestError::SeveralBases)
+        );
+    }
+
+    #[test]
+    fn test_next_files() {
+        let manifest = Manifest::default();
+        let base = Manifest::next_base(None, "appendonly.aof", true);
+        assert_eq!(base.name, "appendonly.aof.1.base.rdb");
+        assert_eq!(
+            Manifest::next_base(Some(&base), "appendonly.aof", false).name,
+            "appendonly.aof.2.base.aof"
+        );
+        let incr = manifest.next_incr("appendonly.aof");
+        assert_eq!(incr.name, "appendonly.aof.1.incr.aof");
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: vec![incr],
+        };
+        assert_eq!(manifest.next_incr("appendonly.aof").seq, 2);
+    }
+}
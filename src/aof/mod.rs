@@ -0,0 +1,858 @@
+//! Append-only file persistence, split in several files like Redis 7.
+//!
+//! Every write applied to the keyspace is appended, as the RESP command propagated to
+//! replicas with absolute expirations, to the last incremental file. A rewrite compacts the
+//! dataset into a new base file, in the RDB format or as commands, while writes flow to a
+//! new incremental file; the manifest then drops the files the new base replaces.
+//! Replaying the base and incremental files listed by the manifest rebuilds the dataset.
+//!
+//! `appendfsync` sets when appended commands reach the disk: after every write, once per
+//! second, or whenever the operating system decides. A crash in the middle of an append
+//! leaves a truncated command at the end of the last file. With `aof-load-truncated` that
+//! command is cut off at load, otherwise the server refuses to start.
+
+pub mod manifest;
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    atomic_file,
+    rdb::{self, encoder, RdbError, Snapshot},
+    redis_commands::{CommandError, ExpireTime, RedisCommands, SetCondition, SetExpiration},
+    replication,
+    resp::decoder::{self, Decoded, ProtocolError},
+    server_config::persistence::{AppendFsync, PersistenceConfig},
+    store::{instant_from_unix_millis, unix_time_millis},
+    Keyspace, RedisValue,
+};
+
+use self::manifest::{AofFile, Manifest, ManifestError};
+
+/// The `aof_*` fields of `INFO persistence` while the append-only file is disabled.
+pub const DISABLED_INFO: &str = "aof_enabled:0\r\n\
+                                 aof_rewrite_in_progress:0\r\n\
+                                 aof_last_rewrite_time_sec:-1\r\n\
+                                 aof_current_rewrite_time_sec:-1\r\n\
+                                 aof_last_bgrewrite_status:ok\r\n\
+                                 aof_rewrites:0\r\n\
+                                 aof_rewrites_consecutive_failures:0\r\n\
+                                 aof_last_write_status:ok\r\n";
+
+/// The append-only files of the server, open for appending to the last incremental file.
+#[derive(Debug)]
+pub struct Aof {
+    dir: PathBuf,
+    /// Prefix of the names of the files, `appendfilename`.
+    prefix: String,
+    manifest: Manifest,
+    /// The last incremental file, and its size.
+    file: File,
+    incr_size: u64,
+    fsync: AppendFsync,
+    rdb_preamble: bool,
+    /// Size of all the files listed by the manifest.
+    current_size: u64,
+    /// Size of all the files after the last rewrite, or at startup, which automatic
+    /// rewrites compare the current size to.
+    base_size: u64,
+    /// Whether commands were appended since the file was last flushed to disk.
+    unsynced: bool,
+    last_write_ok: bool,
+    /// The base file being written by the running rewrite, and when the rewrite started.
+    rewriting: Option<(AofFile, Instant)>,
+    rewrites: RewriteStats,
+}
+
+/// Outcome of the rewrites, reported by `INFO persistence`.
+#[derive(Debug, Clone, Default)]
+struct RewriteStats {
+    last_duration: Option<Duration>,
+    last_failed: bool,
+    /// When the last failed rewrite ended, to delay automatic retries.
+    last_failure: Option<Instant>,
+    succeeded: u64,
+    consecutive_failures: u64,
+}
+impl Aof {
+    /// Opens the files listed by the manifest of `persistence`, appending to the last
+    /// incremental file, or to a new one if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `AofError` if the manifest cannot be read or the files cannot be opened.
+    pub fn open(persistence: &PersistenceConfig) -> Result<Self, AofError> {
+        let dir = persistence.aof_dir();
+        let manifest = read_manifest(&dir, persistence.appendfilename())?.unwrap_or_default();
+        Ok(Self::with_manifest(dir, persistence, manifest)?)
+    }
+
+    /// Creates the files of `persistence` with a base file holding `snapshot`, replacing
+    /// any previous manifest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the files cannot be written.
+    pub fn create(persistence: &PersistenceConfig, snapshot: Snapshot) -> io::Result<Self> {
+        let dir = persistence.aof_dir();
+        std::fs::create_dir_all(&dir)?;
+        let rdb_preamble = persistence.aof_use_rdb_preamble();
+        let base = Manifest::next_base(None, persistence.appendfilename(), rdb_preamble);
+        write_base(&dir.join(&base.name), snapshot, rdb_preamble)?;
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: vec![],
+        };
+        Self::with_manifest(dir, persistence, manifest)
+    }
+
+    fn with_manifest(
+        dir: PathBuf,
+        persistence: &PersistenceConfig,
+        mut manifest: Manifest,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let prefix = persistence.appendfilename().to_string();
+        if manifest.incrs.is_empty() {
+            let incr = manifest.next_incr(&prefix);
+            File::create(dir.join(&incr.name))?;
+            manifest.incrs.push(incr);
+            write_manifest(&dir, &prefix, &manifest)?;
+        }
+        let last = manifest.incrs.last().map(|incr| dir.join(&incr.name));
+        let file = OpenOptions::new()
+            .append(true)
+            .open(last.unwrap_or_default())?;
+        let current_size = files_size(&dir, &manifest)?;
+        let incr_size = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            prefix,
+            manifest,
+            file,
+            incr_size,
+            fsync: persistence.appendfsync(),
+            rdb_preamble: persistence.aof_use_rdb_preamble(),
+            current_size,
+            base_size: current_size,
+            unsynced: false,
+            last_write_ok: true,
+            rewriting: None,
+            rewrites: RewriteStats::default(),
+        })
+    }
+
+    /// Appends a write command, flushing it to disk right away with `appendfsync always`.
+    ///
+    /// A partial append is cut off so that the file stays loadable. With `always` the
+    /// server exits instead, like Redis, rather than acknowledge a write it could not
+    /// persist.
+    pub fn append(&mut self, command: &[u8]) {
+        let result = self.file.write_all(command).and_then(|()| {
+            if self.fsync == AppendFsync::Always {
+                self.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        match result {
+            Ok(()) => {
+                self.current_size += command.len() as u64;
+                self.incr_size += command.len() as u64;
+                self.unsynced |= self.fsync == AppendFsync::EverySec;
+                self.last_write_ok = true;
+            }
+            Err(e) => {
+                eprintln!("Error writing to the append only file: {e}");
+                if self.fsync == AppendFsync::Always {
+                    eprintln!("Cannot recover from an append only file error with appendfsync always, exiting");
+                    std::process::exit(1);
+                }
+                if let Err(e) = self.file.set_len(self.incr_size) {
+                    eprintln!("Cannot remove a partial command from the append only file: {e}");
+                }
+                self.last_write_ok = false;
+            }
+        }
+    }
+
+    /// Adopts the `appendfsync` and `aof-use-rdb-preamble` settings of `persistence`, as
+    /// changed by `CONFIG SET`.
+    pub const fn configure(&mut self, persistence: &PersistenceConfig) {
+        self.fsync = persistence.appendfsync();
+        self.rdb_preamble = persistence.aof_use_rdb_preamble();
+    }
+
+    /// Clears the count of rewrites, for `CONFIG RESETSTAT`.
+    pub const fn reset_stats(&mut self) {
+        self.rewrites.succeeded = 0;
+        self.rewrites.consecutive_failures = 0;
+    }
+
+    /// Returns a handle to flush the file to disk with, if commands were appended since the
+    /// last flush of `appendfsync everysec`.
+    ///
+    /// The flush is left to the caller so that it happens without holding any lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the file handle cannot be duplicated.
+    pub fn pending_fsync(&mut self) -> io::Result<Option<File>> {
+        if !self.unsynced {
+            return Ok(None);
+        }
+        let file = self.file.try_clone()?;
+        self.unsynced = false;
+        Ok(Some(file))
+    }
+
+    pub const fn rewrite_in_progress(&self) -> bool {
+        self.rewriting.is_some()
+    }
+
+    pub const fn last_rewrite_failure(&self) -> Option<Instant> {
+        self.rewrites.last_failure
+    }
+
+    /// Returns whether the files grew by `percentage` percent since the last rewrite and
+    /// reached `min_size` bytes, a percentage of 0 disabling automatic rewrites.
+    pub fn rewrite_due(&self, percentage: u64, min_size: u64) -> bool {
+        if percentage == 0 || self.rewrite_in_progress() || self.current_size < min_size {
+            return false;
+        }
+        let base_size = self.base_size.max(1);
+        self.current_size.saturating_sub(base_size) * 100 / base_size >= percentage
+    }
+
+    /// Starts a rewrite: writes now go to a new incremental file, and the returned
+    /// `Rewrite` writes the base file that replaces the files before it.
+    ///
+    /// The caller holds the store lock, so that the snapshot given to the rewrite holds
+    /// exactly the writes of the previous files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the new incremental file or the manifest cannot be
+    /// written.
+    pub fn start_rewrite(&mut self) -> io::Result<Rewrite> {
+        if self.fsync != AppendFsync::No {
+            self.file.sync_data()?;
+        }
+        let incr = self.manifest.next_incr(&self.prefix);
+        let file = create_incr(&self.dir.join(&incr.name))?;
+        let mut manifest = self.manifest.clone();
+        manifest.incrs.push(incr);
+        write_manifest(&self.dir, &self.prefix, &manifest)?;
+        self.manifest = manifest;
+        self.file = file;
+        self.incr_size = 0;
+        self.unsynced = false;
+        // A rewrite superseding a running one must not write the same base file.
+        let previous = self
+            .rewriting
+            .as_ref()
+            .map(|(base, _)| base)
+            .or(self.manifest.base.as_ref());
+        let base = Manifest::next_base(previous, &self.prefix, self.rdb_preamble);
+        self.rewriting = Some((base.clone(), Instant::now()));
+        Ok(Rewrite {
+            path: self.dir.join(&base.name),
+            base,
+            rdb_preamble: self.rdb_preamble,
+        })
+    }
+
+    /// Ends `rewrite`: once its base file is written, it replaces the files before the
+    /// incremental file the rewrite started. A rewrite superseded by another is discarded.
+    pub fn finish_rewrite(&mut self, rewrite: &Rewrite, written: bool) {
+        let Some((base, started)) = self.rewriting.take_if(|(base, _)| *base == rewrite.base)
+        else {
+            let _ = std::fs::remove_file(&rewrite.path);
+            return;
+        };
+        self.rewrites.last_duration = Some(started.elapsed());
+        let result = if written {
+            self.install(base)
+        } else {
+            Err(io::Error::other("the base file was not written"))
+        };
+        match result {
+            Ok(()) => {
+                println!("Background append only file rewriting finished");
+                self.rewrites.last_failed = false;
+                self.rewrites.last_failure = None;
+                self.rewrites.succeeded += 1;
+                self.rewrites.consecutive_failures = 0;
+            }
+            Err(e) => {
+                eprintln!("Background append only file rewriting failed: {e}");
+                let _ = std::fs::remove_file(&rewrite.path);
+                self.rewrites.last_failed = true;
+                self.rewrites.last_failure = Some(Instant::now());
+                self.rewrites.consecutive_failures += 1;
+            }
+        }
+    }
+
+    /// Replaces the files with a base holding `snapshot`, as when the dataset was replaced
+    /// by the one of a master. A running rewrite is superseded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the files cannot be written, the previous ones being
+    /// kept in that case.
+    pub fn reset(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        let rewrite = self.start_rewrite()?;
+        self.rewriting = None;
+        rewrite.write(snapshot)?;
+        self.install(rewrite.base)
+    }
+
+    /// Makes `base` the base file, followed by the last incremental file alone, and deletes
+    /// the files it replaces.
+    fn install(&mut self, base: AofFile) -> io::Result<()> {
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: self.manifest.incrs.last().cloned().into_iter().collect(),
+        };
+        write_manifest(&self.dir, &self.prefix, &manifest)?;
+        let replaced = std::mem::replace(&mut self.manifest, manifest);
+        for file in replaced.files() {
+            if !self.manifest.files().any(|kept| kept == file) {
+                if let Err(e) = std::fs::remove_file(self.dir.join(&file.name)) {
+                    eprintln!("Cannot remove append only file {}: {e}", file.name);
+                }
+            }
+        }
+        self.current_size = files_size(&self.dir, &self.manifest)?;
+        self.base_size = self.current_size;
+        Ok(())
+    }
+
+    /// Returns the `aof_*` fields of `INFO persistence`.
+    pub fn info(&self) -> String {
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or(-1, |duration| {
+                i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+            })
+        };
+        let status = |ok: bool| if ok { "ok" } else { "err" };
+        format!(
+            "aof_enabled:1\r\n\
+             aof_rewrite_in_progress:{}\r\n\
+             aof_last_rewrite_time_sec:{}\r\n\
+             aof_current_rewrite_time_sec:{}\r\n\
+             aof_last_bgrewrite_status:{}\r\n\
+             aof_rewrites:{}\r\n\
+             aof_rewrites_consecutive_failures:{}\r\n\
+             aof_last_write_status:{}\r\n\
+             aof_current_size:{}\r\n\
+             aof_base_size:{}\r\n",
+            u8::from(self.rewrite_in_progress()),
+            seconds(self.rewrites.last_duration),
+            seconds(
+                self.rewriting
+                    .as_ref()
+                    .map(|(_, started)| started.elapsed())
+            ),
+            status(!self.rewrites.last_failed),
+            self.rewrites.succeeded,
+            self.rewrites.consecutive_failures,
+            status(self.last_write_ok),
+            self.current_size,
+            self.base_size,
+        )
+    }
+}
+
+/// A rewrite started by `Aof::start_rewrite`, whose base file is written without holding
+/// any lock.
+#[derive(Debug)]
+pub struct Rewrite {
+    base: AofFile,
+    path: PathBuf,
+    rdb_preamble: bool,
+}
+impl Rewrite {
+    /// Writes `snapshot` as the new base file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the file cannot be written.
+    pub fn write(&self, snapshot: Snapshot) -> io::Result<()> {
+        write_base(&self.path, snapshot, self.rdb_preamble)
+    }
+}
+
+/// Writes a base file holding `snapshot`, in the RDB format or as commands.
+fn write_base(path: &Path, mut snapshot: Snapshot, rdb_preamble: bool) -> io::Result<()> {
+    if rdb_preamble {
+        snapshot.set_aux("aof-base", "1");
+        atomic_file::write(path, &encoder::encode(&snapshot))
+    } else {
+        atomic_file::write(path, &encode_snapshot(&snapshot))
+    }
+}
+
+/// Creates an empty incremental file, open for appending.
+fn create_incr(path: &Path) -> io::Result<File> {
+    File::create(path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+/// Returns the total size of the files listed by `manifest`.
+fn files_size(dir: &Path, manifest: &Manifest) -> io::Result<u64> {
+    manifest
+        .files()
+        .map(|file| std::fs::metadata(dir.join(&file.name)).map(|metadata| metadata.len()))
+        .sum()
+}
+
+fn manifest_path(dir: &Path, prefix: &str) -> PathBuf {
+    dir.join(format!("{prefix}.manifest"))
+}
+
+/// Reads the manifest of the files of `dir` named after `prefix`, `None` if there is none.
+fn read_manifest(dir: &Path, prefix: &str) -> Result<Option<Manifest>, AofError> {
+    match std::fs::read_to_string(manifest_path(dir, prefix)) {
+        Ok(manifest) => Ok(Some(Manifest::parse(&manifest)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(AofError::Io(error)),
+    }
+}
+
+fn write_manifest(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<()> {
+    atomic_file::write(&manifest_path(dir, prefix), manifest.to_string().as_bytes())
+}
+
+/// Encodes the keys of database 0 of `snapshot` as the commands that recreate them.
+#[must_use]
+pub fn encode_snapshot(snapshot: &Snapshot) -> Vec<u8> {
+    snapshot
+        .entries
+        .iter()
+        .filter(|entry| entry.db == 0)
+        .flat_map(|entry| replication::encode_set(&entry.key, &entry.value, entry.expiration))
+        .collect()
+}
+
+/// Loads the files listed by the manifest of `persistence` into `keyspace`, returning how
+/// many keys and commands were loaded, or `None` if there are no files.
+///
+/// A single append-only file written before files were split, at `dir/appendfilename`, is
+/// first moved into the directory as the base file. Only the last file may be truncated.
+///
+/// # Errors
+///
+/// Returns an `AofError` if the manifest or a file cannot be loaded.
+pub fn load(
+    persistence: &PersistenceConfig,
+    keyspace: &mut Keyspace,
+) -> Result<Option<usize>, AofError> {
+    let dir = persistence.aof_dir();
+    let manifest = match read_manifest(&dir, persistence.appendfilename())? {
+        Some(manifest) => manifest,
+        None => match upgrade(persistence)? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        },
+    };
+    let load_truncated = persistence.aof_load_truncated();
+    let mut loaded = 0;
+    if let Some(base) = &manifest.base {
+        let last = manifest.incrs.is_empty();
+        loaded += load_base(&dir.join(&base.name), keyspace, load_truncated && last)
+            .map_err(|e| AofError::File(base.name.clone(), Box::new(e)))?;
+    }
+    for (index, incr) in manifest.incrs.iter().enumerate() {
+        let last = index + 1 == manifest.incrs.len();
+        loaded += replay(&dir.join(&incr.name), keyspace, load_truncated && last)
+            .map_err(|e| AofError::File(incr.name.clone(), Box::new(e)))?;
+    }
+    Ok(Some(loaded))
+}
+
+/// Moves the single append-only file of `persistence` into its directory, as the base file
+/// of a new manifest, `None` if there is no such file.
+fn upgrade(persistence: &PersistenceConfig) -> Result<Option<Manifest>, AofError> {
+    let prefix = persistence.appendfilename();
+    let single = persistence.dir().join(prefix);
+    if !single.is_file() {
+        return Ok(None);
+    }
+    let dir = persistence.aof_dir();
+    std::fs::create_dir_all(&dir)?;
+    std::fs::rename(&single, dir.join(prefix))?;
+    let manifest = Manifest {
+        base: Some(AofFile {
+            name: prefix.to_string(),
+            seq: 1,
+        }),
+        incrs: vec![],
+    };
+    write_manifest(&dir, prefix, &manifest)?;
+    println!(
+        "Moved append only file {} to {} as the base of its manifest",
+        single.display(),
+        dir.display()
+    );
+    Ok(Some(manifest))
+}
+
+/// Loads a base file, in the RDB format or as commands, returning how many keys or commands
+/// it held.
+fn load_base(
+    path: &Path,
+    keyspace: &mut Keyspace,
+    load_truncated: bool,
+) -> Result<usize, AofError> {
+    let data = std::fs::read(path)?;
+    if data.starts_with(rdb::MAGIC) {
+        let snapshot = rdb::decoder::decode(&data).map_err(AofError::Rdb)?;
+        return Ok(snapshot.load_into(keyspace));
+    }
+    replay(path, keyspace, load_truncated)
+}
+
+/// Replays the commands of the file at `path` into `keyspace`, returning how many were
+/// applied.
+///
+/// A command cut short at the end of the file is removed from the file when
+/// `load_truncated` is set.
+///
+/// # Errors
+///
+/// Returns an `AofError` if the file cannot be read, holds something else than the write
+/// commands this server appends, or is truncated while `load_truncated` is not set.
+pub fn replay(
+    path: &Path,
+    keyspace: &mut Keyspace,
+    load_truncated: bool,
+) -> Result<usize, AofError> {
+    let data = std::fs::read(path)?;
+    let (mut position, mut applied) = (0, 0);
+    while position < data.len() {
+        let decoded =
+            decoder::decode(&data[position..]).map_err(|e| AofError::Protocol(position, e))?;
+        let Decoded::Frame(frame, length) = decoded else {
+            if !load_truncated {
+                return Err(AofError::Truncated(position));
+            }
+            eprintln!(
+                "!!! Warning: short read while loading the append only file {}, truncating it to {position} bytes",
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(position as u64))?;
+            break;
+        };
+        let command = RedisCommands::parse(&frame).map_err(|e| AofError::Command(position, e))?;
+        apply(command, keyspace).map_err(|command| AofError::Unsupported(position, command))?;
+        position += length;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// Applies a command read from the file to `keyspace`, returning the command back if it is
+/// not one this server appends.
+fn apply(command: RedisCommands, keyspace: &mut Keyspace) -> Result<(), String> {
+    match command {
+        RedisCommands::Set(key, value, options) => {
+            let previous = keyspace.get(&key).map(RedisValue::expiration);
+            let allowed = match options.condition {
+                None => true,
+                Some(SetCondition::NotExists) => previous.is_none(),
+                Some(SetCondition::Exists) => previous.is_some(),
+            };
+            if !allowed {
+                return Ok(());
+            }
+            let expiration = match options.expiration {
+                None => None,
+                Some(SetExpiration::In(duration)) => Some(Instant::now() + duration),
+                Some(SetExpiration::At(time)) => match time.duration_since(SystemTime::now()) {
+                    Ok(remaining) if !remaining.is_zero() => Some(Instant::now() + remaining),
+                    _ => {
+                        keyspace.remove(&key);
+                        return Ok(());
+                    }
+                },
+                Some(SetExpiration::KeepTtl) => previous.flatten(),
+            };
+            keyspace.insert(key, RedisValue::expiring_at(value, expiration));
+        }
+        RedisCommands::Expire(key, time, conditions) if conditions.is_empty() => {
+            let unix_deadline = match time {
+                ExpireTime::In(milliseconds) => unix_time_millis().saturating_add(milliseconds),
+                ExpireTime::At(milliseconds) => milliseconds,
+            };
+            match instant_from_unix_millis(unix_deadline) {
+                Some(deadline) => {
+                    keyspace.set_expiration(&key, Some(deadline));
+                }
+                None => {
+                    keyspace.remove(&key);
+                }
+            }
+        }
+        RedisCommands::Persist(key) => {
+            keyspace.set_expiration(&key, None);
+        }
+        RedisCommands::Del(keys) => {
+            for key in keys {
+                keyspace.remove(&key);
+            }
+        }
+        command => return Err(command.to_string()),
+    }
+    Ok(())
+}
+
+/// Builds the keyspace the server starts with from the append-only files of
+/// `persistence`, `None` if there are none yet.
+///
+/// # Panics
+///
+/// Panics if the files cannot be loaded, as Redis refuses to start rather than silently
+/// dropping writes.
+#[must_use]
+pub fn load_keyspace(persistence: &PersistenceConfig) -> Option<Keyspace> {
+    let mut keyspace = Keyspace::new();
+    let dir = persistence.aof_dir();
+    match load(persistence, &mut keyspace) {
+        Ok(None) => return None,
+        Ok(Some(loaded)) => {
+            println!("Loaded {loaded} keys and commands from {}", dir.display());
+        }
+        Err(error) => panic!(
+            "Cannot load append only files of {}: {error}",
+            dir.display()
+        ),
+    }
+    keyspace.mark_saved(keyspace.dirty());
+    Some(keyspace)
+}
+
+#[derive(Debug)]
+pub enum AofError {
+    Io(io::Error),
+    Manifest(ManifestError),
+    /// A base file in the RDB format cannot be decoded.
+    Rdb(RdbError),
+    /// The bytes at the given offset are not a RESP command.
+    Protocol(usize, ProtocolError),
+    /// The command at the given offset cannot be parsed.
+    Command(usize, CommandError),
+    /// The command at the given offset is not a write this server appends.
+    Unsupported(usize, String),
+    /// The last command, starting at the given offset, was cut short.
+    Truncated(usize),
+    /// An error loading the named file.
+    File(String, Box<Self>),
+}
+impl From<io::Error> for AofError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+impl From<ManifestError> for AofError {
+    fn from(error: ManifestError) -> Self {
+        Self::Manifest(error)
+    }
+}
+impl Display for AofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Manifest(e) => write!(f, "{e}"),
+            Self::Rdb(e) => write!(f, "Bad RDB base file: {e}"),
+            Self::Protocol(offset, e) => write!(f, "Bad file format at offset {offset}: {e}"),
+            Self::Command(offset, e) => write!(f, "Invalid command at offset {offset}: {e}"),
+            Self::Unsupported(offset, command) => {
+                write!(f, "Unsupported command at offset {offset}: {command}")
+            }
+            Self::Truncated(offset) => write!(
+                f,
+                "Unexpected end of file at offset {offset}, \
+                 start with --aof-load-truncated yes to load it anyway"
+            ),
+            Self::File(name, e) => write!(f, "{name}: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{replication::encode_command, MasterConfig};
+
+    /// Returns the persistence configuration of an empty directory named after `name`.
+    fn persistence(name: &str, options: &[&str]) -> PersistenceConfig {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut args = vec!["--dir", dir.to_str().unwrap(), "--appendonly", "yes"];
+        args.extend(options);
+        crate::Config::Master(MasterConfig::from_args(&args).unwrap())
+            .persistence()
+            .clone()
+    }
+
+    fn empty() -> Snapshot {
+        Snapshot::from_keyspace(&Keyspace::new())
+    }
+
+    fn load_all(persistence: &PersistenceConfig) -> Keyspace {
+        let mut keyspace = Keyspace::new();
+        load(persistence, &mut keyspace).unwrap().unwrap();
+        keyspace
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let persistence = persistence("aof-replay", &["--appendfsync", "always"]);
+        assert!(load(&persistence, &mut Keyspace::new()).unwrap().is_none());
+        let future = (unix_time_millis() + 60_000).to_string();
+        let past = (unix_time_millis() - 1000).to_string();
+        let mut aof = Aof::create(&persistence, empty()).unwrap();
+        for command in [
+            replication::encode_set(b"job", b"1", None),
+            replication::encode_set(b"lease", b"worker", Some(unix_time_millis() + 60_000)),
+            replication::encode_set(b"done", b"0", None),
+            encode_command(&[b"PEXPIREAT", b"job", future.as_bytes()]),
+            encode_command(&[b"PERSIST", b"job"]),
+            encode_command(&[b"PEXPIREAT", b"done", past.as_bytes()]),
+        ] {
+            aof.append(&command);
+        }
+        let info = aof.info();
+        assert!(info.contains("aof_last_write_status:ok\r\n"));
+        assert!(info.contains(&format!("aof_current_size:{}\r\n", aof.current_size)));
+
+        let mut keyspace = Keyspace::new();
+        assert_eq!(load(&persistence, &mut keyspace).unwrap(), Some(6));
+        assert_eq!(keyspace.len(), 2);
+        assert_eq!(keyspace.get(b"job").unwrap().expiration(), None);
+        assert!(keyspace.get(b"lease").unwrap().expiration().is_some());
+        assert!(keyspace.get(b"done").is_none());
+        std::fs::remove_dir_all(persistence.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_compacts_files() {
+        for preamble in ["yes", "no"] {
+            let persistence = persistence("aof-rewrite", &["--aof-use-rdb-preamble", preamble]);
+            let mut aof = Aof::create(&persistence, empty()).unwrap();
+            let mut keyspace = Keyspace::new();
+            for counter in 0..100 {
+                let value = Bytes::from(format!("{counter}"));
+                aof.append(&replication::encode_set(b"counter", &value, None));
+                keyspace.insert(Bytes::from("counter"), RedisValue::new(value, None));
+            }
+            assert!(aof.rewrite_due(100, 0));
+            let size = aof.current_size;
+
+            let rewrite = aof.start_rewrite().unwrap();
+            assert!(aof.rewrite_in_progress());
+            assert!(aof.info().contains("aof_rewrite_in_progress:1\r\n"));
+            aof.append(&replication::encode_set(b"after", b"rewrite", None));
+            rewrite.write(Snapshot::from_keyspace(&keyspace)).unwrap();
+            aof.finish_rewrite(&rewrite, true);
+
+            assert!(!aof.rewrite_in_progress());
+            assert!(aof.current_size < size);
+            assert!(!aof.rewrite_due(100, 0));
+            assert_eq!(aof.manifest.base.as_ref().unwrap().seq, 2);
+            assert_eq!(aof.manifest.incrs.len(), 1);
+            assert!(aof.info().contains("aof_rewrites:1\r\n"));
+            let files = std::fs::read_dir(persistence.aof_dir()).unwrap().count();
+            assert_eq!(files, 3);
+            let mut keyspace = load_all(&persistence);
+            assert_eq!(keyspace.get(b"counter").unwrap().value(), "99");
+            assert_eq!(keyspace.get(b"after").unwrap().value(), "rewrite");
+            std::fs::remove_dir_all(persistence.dir()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reset_supersedes_rewrite() {
+        let persistence = persistence("aof-reset", &[]);
+        let mut aof = Aof::create(&persistence, empty()).unwrap();
+        aof.append(&replication::encode_set(b"stale", b"1", None));
+        let rewrite = aof.start_rewrite().unwrap();
+
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(
+            Bytes::from("synced"),
+            RedisValue::new(Bytes::from("1"), None),
+        );
+        aof.reset(Snapshot::from_keyspace(&keyspace)).unwrap();
+        rewrite.write(empty()).unwrap();
+        aof.finish_rewrite(&rewrite, true);
+        assert!(!rewrite.path.exists());
+        assert!(aof.info().contains("aof_rewrites:0\r\n"));
+
+        let mut keyspace = load_all(&persistence);
+        assert!(keyspace.get(b"stale").is_none());
+        assert!(keyspace.get(b"synced").is_some());
+        std::fs::remove_dir_all(persistence.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_tail() {
+        let persistence = persistence("aof-truncated", &["--aof-load-truncated", "no"]);
+        let mut aof = Aof::create(&persistence, empty()).unwrap();
+        let complete = replication::encode_set(b"key", b"value", None);
+        aof.append(&complete);
+        aof.append(&replication::encode_set(b"other", b"value", None)[..10]);
+        let incr = persistence.aof_dir().join(&aof.manifest.incrs[0].name);
+
+        assert!(matches!(
+            load(&persistence, &mut Keyspace::new()),
+            Err(AofError::File(_, e)) if matches!(*e, AofError::Truncated(offset) if offset == complete.len())
+        ));
+        let dir = persistence.dir().to_str().unwrap().to_string();
+        let persistence = MasterConfig::from_args(&["--dir", &dir, "--appendonly", "yes"]).unwrap();
+        let persistence = crate::Config::Master(persistence).persistence().clone();
+        let mut keyspace = load_all(&persistence);
+        assert_eq!(keyspace.get(b"key").unwrap().value(), "value");
+        assert_eq!(std::fs::read(&incr).unwrap(), complete.to_vec());
+        std::fs::remove_dir_all(persistence.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_single_file_upgrade() {
+        let persistence = persistence("aof-upgrade", &[]);
+        let single = persistence.dir().join("appendonly.aof");
+        std::fs::write(&single, replication::encode_set(b"key", b"value", None)).unwrap();
+
+        let mut keyspace = load_all(&persistence);
+        assert!(keyspace.get(b"key").is_some());
+        assert!(!single.exists());
+        let mut aof = Aof::open(&persistence).unwrap();
+        aof.append(&replication::encode_set(b"next", b"value", None));
+        assert_eq!(load_all(&persistence).len(), 2);
+
+        std::fs::write(
+            persistence.aof_dir().join("appendonly.aof.1.incr.aof"),
+            encode_command(&[b"GET", b"key"]),
+        )
+        .unwrap();
+        assert!(matches!(
+            load(&persistence, &mut Keyspace::new()),
+            Err(AofError::File(_, e)) if matches!(*e, AofError::Unsupported(0, _))
+        ));
+        std::fs::remove_dir_all(persistence.dir()).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+//! Atomic replacement of the files the server persists: RDB snapshots, append-only files,
+//! their manifest and the configuration file.
+//!
+//! The data goes to a temporary file of the same directory, which then replaces the
+//! target, so that a crash never leaves a truncated file behind.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Writes `data` to `path` atomically.
+///
+/// Each write has its own temporary file, as `SAVE`, `BGSAVE` and a replica receiving a
+/// snapshot from its master may write the same file at the same time.
+///
+/// # Errors
+///
+/// Returns an `std::io::Error` if the temporary file cannot be written or renamed.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let temporary = path.with_file_name(format!(
+        "temp-{}-{}-{name}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&temporary).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(error) = result.and_then(|()| std::fs::rename(&temporary, path)) {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writes_do_not_clash() {
+        let dir = std::env::temp_dir().join(format!("atomic-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| write(&path, b"data").unwrap());
+            }
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_leaves_target_alone() {
+        let dir = std::env::temp_dir().join(format!("atomic-file-fail-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        assert!(write(&dir.join("target"), b"data").is_err());
+        assert!(dir.join("target").is_dir());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,590 @@
+//! The table of commands known by the server.
+//!
+//! Every command is described once here: its arity, its flags, where its keys are, which
+//! ACL categories it belongs to and how its arguments are parsed. Parsing looks the name up
+//! in this table, checks the arity and hands the arguments to the parser of the entry,
+//! read-only replicas and the link to a master rely on its flags to tell writes apart, and
+//! `COMMAND` reports it to client libraries that introspect the server.
+
+use std::fmt::Display;
+
+use crate::{
+    redis_commands::{key, CommandError, RedisCommands, TimeUnit},
+    resp::{Array, BulkString, SimpleString, Type},
+};
+
+/// Properties of a command, reported as simple strings by `COMMAND INFO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    /// The command may modify the dataset.
+    Write,
+    /// The command never modifies the dataset.
+    Readonly,
+    /// The command may grow memory usage and is refused once `maxmemory` is reached.
+    Denyoom,
+    /// The command is reserved to administration and replication.
+    Admin,
+    /// The command is related to publish/subscribe.
+    Pubsub,
+    /// The command cannot be called from scripts.
+    Noscript,
+    /// The command is allowed while the dataset is loading.
+    Loading,
+    /// The command is allowed on a replica with stale data.
+    Stale,
+    /// The command runs in constant or logarithmic time.
+    Fast,
+    /// The command can be called before authenticating.
+    NoAuth,
+}
+impl Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Write => write!(f, "write"),
+            Self::Readonly => write!(f, "readonly"),
+            Self::Denyoom => write!(f, "denyoom"),
+            Self::Admin => write!(f, "admin"),
+            Self::Pubsub => write!(f, "pubsub"),
+            Self::Noscript => write!(f, "noscript"),
+            Self::Loading => write!(f, "loading"),
+            Self::Stale => write!(f, "stale"),
+            Self::Fast => write!(f, "fast"),
+            Self::NoAuth => write!(f, "no_auth"),
+        }
+    }
+}
+
+/// Parser of the arguments of a command.
+pub type Parser = fn(&[BulkString]) -> Result<RedisCommands, CommandError>;
+
+/// Static description of a command.
+#[derive(Debug)]
+pub struct CommandDescriptor {
+    /// Lowercase name of the command.
+    pub name: &'static str,
+    /// Number of arguments, command name included. A negative arity `-n` means at least `n`.
+    pub arity: i64,
+    pub flags: &'static [Flag],
+    /// Position of the first key argument, 0 if the command takes no key.
+    pub first_key: i64,
+    /// Position of the last key argument, -1 meaning the last argument.
+    pub last_key: i64,
+    /// Step between two key arguments.
+    pub step: i64,
+    /// ACL categories of the command, without their `@` prefix.
+    pub acl_categories: &'static [&'static str],
+    pub summary: &'static str,
+    pub since: &'static str,
+    pub group: &'static str,
+    /// Builds the command from its arguments, the name excluded, once their number
+    /// matches the arity.
+    pub parse: Parser,
+}
+impl CommandDescriptor {
+    /// Returns whether a call with `argc` arguments, command name included, has a valid arity.
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = i64::try_from(argc).unwrap_or(i64::MAX);
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    pub fn has_flag(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Returns whether the command may modify the dataset: read-only replicas refuse it
+    /// from clients, and it is the only kind applied from the link to a master. The flag
+    /// does not decide propagation: the handler of a write propagates it while it holds
+    /// the store, so that replicas apply writes in the order of the master, and only when
+    /// it changed the dataset, rewritten into a deterministic form.
+    pub fn is_write(&self) -> bool {
+        self.has_flag(Flag::Write)
+    }
+
+    /// Returns the reply entry of `COMMAND INFO` for this command.
+    pub fn info(&self) -> Type {
+        let status = |value: &str| Type::SimpleString(SimpleString::from(value));
+        Type::Array(Array::from(vec![
+            Type::BulkString(BulkString::from(self.name)),
+            Type::Integer(self.arity),
+            Type::Set(
+                self.flags
+                    .iter()
+                    .map(|flag| status(&flag.to_string()))
+                    .collect(),
+            ),
+            Type::Integer(self.first_key),
+            Type::Integer(self.last_key),
+            Type::Integer(self.step),
+            Type::Set(
+                self.acl_categories
+                    .iter()
+                    .map(|category| status(&format!("@{category}")))
+                    .collect(),
+            ),
+            Type::Array(Array::from(Vec::<Type>::new())),
+            Type::Array(Array::from(Vec::<Type>::new())),
+            Type::Array(Array::from(Vec::<Type>::new())),
+        ]))
+    }
+
+    /// Returns the documentation of the command, as listed by `COMMAND DOCS`.
+    pub fn docs(&self) -> Type {
+        let field = |name: &str, value: &str| {
+            (
+                Type::BulkString(BulkString::from(name)),
+                Type::BulkString(BulkString::from(value)),
+            )
+        };
+        Type::Map(vec![
+            field("summary", self.summary),
+            field("since", self.since),
+            field("group", self.group),
+        ])
+    }
+}
+
+/// Every command the server knows, sorted by name.
+pub const COMMANDS: &[CommandDescriptor] = &[
+    CommandDescriptor {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: &[Flag::Admin, Flag::Noscript],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "Asynchronously rewrites the append-only file to disk.",
+        since: "1.0.0",
+        group: "server",
+        parse: |_| Ok(RedisCommands::Bgrewriteaof),
+    },
+    CommandDescriptor {
+        name: "bgsave",
+        arity: -1,
+        flags: &[Flag::Admin, Flag::Noscript],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "Asynchronously saves the database(s) to disk.",
+        since: "1.0.0",
+        group: "server",
+        parse: RedisCommands::parse_bgsave,
+    },
+    CommandDescriptor {
+        name: "command",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["slow", "connection"],
+        summary: "Returns detailed information about all commands.",
+        since: "2.8.13",
+        group: "server",
+        parse: RedisCommands::parse_command,
+    },
+    CommandDescriptor {
+        name: "config",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["slow"],
+        summary: "A container for server configuration commands.",
+        since: "2.0.0",
+        group: "server",
+        parse: RedisCommands::parse_config,
+    },
+    CommandDescriptor {
+        name: "del",
+        arity: -2,
+        flags: &[Flag::Write],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "slow"],
+        summary: "Deletes one or more keys.",
+        since: "1.0.0",
+        group: "generic",
+        parse: |keys| Ok(RedisCommands::Del(keys.iter().map(|key| key.data().clone()).collect())),
+    },
+    CommandDescriptor {
+        name: "echo",
+        arity: -2,
+        flags: &[Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["fast", "connection"],
+        summary: "Returns the given string.",
+        since: "1.0.0",
+        group: "connection",
+        parse: |messages| Ok(RedisCommands::Echo(messages.to_vec())),
+    },
+    CommandDescriptor {
+        name: "expire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        summary: "Sets the expiration time of a key in seconds.",
+        since: "1.0.0",
+        group: "generic",
+        parse: |arguments| RedisCommands::parse_expire("expire", arguments),
+    },
+    CommandDescriptor {
+        name: "expireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+        since: "1.2.0",
+        group: "generic",
+        parse: |arguments| RedisCommands::parse_expire("expireat", arguments),
+    },
+    CommandDescriptor {
+        name: "expiretime",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+        since: "7.0.0",
+        group: "generic",
+        parse: |arguments| Ok(RedisCommands::ExpireTime(key(arguments, "expiretime")?, TimeUnit::Seconds)),
+    },
+    CommandDescriptor {
+        name: "get",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["read", "string", "fast"],
+        summary: "Returns the string value of a key.",
+        since: "1.0.0",
+        group: "string",
+        parse: |arguments| Ok(RedisCommands::Get(key(arguments, "get")?)),
+    },
+    CommandDescriptor {
+        name: "hello",
+        arity: -1,
+        flags: &[
+            Flag::Noscript,
+            Flag::Loading,
+            Flag::Stale,
+            Flag::Fast,
+            Flag::NoAuth,
+        ],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["fast", "connection"],
+        summary: "Handshakes with the Redis server.",
+        since: "6.0.0",
+        group: "connection",
+        parse: RedisCommands::parse_hello,
+    },
+    CommandDescriptor {
+        name: "info",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["slow", "dangerous"],
+        summary: "Returns information and statistics about the server.",
+        since: "1.0.0",
+        group: "server",
+        parse: |arguments| {
+            let section = arguments.first().map_or_else(|| "default".to_string(), ToString::to_string);
+            Ok(RedisCommands::Info(section))
+        },
+    },
+    CommandDescriptor {
+        name: "lastsave",
+        arity: 1,
+        flags: &[Flag::Loading, Flag::Stale, Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "fast", "dangerous"],
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+        since: "1.0.0",
+        group: "server",
+        parse: |_| Ok(RedisCommands::Lastsave),
+    },
+    CommandDescriptor {
+        name: "persist",
+        arity: 2,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        summary: "Removes the expiration time of a key.",
+        since: "2.2.0",
+        group: "generic",
+        parse: |arguments| Ok(RedisCommands::Persist(key(arguments, "persist")?)),
+    },
+    CommandDescriptor {
+        name: "pexpire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        summary: "Sets the expiration time of a key in milliseconds.",
+        since: "2.6.0",
+        group: "generic",
+        parse: |arguments| RedisCommands::parse_expire("pexpire", arguments),
+    },
+    CommandDescriptor {
+        name: "pexpireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "write", "fast"],
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        since: "2.6.0",
+        group: "generic",
+        parse: |arguments| RedisCommands::parse_expire("pexpireat", arguments),
+    },
+    CommandDescriptor {
+        name: "pexpiretime",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+        since: "7.0.0",
+        group: "generic",
+        parse: |arguments| Ok(RedisCommands::ExpireTime(key(arguments, "pexpiretime")?, TimeUnit::Milliseconds)),
+    },
+    CommandDescriptor {
+        name: "ping",
+        arity: -1,
+        flags: &[Flag::Fast],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["fast", "connection"],
+        summary: "Returns the server's liveliness response.",
+        since: "1.0.0",
+        group: "connection",
+        parse: |_| Ok(RedisCommands::Ping),
+    },
+    CommandDescriptor {
+        name: "psync",
+        arity: -3,
+        flags: &[Flag::Admin, Flag::Noscript],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "An internal command used in replication.",
+        since: "2.8.0",
+        group: "server",
+        parse: RedisCommands::parse_psync,
+    },
+    CommandDescriptor {
+        name: "pttl",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        summary: "Returns the expiration time in milliseconds of a key.",
+        since: "2.6.0",
+        group: "generic",
+        parse: |arguments| Ok(RedisCommands::Ttl(key(arguments, "pttl")?, TimeUnit::Milliseconds)),
+    },
+    CommandDescriptor {
+        name: "replconf",
+        arity: -1,
+        flags: &[Flag::Admin, Flag::Noscript, Flag::Loading, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "An internal command for configuring the replication stream.",
+        since: "3.0.0",
+        group: "server",
+        parse: RedisCommands::parse_replconf,
+    },
+    CommandDescriptor {
+        name: "replicaof",
+        arity: 3,
+        flags: &[Flag::Admin, Flag::Noscript, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "Configures a server as replica of another, or promotes it to a master.",
+        since: "5.0.0",
+        group: "server",
+        parse: RedisCommands::parse_replicaof,
+    },
+    CommandDescriptor {
+        name: "save",
+        arity: 1,
+        flags: &[Flag::Admin, Flag::Noscript],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "Synchronously saves the database(s) to disk.",
+        since: "1.0.0",
+        group: "server",
+        parse: |_| Ok(RedisCommands::Save),
+    },
+    CommandDescriptor {
+        name: "set",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Denyoom],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["write", "string", "slow"],
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+        since: "1.0.0",
+        group: "string",
+        parse: RedisCommands::parse_set,
+    },
+    CommandDescriptor {
+        name: "slaveof",
+        arity: 3,
+        flags: &[Flag::Admin, Flag::Noscript, Flag::Stale],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["admin", "slow", "dangerous"],
+        summary: "Sets a Redis server as a replica of another, or promotes it to being a master.",
+        since: "1.0.0",
+        group: "server",
+        parse: RedisCommands::parse_replicaof,
+    },
+    CommandDescriptor {
+        name: "ttl",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        acl_categories: &["keyspace", "read", "fast"],
+        summary: "Returns the expiration time in seconds of a key.",
+        since: "1.0.0",
+        group: "generic",
+        parse: |arguments| Ok(RedisCommands::Ttl(key(arguments, "ttl")?, TimeUnit::Seconds)),
+    },
+    CommandDescriptor {
+        name: "wait",
+        arity: 3,
+        flags: &[Flag::Noscript],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        acl_categories: &["slow", "connection"],
+        summary: "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed.",
+        since: "3.0.0",
+        group: "generic",
+        parse: RedisCommands::parse_wait,
+    },
+];
+
+/// Returns the descriptor of a command from its case insensitive name.
+pub fn lookup(name: &str) -> Option<&'static CommandDescriptor> {
+    COMMANDS
+        .iter()
+        .find(|descriptor| descriptor.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        assert_eq!(lookup("GeT").unwrap().name, "get");
+        assert!(lookup("unknown").is_none());
+    }
+
+    #[test]
+    fn test_commands_are_sorted_by_name() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].name < pair[1].name));
+    }
+
+    #[test]
+    fn test_accepts_arity() {
+        let get = lookup("get").unwrap();
+        assert!(!get.accepts(1));
+        assert!(get.accepts(2));
+        assert!(!get.accepts(3));
+        let set = lookup("set").unwrap();
+        assert!(!set.accepts(2));
+        assert!(set.accepts(3));
+        assert!(set.accepts(5));
+    }
+
+    #[test]
+    fn test_parsers_build_the_command_of_their_entry() {
+        let example = |name: &str| match name {
+            "bgrewriteaof" | "bgsave" | "command" | "hello" | "info" | "lastsave" | "ping"
+            | "save" => "",
+            "config" => "GET maxmemory",
+            "del" | "echo" | "expiretime" | "get" | "persist" | "pexpiretime" | "pttl" | "ttl" => {
+                "key"
+            }
+            "expire" | "expireat" | "pexpire" | "pexpireat" => "key 10",
+            "psync" => "? -1",
+            "replconf" => "listening-port 6380",
+            "replicaof" | "slaveof" => "no one",
+            "set" => "key value",
+            "wait" => "1 0",
+            name => panic!("No example of {name}"),
+        };
+        for descriptor in COMMANDS {
+            let arguments = Array::from_string(example(descriptor.name))
+                .to_bulkstrings()
+                .unwrap();
+            assert!(
+                descriptor.accepts(arguments.len() + 1),
+                "{}",
+                descriptor.name
+            );
+            let command = (descriptor.parse)(&arguments).unwrap();
+            assert_eq!(
+                command.descriptor().unwrap().flags,
+                descriptor.flags,
+                "{}",
+                descriptor.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_flag() {
+        assert!(lookup("set").unwrap().is_write());
+        assert!(!lookup("get").unwrap().is_write());
+    }
+}
@@ -0,0 +1,119 @@
+//! Glob-style pattern matching, as Redis does for `CONFIG GET` and the commands listing
+//! names.
+
+/// Returns whether `string` matches `pattern`, in which `*` matches any sequence of
+/// characters, `?` any single character, `[abc]` one of a set, `[^abc]` any character out
+/// of it and `[a-z]` a range, while `\` escapes the character that follows.
+pub fn matches(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let fold = |byte: u8| {
+        if nocase {
+            byte.to_ascii_lowercase()
+        } else {
+            byte
+        }
+    };
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last `*` when the rest of the pattern does not match: the
+    // position in the pattern after the star, and the one in the string it skipped to.
+    let mut backtrack = None;
+    while let Some(&byte) = string.get(s) {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, s));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => {
+                let (matched, next) = class(pattern, p + 1, fold(byte), &fold);
+                matched.then_some(next)
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                (fold(pattern[p + 1]) == fold(byte)).then_some(p + 2)
+            }
+            Some(&literal) => (fold(literal) == fold(byte)).then_some(p + 1),
+            None => None,
+        };
+        if let Some(next) = next {
+            p = next;
+            s += 1;
+        } else if let Some((star, skipped)) = backtrack {
+            p = star;
+            s = skipped + 1;
+            backtrack = Some((star, s));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Matches `byte` against the set starting at `start`, right after its `[`, and returns
+/// whether it matched along with the position after the closing `]`.
+fn class(pattern: &[u8], start: usize, byte: u8, fold: &impl Fn(u8) -> u8) -> (bool, usize) {
+    let mut i = start;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while let Some(&current) = pattern.get(i) {
+        match (current, pattern.get(i + 1), pattern.get(i + 2)) {
+            (b']', ..) => return (matched != negate, i + 1),
+            (b'\\', Some(&escaped), _) => {
+                matched |= fold(escaped) == byte;
+                i += 2;
+            }
+            (low, Some(b'-'), Some(&high)) if high != b']' => {
+                let (low, high) = (fold(low.min(high)), fold(low.max(high)));
+                matched |= (low..=high).contains(&byte);
+                i += 3;
+            }
+            (current, ..) => {
+                matched |= fold(current) == byte;
+                i += 1;
+            }
+        }
+    }
+    (matched != negate, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        for (pattern, string) in [
+            ("*", "maxmemory"),
+            ("max*", "maxmemory"),
+            ("*memory", "maxmemory"),
+            ("*e*o*", "appendonly"),
+            ("a?pendonly", "appendonly"),
+            ("append[eo]nly", "appendonly"),
+            ("append[^e]nly", "appendonly"),
+            ("repl-[a-c]acklog-size", "repl-backlog-size"),
+            ("save\\*", "save*"),
+            ("MAXMEMORY", "maxmemory"),
+        ] {
+            assert!(
+                matches(pattern.as_bytes(), string.as_bytes(), true),
+                "{pattern} {string}"
+            );
+        }
+        for (pattern, string) in [
+            ("max", "maxmemory"),
+            ("*x", "maxmemory"),
+            ("append[^o]nly", "appendonly"),
+            ("save\\*", "saves"),
+            ("?", ""),
+        ] {
+            assert!(
+                !matches(pattern.as_bytes(), string.as_bytes(), true),
+                "{pattern} {string}"
+            );
+        }
+        assert!(!matches(b"MAXMEMORY", b"maxmemory", false));
+        assert!(matches(b"", b"", false));
+    }
+}
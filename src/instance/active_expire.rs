@@ -0,0 +1,127 @@
+//! Background deletion of expired keys.
+//!
+//! Like Redis, the cycle runs ten times per second. Each run samples keys with an
+//! expiration 20 at a time and deletes the expired ones. It keeps sampling while more than
+//! 10% of the sampled keys were expired, until it has spent a quarter of its period. The
+//! deletions are propagated to the append-only file and the replicas, while a replica's
+//! cycle deletes nothing.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{redis_info::RedisInfo, RedisStore};
+
+const CYCLE_PERIOD: Duration = Duration::from_millis(100);
+/// Time a cycle may spend deleting keys, 25% of its period.
+const CYCLE_BUDGET: Duration = Duration::from_millis(25);
+const KEYS_PER_SAMPLE: usize = 20;
+/// Percentage of expired keys in a sample below which the cycle stops.
+const ACCEPTABLE_STALE_PERCENT: usize = 10;
+
+/// Runs the active expiration cycle on `store` forever.
+pub async fn active_expire(store: RedisStore, redis_info: Arc<Mutex<RedisInfo>>) {
+    let mut interval = tokio::time::interval(CYCLE_PERIOD);
+    loop {
+        interval.tick().await;
+        if !expire_cycle(&store, &redis_info) {
+            eprintln!("Active expiration stopped: poisoned store");
+            return;
+        }
+    }
+}
+
+/// Runs a single cycle, returns `false` if a lock is poisoned.
+///
+/// The lock is released between samples so that clients are not blocked for the whole
+/// cycle.
+fn expire_cycle(store: &RedisStore, redis_info: &Arc<Mutex<RedisInfo>>) -> bool {
+    let start = Instant::now();
+    let (mut sampled, mut expired) = (0, 0);
+    loop {
+        let Ok(mut keyspace) = store.lock() else {
+            return false;
+        };
+        let (sample, deleted) = keyspace.expire_sample(KEYS_PER_SAMPLE);
+        if deleted > 0 {
+            let Ok(mut info) = redis_info.lock() else {
+                return false;
+            };
+            info.propagate_expired(&keyspace.take_expired());
+        }
+        sampled += sample;
+        expired += deleted;
+        if sample == 0
+            || deleted * 100 <= sample * ACCEPTABLE_STALE_PERCENT
+            || start.elapsed() > CYCLE_BUDGET
+        {
+            keyspace.record_expire_cycle(sampled, expired);
+            return true;
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::significant_drop_tightening)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{Config, Keyspace, MasterConfig, RedisValue};
+
+    #[tokio::test]
+    async fn test_expire_cycle_deletes_expired_keys() {
+        let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+        let config = Config::Master(MasterConfig::from_args(&["--save", ""]).unwrap());
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&config)));
+        let mut replication = redis_info.lock().unwrap().attach_replica(1, String::new());
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        {
+            let mut keyspace = store.lock().unwrap();
+            for i in 0..1000 {
+                let value = RedisValue::expiring_at(Bytes::from("v"), Some(past));
+                keyspace.insert(Bytes::from(format!("key:{i}")), value);
+            }
+            keyspace.insert(Bytes::from("kept"), RedisValue::new(Bytes::from("v"), None));
+        }
+        assert!(expire_cycle(&store, &redis_info));
+        let expired_keys = {
+            let keyspace = store.lock().unwrap();
+            assert!(keyspace.len() < 100);
+            assert!(keyspace.expired_keys() > 900);
+            assert!(keyspace.expired_stale_perc() > 0.0);
+            keyspace.expired_keys()
+        };
+        for _ in 0..expired_keys {
+            let command = replication.recv().await.unwrap();
+            assert!(command.starts_with(b"*2\r\n$3\r\nDEL\r\n"));
+        }
+        redis_info
+            .lock()
+            .unwrap()
+            .replicas_mut()
+            .propagate(&Bytes::from("end"));
+        assert_eq!(replication.recv().await.unwrap(), Bytes::from("end"));
+    }
+
+    #[test]
+    fn test_expire_cycle_keeps_keys_of_replica() {
+        let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+        let config = Config::Master(MasterConfig::from_args(&["--save", ""]).unwrap());
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&config)));
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        {
+            let mut keyspace = store.lock().unwrap();
+            keyspace.set_replica(true);
+            let value = RedisValue::expiring_at(Bytes::from("v"), Some(past));
+            keyspace.insert(Bytes::from("stale"), value);
+        }
+        assert!(expire_cycle(&store, &redis_info));
+        assert_eq!(store.lock().unwrap().len(), 1);
+    }
+}
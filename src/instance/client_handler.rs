@@ -1,156 +1,1006 @@
+use bytes::Bytes;
 use std::{
-    cmp::Ordering,
     fmt::Display,
-    io::{Read, Write},
-    net::TcpStream,
+    future::Future,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use tokio::net::TcpStream;
 
 use crate::{
-    redis_commands::RedisCommands,
-    redis_info::RedisInfo,
-    resp::{redis_response::RedisResponse, BulkString, ToRedisBytes, Type},
-    RedisStore, RedisValue,
+    command_table::{self, CommandDescriptor, COMMANDS},
+    redis_commands::{
+        CommandError, CommandQuery, ConfigCommand, ExpireCondition, ExpireTime, RedisCommands,
+        SetCondition, SetExpiration, SetOptions, TimeUnit,
+    },
+    redis_info::{RedisInfo, Role, Stats, REDIS_VERSION},
+    replication,
+    resp::{
+        redis_response::RedisResponse, Array, BulkString, Protocol, SimpleError, SimpleString,
+        ToRedisBytes, Type,
+    },
+    server_config::registry,
+    store::{instant_from_unix_millis, unix_time_millis},
+    Keyspace, RedisStore, RedisValue,
+};
+
+use super::{
+    connection::{Connection, ConnectionError},
+    persistence::{self, SaveError},
 };
 
 pub trait CommonCommands {
-    fn ping(stream: &mut TcpStream) {
-        Self::respond(&RedisResponse::Pong, stream);
+    fn ping(connection: &mut Connection) {
+        Self::respond(&RedisResponse::Pong, connection);
     }
 
-    fn echo(message: &[BulkString], stream: &mut TcpStream) {
-        let message = message.iter().map(BulkString::data).collect::<String>();
-        let message = BulkString::from(message.as_str());
-        Self::respond(&message, stream);
+    fn echo(message: &[BulkString], connection: &mut Connection) {
+        let message = message
+            .iter()
+            .flat_map(|bulkstring| bulkstring.data().iter().copied())
+            .collect::<Vec<u8>>();
+        let message = BulkString::from(message.as_slice());
+        Self::respond(&message, connection);
     }
     fn set(
         store: &RedisStore,
-        key: &str,
-        value: String,
-        expiration: Option<Duration>,
-        stream: &mut TcpStream,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        value: Bytes,
+        options: &SetOptions,
+        connection: &mut Connection,
     ) {
-        let value = RedisValue::new(value, expiration);
-        println!("Inserting key:{key} with value:{value}");
-
-        match store.lock() {
-            Ok(mut store) => store.insert(key.to_string(), value),
+        let mut store = match store.lock() {
+            Ok(store) => store,
             Err(e) => {
                 eprintln!("Error locking store: {e}");
-                Self::respond(&ClientHandlerError::PoisonedStore, stream);
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
                 return;
             }
         };
-        Self::respond(&RedisResponse::Ok, stream);
+        let previous = store.get(key).cloned();
+        Self::propagate_expired(redis_info, &mut store);
+        let old_value = || {
+            previous.as_ref().map_or(Type::Null, |previous| {
+                Type::BulkString(BulkString::from(previous.value().clone()))
+            })
+        };
+        let allowed = match options.condition {
+            None => true,
+            Some(SetCondition::NotExists) => previous.is_none(),
+            Some(SetCondition::Exists) => previous.is_some(),
+        };
+        if !allowed {
+            let reply = if options.get { old_value() } else { Type::Null };
+            drop(store);
+            println!(
+                "Set -- Key:{} has not been set",
+                String::from_utf8_lossy(key)
+            );
+            Self::reply(&reply, connection);
+            return;
+        }
+        let reply = if options.get {
+            old_value()
+        } else {
+            Type::SimpleString(SimpleString::from("OK"))
+        };
+        let expiration = match options.expiration {
+            None => None,
+            Some(SetExpiration::In(duration)) => Some(Instant::now() + duration),
+            Some(SetExpiration::At(time)) => match time.duration_since(SystemTime::now()) {
+                Ok(remaining) if !remaining.is_zero() => Some(Instant::now() + remaining),
+                // A time in the past deletes the key, as the value would expire at once.
+                _ => {
+                    store.remove(key);
+                    Self::propagate(redis_info, &replication::encode_command(&[b"DEL", key]));
+                    drop(store);
+                    Self::reply(&reply, connection);
+                    return;
+                }
+            },
+            Some(SetExpiration::KeepTtl) => previous.as_ref().and_then(RedisValue::expiration),
+        };
+        let value = RedisValue::expiring_at(value, expiration);
+        println!(
+            "Inserting key:{} with value:{value}",
+            String::from_utf8_lossy(key)
+        );
+        let command = replication::encode_set(key, value.value(), value.expiration_unix_millis());
+        store.insert(key.clone(), value);
+        Self::propagate(redis_info, &command);
+        drop(store);
+        Self::reply(&reply, connection);
     }
 
-    fn get(store: &RedisStore, key: &str, stream: &mut TcpStream) {
+    fn get(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        connection: &mut Connection,
+    ) {
         let redis_value = match store.lock() {
-            Ok(store) => store.get(key).cloned(),
+            Ok(mut store) => {
+                let value = store.get(key).cloned();
+                Self::propagate_expired(redis_info, &mut store);
+                value
+            }
             Err(e) => {
                 eprintln!("Error locking store: {e}");
-                Self::respond(&ClientHandlerError::PoisonedStore, stream);
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
                 return;
             }
         };
+        let key = String::from_utf8_lossy(key);
         let Some(redis_value) = redis_value else {
             println!("Get -- Key:{key} has not been found");
-            Self::respond(&RedisResponse::Null, stream);
+            Self::reply(&Type::Null, connection);
             return;
         };
-        let Some(expiration) = redis_value.expiration() else {
-            println!("Get -- Key:{key} has been found and have no expiration");
-            Self::respond(&redis_value.value(), stream);
+        println!("Get -- Key:{key} has been found");
+        Self::respond(&BulkString::from(redis_value.value().clone()), connection);
+    }
+
+    fn expire(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        time: ExpireTime,
+        conditions: &[ExpireCondition],
+        connection: &mut Connection,
+    ) {
+        let mut store = match store.lock() {
+            Ok(store) => store,
+            Err(e) => {
+                eprintln!("Error locking store: {e}");
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                return;
+            }
+        };
+        let current = store.get(key).map(RedisValue::expiration);
+        Self::propagate_expired(redis_info, &mut store);
+        let Some(current) = current else {
+            drop(store);
+            Self::reply(&Type::Integer(0), connection);
             return;
         };
+        let unix_deadline = match time {
+            ExpireTime::In(milliseconds) => unix_time_millis().saturating_add(milliseconds),
+            ExpireTime::At(milliseconds) => milliseconds,
+        };
+        // `None` when the deadline is already past and the key must be deleted.
+        let deadline = instant_from_unix_millis(unix_deadline);
+        let allowed = conditions.iter().all(|condition| match condition {
+            ExpireCondition::Nx => current.is_none(),
+            ExpireCondition::Xx => current.is_some(),
+            ExpireCondition::Gt => {
+                current.is_some_and(|current| deadline.is_some_and(|deadline| deadline > current))
+            }
+            ExpireCondition::Lt => {
+                current.is_none_or(|current| deadline.is_none_or(|deadline| deadline < current))
+            }
+        });
+        if allowed {
+            match deadline {
+                Some(deadline) => {
+                    store.set_expiration(key, Some(deadline));
+                }
+                None => {
+                    store.remove(key);
+                }
+            }
+            let unix_deadline = unix_deadline.to_string();
+            let command = [b"PEXPIREAT".as_slice(), key, unix_deadline.as_bytes()];
+            Self::propagate(redis_info, &replication::encode_command(&command));
+        }
+        drop(store);
+        Self::reply(&Type::Integer(allowed.into()), connection);
+    }
+
+    fn ttl(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        unit: TimeUnit,
+        connection: &mut Connection,
+    ) {
+        Self::reply_expiration(store, redis_info, key, connection, |value| {
+            value.time_to_live().map(|ttl| {
+                let milliseconds = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+                match unit {
+                    TimeUnit::Seconds => milliseconds.saturating_add(500) / 1000,
+                    TimeUnit::Milliseconds => milliseconds,
+                }
+            })
+        });
+    }
+
+    fn expire_time(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        unit: TimeUnit,
+        connection: &mut Connection,
+    ) {
+        Self::reply_expiration(store, redis_info, key, connection, |value| {
+            value.expiration_unix_millis().map(|expiration| match unit {
+                TimeUnit::Seconds => expiration.saturating_add(500) / 1000,
+                TimeUnit::Milliseconds => expiration,
+            })
+        });
+    }
+
+    /// Replies with the expiration reported by `report` for a key, -1 if it has none and -2
+    /// if the key does not exist.
+    fn reply_expiration(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        connection: &mut Connection,
+        report: impl Fn(&RedisValue) -> Option<i64>,
+    ) {
+        let reply = match store.lock() {
+            Ok(mut store) => {
+                let reply = store
+                    .get(key)
+                    .map_or(-2, |value| report(value).unwrap_or(-1));
+                Self::propagate_expired(redis_info, &mut store);
+                reply
+            }
+            Err(e) => {
+                eprintln!("Error locking store: {e}");
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                return;
+            }
+        };
+        Self::reply(&Type::Integer(reply), connection);
+    }
 
-        match Instant::now().cmp(&expiration) {
-            Ordering::Equal | Ordering::Less => {
-                println!("Get -- Key:{key} has been found and is not expired");
-                Self::respond(&redis_value.value(), stream);
+    fn persist(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        key: &Bytes,
+        connection: &mut Connection,
+    ) {
+        let persisted = match store.lock() {
+            Ok(mut store) => {
+                let expiring = store
+                    .get(key)
+                    .is_some_and(|value| value.expiration().is_some());
+                Self::propagate_expired(redis_info, &mut store);
+                let persisted = expiring && store.set_expiration(key, None);
+                if persisted {
+                    let command = replication::encode_command(&[b"PERSIST", key]);
+                    Self::propagate(redis_info, &command);
+                }
+                persisted
             }
-            Ordering::Greater => {
-                println!("Get -- Key:{key} has been found but is expired");
-                Self::respond(&RedisResponse::Null, stream);
+            Err(e) => {
+                eprintln!("Error locking store: {e}");
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                return;
+            }
+        };
+        Self::reply(&Type::Integer(persisted.into()), connection);
+    }
+
+    /// Deletes keys and replies how many existed and had not expired.
+    fn del(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        keys: &[Bytes],
+        connection: &mut Connection,
+    ) {
+        let Ok(mut store) = store.lock() else {
+            Self::respond(&ClientHandlerError::PoisonedStore, connection);
+            return;
+        };
+        let mut deleted: Vec<&[u8]> = vec![];
+        let mut count = 0;
+        for key in keys {
+            // An expired key is not counted. The lookup deletes it on a master, while a
+            // replica keeps it until a `DEL`, such as its master's, removes it.
+            let live = store.get(key).is_some();
+            if store.contains_key(key) {
+                store.remove(key);
+                deleted.push(key);
+                count += usize::from(live);
             }
         }
+        Self::propagate_expired(redis_info, &mut store);
+        if !deleted.is_empty() {
+            deleted.insert(0, b"DEL");
+            Self::propagate(redis_info, &replication::encode_command(&deleted));
+        }
+        drop(store);
+        Self::reply(
+            &Type::Integer(i64::try_from(count).unwrap_or(i64::MAX)),
+            connection,
+        );
     }
 
-    fn info(server_info: &Arc<Mutex<RedisInfo>>, section: &str, stream: &mut TcpStream) {
-        let info = match section.to_lowercase().as_str() {
-            "replication" => server_info
+    fn info(
+        server_info: &Arc<Mutex<RedisInfo>>,
+        store: &RedisStore,
+        section: &str,
+        connection: &mut Connection,
+    ) {
+        let section = section.to_lowercase();
+        let all = matches!(section.as_str(), "default" | "all" | "everything");
+        let mut sections = vec![];
+        if all || section == "persistence" {
+            let Ok(changes) = store.lock().map(|store| store.dirty()) else {
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                return;
+            };
+            let info = server_info
+                .lock()
+                .expect("Poisonned lock when getting server info");
+            let persistence = format!("{}{}", info.rdb().info(changes), info.aof_info());
+            drop(info);
+            sections.push(format!("# Persistence\r\n{persistence}"));
+        }
+        if all || section == "stats" {
+            let Ok(store) = store.lock() else {
+                Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                return;
+            };
+            sections.push(format!("# Stats\r\n{}", Stats::new(&store)));
+        }
+        if all || section == "replication" {
+            let server_info = server_info
                 .lock()
                 .expect("Poisonned lock when getting server info")
-                .to_bulk_string(),
-            _ => BulkString::from("Unknown section"),
+                .to_string();
+            sections.push(format!("# Replication\r\n{server_info}"));
+        }
+        let info = if sections.is_empty() {
+            BulkString::from("Unknown section")
+        } else {
+            BulkString::from(sections.join("\r\n").as_str())
         };
-        Self::respond(&info, stream);
+        Self::respond(&info, connection);
     }
 
-    fn respond(response: &impl ToRedisBytes, stream: &mut TcpStream) {
-        println!(
-            "Responding with: {:?}",
-            std::str::from_utf8(&response.to_redis_bytes())
-        );
-        let response = response.to_redis_bytes();
-        match stream.write_all(&response) {
-            Ok(()) => (),
-            Err(e) => eprintln!("Error writing to stream: {e}"),
+    fn save(store: &RedisStore, redis_info: &Arc<Mutex<RedisInfo>>, connection: &mut Connection) {
+        match persistence::save(store, redis_info) {
+            Ok(()) => Self::respond(&RedisResponse::Ok, connection),
+            Err(e) => {
+                eprintln!("Cannot save: {e}");
+                Self::respond(&e, connection);
+            }
+        }
+    }
+
+    /// Starts a background save, or with `SCHEDULE` postpones it until the running one
+    /// completes.
+    fn bgsave(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        schedule: bool,
+        connection: &mut Connection,
+    ) {
+        match persistence::background_save(store, redis_info) {
+            Ok(()) => Self::respond(&SimpleString::from("Background saving started"), connection),
+            Err(SaveError::InProgress) if schedule => {
+                redis_info
+                    .lock()
+                    .expect("Poisonned lock when getting server info")
+                    .rdb_mut()
+                    .schedule_bgsave(true);
+                Self::respond(
+                    &SimpleString::from("Background saving scheduled"),
+                    connection,
+                );
+            }
+            Err(e) => Self::respond(&e, connection),
+        }
+    }
+
+    fn bgrewriteaof(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        connection: &mut Connection,
+    ) {
+        match persistence::background_rewrite(store, redis_info) {
+            Ok(()) => Self::respond(
+                &SimpleString::from("Background append only file rewriting started"),
+                connection,
+            ),
+            Err(e) => Self::respond(&e, connection),
+        }
+    }
+
+    fn lastsave(redis_info: &Arc<Mutex<RedisInfo>>, connection: &mut Connection) {
+        let last_save = redis_info
+            .lock()
+            .expect("Poisonned lock when getting server info")
+            .rdb()
+            .last_save();
+        Self::reply(&Type::Integer(last_save), connection);
+    }
+
+    fn hello(
+        protover: Option<i64>,
+        auth: Option<&(Bytes, Bytes)>,
+        setname: Option<&Bytes>,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        connection: &mut Connection,
+    ) {
+        let protocol = match protover {
+            None => connection.session().protocol(),
+            Some(2) => Protocol::Resp2,
+            Some(3) => Protocol::Resp3,
+            Some(_) => {
+                Self::respond(&CommandError::UnsupportedProtocol, connection);
+                return;
+            }
+        };
+        if let Some((username, _)) = auth {
+            if username.as_ref() != b"default" {
+                Self::respond(&CommandError::WrongPass, connection);
+                return;
+            }
+        }
+        connection.session_mut().set_protocol(protocol);
+        if let Some(name) = setname {
+            connection.session_mut().set_name(name.clone());
+        }
+        let role = match redis_info
+            .lock()
+            .expect("Poisonned lock when getting server info")
+            .role()
+        {
+            Role::Master => "master",
+            Role::Replica => "replica",
+        };
+        let field = |name: &str, value: Type| (Type::BulkString(BulkString::from(name)), value);
+        let text = |value: &str| Type::BulkString(BulkString::from(value));
+        let hello = Type::Map(vec![
+            field("server", text("redis")),
+            field("version", text(REDIS_VERSION)),
+            field("proto", Type::Integer(protocol.version())),
+            field(
+                "id",
+                Type::Integer(i64::try_from(connection.session().id()).unwrap_or(i64::MAX)),
+            ),
+            field("mode", text("standalone")),
+            field("role", text(role)),
+            field("modules", Type::Array(Array::from(Vec::<Type>::new()))),
+        ]);
+        Self::reply(&hello, connection);
+    }
+
+    fn command(query: &CommandQuery, connection: &mut Connection) {
+        let reply = match query {
+            CommandQuery::All => Type::Array(Array::from(
+                COMMANDS
+                    .iter()
+                    .map(CommandDescriptor::info)
+                    .collect::<Vec<_>>(),
+            )),
+            CommandQuery::Count => Type::Integer(i64::try_from(COMMANDS.len()).unwrap_or(i64::MAX)),
+            CommandQuery::Info(names) if names.is_empty() => {
+                return Self::command(&CommandQuery::All, connection);
+            }
+            CommandQuery::Info(names) => Type::Array(Array::from(
+                names
+                    .iter()
+                    .map(|name| {
+                        command_table::lookup(name).map_or(Type::Null, CommandDescriptor::info)
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+            CommandQuery::Docs(names) => {
+                let descriptors = if names.is_empty() {
+                    COMMANDS.iter().collect()
+                } else {
+                    names
+                        .iter()
+                        .filter_map(|name| command_table::lookup(name))
+                        .collect::<Vec<_>>()
+                };
+                Type::Map(
+                    descriptors
+                        .into_iter()
+                        .map(|descriptor| {
+                            (
+                                Type::BulkString(BulkString::from(descriptor.name)),
+                                descriptor.docs(),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+        };
+        Self::reply(&reply, connection);
+    }
+
+    /// Gets, sets or rewrites the live configuration, or resets the statistics.
+    ///
+    /// `CONFIG SET` locks the store before `RedisInfo`, since turning `appendonly` on
+    /// writes the dataset to the new append-only files.
+    fn config(
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        command: &ConfigCommand,
+        connection: &mut Connection,
+    ) {
+        let info = || {
+            redis_info
+                .lock()
+                .expect("Poisonned lock when getting server info")
+        };
+        let result = match command {
+            ConfigCommand::Get(patterns) => {
+                let values = registry::get(info().config(), patterns);
+                let text = |value: &str| Type::BulkString(BulkString::from(value));
+                let reply = Type::Map(
+                    values
+                        .iter()
+                        .map(|(name, value)| (text(name), text(value)))
+                        .collect(),
+                );
+                Self::reply(&reply, connection);
+                return;
+            }
+            ConfigCommand::Set(changes) => {
+                let Ok(store) = store.lock() else {
+                    Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                    return;
+                };
+                let result = info().set_config(changes, &store);
+                drop(store);
+                result.map_err(|e| e.to_string())
+            }
+            ConfigCommand::Rewrite => {
+                let (config, master) = {
+                    let info = info();
+                    (info.config().clone(), info.master())
+                };
+                registry::rewrite(&config, master.as_ref()).map_err(|e| e.to_string())
+            }
+            ConfigCommand::Resetstat => {
+                let Ok(mut store) = store.lock() else {
+                    Self::respond(&ClientHandlerError::PoisonedStore, connection);
+                    return;
+                };
+                store.reset_stats();
+                info().reset_stats();
+                drop(store);
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => Self::respond(&RedisResponse::Ok, connection),
+            Err(e) => Self::respond(&ClientHandlerError::Config(e), connection),
         }
     }
 
-    fn parse_redis_command_from_stream(buf: &[u8; 512], n: usize) -> Option<RedisCommands> {
-        let command = Type::from_bytes(&buf[..n]);
-        let redis_command = RedisCommands::parse(&command);
-        Some(redis_command)
+    /// Forwards a write to the append-only file and the replicas, called with the store
+    /// still locked.
+    fn propagate(redis_info: &Arc<Mutex<RedisInfo>>, command: &Bytes) {
+        redis_info
+            .lock()
+            .expect("Poisonned lock when getting server info")
+            .propagate(command);
+    }
+
+    /// Propagates the deletion of the keys a command found expired, called with the store
+    /// still locked and before anything else the command propagates.
+    fn propagate_expired(redis_info: &Arc<Mutex<RedisInfo>>, keyspace: &mut Keyspace) {
+        let expired = keyspace.take_expired();
+        if !expired.is_empty() {
+            redis_info
+                .lock()
+                .expect("Poisonned lock when getting server info")
+                .propagate_expired(&expired);
+        }
+    }
+
+    fn reply(frame: &Type, connection: &mut Connection) {
+        connection.reply(frame);
+    }
+
+    fn respond(response: &impl ToRedisBytes, connection: &mut Connection) {
+        connection.respond(response);
+    }
+
+    fn parse_redis_command(frame: &Type) -> Result<RedisCommands, CommandError> {
+        RedisCommands::parse(frame)
+    }
+
+    /// Executes a command shared by every kind of instance, and hands the others over to
+    /// `match_redis_command`.
+    fn dispatch(
+        redis_command: RedisCommands,
+        connection: &mut Connection,
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+    ) {
+        match redis_command {
+            RedisCommands::Ping => Self::ping(connection),
+            RedisCommands::Echo(message) => Self::echo(&message, connection),
+            RedisCommands::Get(key) => Self::get(store, redis_info, &key, connection),
+            RedisCommands::Set(key, value, options) => {
+                Self::set(store, redis_info, &key, value, &options, connection);
+            }
+            RedisCommands::Info(section) => Self::info(redis_info, store, &section, connection),
+            RedisCommands::Hello(protover, auth, setname) => Self::hello(
+                protover,
+                auth.as_ref(),
+                setname.as_ref(),
+                redis_info,
+                connection,
+            ),
+            RedisCommands::Command(query) => Self::command(&query, connection),
+            RedisCommands::Expire(key, time, conditions) => {
+                Self::expire(store, redis_info, &key, time, &conditions, connection);
+            }
+            RedisCommands::Ttl(key, unit) => Self::ttl(store, redis_info, &key, unit, connection),
+            RedisCommands::ExpireTime(key, unit) => {
+                Self::expire_time(store, redis_info, &key, unit, connection);
+            }
+            RedisCommands::Persist(key) => Self::persist(store, redis_info, &key, connection),
+            RedisCommands::Del(keys) => Self::del(store, redis_info, &keys, connection),
+            RedisCommands::Save => Self::save(store, redis_info, connection),
+            RedisCommands::Bgsave(schedule) => {
+                Self::bgsave(store, redis_info, schedule, connection);
+            }
+            RedisCommands::Bgrewriteaof => Self::bgrewriteaof(store, redis_info, connection),
+            RedisCommands::Lastsave => Self::lastsave(redis_info, connection),
+            RedisCommands::Config(command) => {
+                Self::config(store, redis_info, &command, connection);
+            }
+            // `WAIT` blocks and is served by `ClientHandler`, it only gets here from the link
+            // to a master.
+            RedisCommands::Wait(..) => {
+                Self::respond(
+                    &ClientHandlerError::NotAllowedHere(redis_command.to_string()),
+                    connection,
+                );
+            }
+            redis_command => {
+                Self::match_redis_command(redis_command, connection, store, redis_info);
+            }
+        }
     }
 
+    /// Executes the commands whose behaviour depends on the role of the instance.
     fn match_redis_command(
         redis_command: RedisCommands,
-        stream: &mut TcpStream,
-        store: &Arc<Mutex<std::collections::HashMap<String, RedisValue>>>,
+        connection: &mut Connection,
+        store: &RedisStore,
         redis_info: &Arc<Mutex<RedisInfo>>,
     );
 }
 
 pub trait ClientHandler: CommonCommands {
-    fn handle(redis_info: Arc<Mutex<RedisInfo>>, store: RedisStore, stream: &mut TcpStream) {
-        let mut buf = [0; 512];
-        while let Ok(n) = stream.read(&mut buf) {
-            if n == 0 {
-                break;
+    /// Serves a client until it closes the connection.
+    fn handle(
+        redis_info: Arc<Mutex<RedisInfo>>,
+        store: RedisStore,
+        stream: TcpStream,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            let mut connection = Connection::new(stream);
+            loop {
+                let frame = match connection.read_frame().await {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    // The rest of a malformed request cannot be told from the next one,
+                    // so the connection is closed after the reply, like Redis does.
+                    Err(ConnectionError::Protocol(error)) => {
+                        eprintln!("Cannot decode request: {error}");
+                        Self::respond(&error, &mut connection);
+                        break;
+                    }
+                    Err(ConnectionError::Io(error)) => {
+                        eprintln!("Error reading from stream: {error}");
+                        break;
+                    }
+                };
+                println!("Received command: '{frame:?}'");
+                match Self::parse_redis_command(&frame) {
+                    Ok(RedisCommands::Wait(numreplicas, timeout)) => {
+                        Self::wait(&redis_info, numreplicas, timeout, &mut connection).await;
+                    }
+                    Ok(redis_command)
+                        if redis_command.is_write()
+                            && redis_info
+                                .lock()
+                                .expect("Poisonned lock when getting server info")
+                                .refuses_writes() =>
+                    {
+                        Self::respond(&ClientHandlerError::ReadOnlyReplica, &mut connection);
+                    }
+                    Ok(redis_command) => {
+                        Self::dispatch(redis_command, &mut connection, &store, &redis_info);
+                    }
+                    Err(error) => {
+                        eprintln!("Cannot parse command {frame:?}: {error}");
+                        Self::respond(&error, &mut connection);
+                    }
+                }
             }
-            println!(
-                "Received command: '{}'",
-                String::from_utf8_lossy(&buf[0..n])
-            );
-            let Some(redis_command) = Self::parse_redis_command_from_stream(&buf, n) else {
-                eprintln!(
-                    "Cannot parse command {}",
-                    String::from_utf8_lossy(&buf[0..n])
-                );
-                continue;
+            if let Err(error) = connection.flush().await {
+                eprintln!("Error writing to stream: {error}");
+            }
+        }
+    }
+
+    /// Blocks the client until `numreplicas` replicas acknowledged every write propagated
+    /// so far, or until `timeout`, then replies how many did.
+    ///
+    /// Replicas that are behind are asked for an acknowledgement with `REPLCONF GETACK`
+    /// rather than waiting for their periodic one.
+    fn wait(
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        numreplicas: i64,
+        timeout: Option<Duration>,
+        connection: &mut Connection,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            let numreplicas = usize::try_from(numreplicas).unwrap_or(0);
+            let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+            let (offset, acks) = {
+                let mut info = redis_info
+                    .lock()
+                    .expect("Poisonned lock when getting server info");
+                if *info.role() == Role::Replica {
+                    drop(info);
+                    Self::respond(&ClientHandlerError::WaitOnReplica, connection);
+                    return;
+                }
+                let offset = info.master_repl_offset().value();
+                if info.replicas().acknowledged(offset) < numreplicas {
+                    info.feed_replicas(&replication::encode_command(&[
+                        b"REPLCONF",
+                        b"GETACK",
+                        b"*",
+                    ]));
+                }
+                (offset, info.replicas().acks())
+            };
+            let acknowledged = loop {
+                let notified = acks.notified();
+                let acknowledged = redis_info
+                    .lock()
+                    .expect("Poisonned lock when getting server info")
+                    .replicas()
+                    .acknowledged(offset);
+                if acknowledged >= numreplicas {
+                    break acknowledged;
+                }
+                match deadline {
+                    Some(deadline) => tokio::select! {
+                        () = notified => {}
+                        () = tokio::time::sleep_until(deadline) => break acknowledged,
+                    },
+                    None => notified.await,
+                }
             };
-            Self::match_redis_command(redis_command, stream, &store, &redis_info);
+            Self::reply(
+                &Type::Integer(i64::try_from(acknowledged).unwrap_or(i64::MAX)),
+                connection,
+            );
         }
     }
 }
 
-enum ClientHandlerError {
+pub(super) enum ClientHandlerError {
     PoisonedStore,
+    WaitOnReplica,
+    /// A client sent a write to a read only replica.
+    ReadOnlyReplica,
+    /// `CONFIG` failed, for the given reason.
+    Config(String),
+    /// The command cannot be executed on this connection, such as `WAIT` sent by a master.
+    NotAllowedHere(String),
 }
 impl Display for ClientHandlerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::PoisonedStore => write!(f, "Poisoned store"),
+            Self::WaitOnReplica => write!(
+                f,
+                "WAIT cannot be used with replica instances. Please also note that since Redis \
+                 4.0 if a replica is configured to be writable (which is not the default) writes \
+                 to replicas are just local and are not propagated."
+            ),
+            Self::ReadOnlyReplica => write!(f, "You can't write against a read only replica."),
+            Self::Config(reason) => write!(f, "{reason}"),
+            Self::NotAllowedHere(command) => {
+                write!(
+                    f,
+                    "'{}' command cannot be used here",
+                    command.to_lowercase()
+                )
+            }
         }
     }
 }
 impl ToRedisBytes for ClientHandlerError {
     fn to_redis_bytes(&self) -> Vec<u8> {
-        format!("${}\r\n{}\r\n", self.to_string().len(), self)
-            .as_bytes()
-            .to_vec()
+        let code = match self {
+            Self::ReadOnlyReplica => "READONLY",
+            Self::PoisonedStore
+            | Self::WaitOnReplica
+            | Self::Config(_)
+            | Self::NotAllowedHere(_) => "ERR",
+        };
+        SimpleError::from(format!("{code} {self}").as_str()).to_redis_bytes()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{instance::redis_instance::RedisInstance, Config, MasterConfig};
+
+    /// Connects a client to a connection served by `RedisInstance`.
+    async fn connect(
+        store: &RedisStore,
+        redis_info: Arc<Mutex<RedisInfo>>,
+    ) -> (TcpStream, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let handler = tokio::spawn(RedisInstance::handle(redis_info, store.clone(), stream));
+        (client, handler)
+    }
+
+    fn master_info() -> Arc<Mutex<RedisInfo>> {
+        let config = MasterConfig::from_args(&["--save", ""]).unwrap();
+        Arc::new(Mutex::new(RedisInfo::new(&Config::Master(config))))
+    }
+
+    /// Sends `requests` as a single write and returns the replies once the client closed
+    /// its side of the connection.
+    async fn serve(
+        requests: &[u8],
+        store: &RedisStore,
+        redis_info: Arc<Mutex<RedisInfo>>,
+    ) -> Vec<u8> {
+        let (mut client, handler) = connect(store, redis_info).await;
+        client.write_all(requests).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        handler.await.unwrap();
+        replies
+    }
+
+    /// Sends a malformed `request` to a client connection served by `RedisInstance`,
+    /// followed by a command in a later read, and returns what the server replied before
+    /// closing the connection.
+    async fn serve_malformed(request: &[u8], store: &RedisStore) -> Vec<u8> {
+        let (mut client, handler) = connect(store, master_info()).await;
+        client.write_all(request).await.unwrap();
+        let mut reply = vec![0; 128];
+        let read = client.read(&mut reply).await.unwrap();
+        reply.truncate(read);
+        // The server may already be gone.
+        let _ = client.write_all(b"SET smuggled 1\r\n").await;
+        tokio::time::timeout(Duration::from_secs(1), handler)
+            .await
+            .expect("The connection stays open after a protocol error")
+            .unwrap();
+        reply
+    }
+
+    #[tokio::test]
+    async fn test_read_only_replica_refuses_client_writes() {
+        let replica = |args: &[&str]| {
+            let config = Config::from_args(args).unwrap();
+            Arc::new(Mutex::new(RedisInfo::new(&config)))
+        };
+        let requests = b"SET key value\r\nGET key\r\nDEL key\r\n";
+        let serve = |requests, redis_info| async move {
+            let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+            serve(requests, &store, redis_info).await
+        };
+        assert_eq!(
+            serve(requests, replica(&["--replicaof", "127.0.0.1 6380"])).await,
+            b"-READONLY You can't write against a read only replica.\r\n\
+              $-1\r\n\
+              -READONLY You can't write against a read only replica.\r\n"
+        );
+        assert_eq!(
+            serve(
+                requests,
+                replica(&["--replicaof", "127.0.0.1 6380", "--replica-read-only", "no"])
+            )
+            .await,
+            b"+OK\r\n$5\r\nvalue\r\n:1\r\n"
+        );
+        assert_eq!(
+            serve(requests, master_info()).await,
+            b"+OK\r\n$5\r\nvalue\r\n:1\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_at_past_time_deletes_key() {
+        let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+        let redis_info = master_info();
+        let mut replica = redis_info
+            .lock()
+            .unwrap()
+            .attach_replica(1, "127.0.0.1:6380".to_string());
+        let replies = serve(
+            b"SET key old\r\nSET key new PXAT 1 GET\r\nSET other value EXAT 1\r\n",
+            &store,
+            redis_info,
+        )
+        .await;
+        assert_eq!(replies, b"+OK\r\n$3\r\nold\r\n+OK\r\n");
+        assert!(store.lock().unwrap().is_empty());
+        for command in [
+            replication::encode_command(&[b"SET", b"key", b"old"]),
+            replication::encode_command(&[b"DEL", b"key"]),
+            replication::encode_command(&[b"DEL", b"other"]),
+        ] {
+            assert_eq!(replica.recv().await.unwrap(), command);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_del_does_not_count_expired_keys_of_replica() {
+        let mut keyspace = Keyspace::new();
+        keyspace.set_replica(true);
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        keyspace.insert(
+            Bytes::from("stale"),
+            RedisValue::expiring_at(Bytes::from("v"), Some(past)),
+        );
+        keyspace.insert(Bytes::from("live"), RedisValue::new(Bytes::from("v"), None));
+        let store: RedisStore = Arc::new(Mutex::new(keyspace));
+        let config =
+            Config::from_args(&["--replicaof", "127.0.0.1 6380", "--replica-read-only", "no"])
+                .unwrap();
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&config)));
+        let replies = serve(b"DEL stale\r\nDEL stale live\r\n", &store, redis_info).await;
+        assert_eq!(replies, b":0\r\n:1\r\n");
+        assert!(store.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_only_replica_error() {
+        assert_eq!(
+            ClientHandlerError::ReadOnlyReplica.to_redis_bytes(),
+            b"-READONLY You can't write against a read only replica.\r\n"
+        );
+        assert_eq!(
+            ClientHandlerError::WaitOnReplica.to_redis_bytes()[..5],
+            *b"-ERR "
+        );
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_closes_connection() {
+        for request in [
+            &b"*1\r\n$999999999999\r\n"[..],
+            b"*3\r\n$3\r\nSET\r\n$1\r\nkey\r\n",
+        ] {
+            let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+            let reply = serve_malformed(request, &store).await;
+            assert!(
+                reply.starts_with(b"-ERR Protocol error"),
+                "{}",
+                String::from_utf8_lossy(&reply)
+            );
+            assert!(store.lock().unwrap().is_empty());
+        }
     }
 }
@@ -0,0 +1,246 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    replication::ReplicationStream,
+    resp::{decoder::ProtocolError, Decoder, Protocol, ToRedisBytes, Type},
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State tied to a single client connection, such as the negotiated protocol version.
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+    protocol: Protocol,
+    name: Option<Bytes>,
+    /// Port a replica announced with `REPLCONF listening-port`.
+    listening_port: Option<u16>,
+}
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::default(),
+            name: None,
+            listening_port: None,
+        }
+    }
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+    pub const fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    pub const fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+    pub fn set_name(&mut self, name: Bytes) {
+        self.name = Some(name);
+    }
+    pub const fn listening_port(&self) -> Option<u16> {
+        self.listening_port
+    }
+    pub const fn set_listening_port(&mut self, port: u16) {
+        self.listening_port = Some(port);
+    }
+}
+
+/// Frames read from and replies written to a `TcpStream`.
+///
+/// Replies are buffered and only written when the connection runs out of frames to
+/// decode, so a batch of pipelined commands is answered with a single write.
+pub struct Connection {
+    stream: TcpStream,
+    decoder: Decoder,
+    session: Session,
+    output: Vec<u8>,
+    /// Commands propagated to the replica at the other end, once it sent `PSYNC`.
+    replication_stream: Option<ReplicationStream>,
+    /// Whether replies are dropped, as on the link a replica keeps with its master.
+    muted: bool,
+}
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            decoder: Decoder::new(),
+            session: Session::new(),
+            output: vec![],
+            replication_stream: None,
+            muted: false,
+        }
+    }
+
+    /// Turns this connection into a replication link: the commands received on `stream`
+    /// are forwarded to the peer as they come.
+    pub fn attach_replication_stream(&mut self, stream: ReplicationStream) {
+        self.replication_stream = Some(stream);
+    }
+
+    /// Drops every reply from now on, the master not expecting any from its replicas.
+    pub const fn mute(&mut self) {
+        self.muted = true;
+    }
+
+    /// Returns the IP address of the peer, empty if it cannot be known.
+    pub fn peer_ip(&self) -> String {
+        self.stream
+            .peer_addr()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_default()
+    }
+
+    pub const fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub const fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    /// Returns the next frame sent by the peer, or `None` once it closed the connection.
+    ///
+    /// Buffered replies are flushed before waiting on the socket. On a replication link,
+    /// propagated commands are written to the peer while waiting, and the connection ends
+    /// once this instance stops propagating to it. The future can be
+    /// cancelled without losing data, to wait on a timer alongside it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConnectionError` if the socket fails or the peer sent malformed bytes.
+    pub async fn read_frame(&mut self) -> Result<Option<Type>, ConnectionError> {
+        Ok(self.read_raw_frame().await?.map(|(frame, _)| frame))
+    }
+
+    /// Returns the next frame sent by the peer along with the bytes it was sent as, like
+    /// `read_frame`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConnectionError` if the socket fails or the peer sent malformed bytes.
+    pub async fn read_raw_frame(&mut self) -> Result<Option<(Type, Bytes)>, ConnectionError> {
+        let mut buf = [0; 4096];
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(Some(frame));
+            }
+            self.flush().await?;
+            let n = match &mut self.replication_stream {
+                Some(replication_stream) => tokio::select! {
+                    read = self.stream.read(&mut buf) => read?,
+                    command = replication_stream.recv() => {
+                        let Some(command) = command else {
+                            self.flush().await?;
+                            return Ok(None);
+                        };
+                        self.output.extend_from_slice(&command);
+                        continue;
+                    }
+                },
+                None => self.stream.read(&mut buf).await?,
+            };
+            if n == 0 {
+                return Ok(None);
+            }
+            self.decoder.extend(&buf[..n]);
+        }
+    }
+
+    /// Returns the RDB payload sent by a master after `FULLRESYNC`, which may span many
+    /// reads, or `None` if the master closed the connection first.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConnectionError` if the socket fails or the payload is not framed as
+    /// `$<length>\r\n`.
+    pub async fn read_rdb_payload(&mut self) -> Result<Option<Bytes>, ConnectionError> {
+        let mut buf = [0; 4096];
+        loop {
+            if let Some(payload) = self.decoder.next_rdb_payload()? {
+                return Ok(Some(payload));
+            }
+            self.flush().await?;
+            let n = self.stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.decoder.extend(&buf[..n]);
+        }
+    }
+
+    /// Buffers a reply encoded as RESP2.
+    pub fn respond(&mut self, response: &impl ToRedisBytes) {
+        self.write(&response.to_redis_bytes());
+    }
+
+    /// Buffers a reply encoded for the protocol negotiated on this connection.
+    pub fn reply(&mut self, frame: &Type) {
+        self.write(&frame.encode(self.session.protocol()));
+    }
+
+    /// Buffers a message that is sent even on a muted connection, such as the
+    /// acknowledgements a replica sends to its master.
+    pub fn send(&mut self, message: &impl ToRedisBytes) {
+        self.output.extend_from_slice(&message.to_redis_bytes());
+    }
+
+    fn write(&mut self, response: &[u8]) {
+        if self.muted {
+            return;
+        }
+        println!("Responding with: {:?}", String::from_utf8_lossy(response));
+        self.output.extend_from_slice(response);
+    }
+
+    /// Writes every buffered reply to the socket.
+    ///
+    /// Written bytes are removed from the buffer as they go, so a cancelled flush is resumed
+    /// by the next one without sending anything twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if the socket cannot be written to.
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        while !self.output.is_empty() {
+            let written = self.stream.write(&self.output).await?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.output.drain(..written);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectionError {
+    Io(std::io::Error),
+    Protocol(ProtocolError),
+}
+impl From<std::io::Error> for ConnectionError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+impl From<ProtocolError> for ConnectionError {
+    fn from(error: ProtocolError) -> Self {
+        Self::Protocol(error)
+    }
+}
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Protocol(error) => write!(f, "{error}"),
+        }
+    }
+}
@@ -0,0 +1,511 @@
+//! The link a replica keeps with its master.
+//!
+//! A background task follows the master named by `--replicaof` or `REPLICAOF`: it
+//! synchronises with it, applies the writes it propagates and reconnects whenever the link
+//! drops, until `REPLICAOF` names another master or `REPLICAOF NO ONE` stops it.
+
+use crate::{
+    atomic_file,
+    rdb::{self, RdbError, Snapshot},
+    redis_commands::RedisCommands,
+    redis_info::RedisInfo,
+    replication::{LinkState, ACK_PERIOD},
+    resp::{redis_response::RedisResponse, Array, SimpleString, ToRedisBytes, Type},
+    server_config::{Offset, ReplicationId},
+    Keyspace, RedisStore, ReplicaOf,
+};
+
+use super::{
+    client_handler::{ClientHandlerError, CommonCommands},
+    connection::{Connection, ConnectionError},
+    redis_instance::RedisInstance,
+};
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+
+/// Delay before the first attempt to synchronise again with the master.
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between two attempts to synchronise with the master.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The task following the master of this instance, if it has one.
+#[derive(Clone)]
+pub struct Follower {
+    store: RedisStore,
+    redis_info: Arc<Mutex<RedisInfo>>,
+    /// Port this instance listens on, announced to the master.
+    listening_port: u16,
+}
+
+impl Follower {
+    pub const fn new(
+        store: RedisStore,
+        redis_info: Arc<Mutex<RedisInfo>>,
+        listening_port: u16,
+    ) -> Self {
+        Self {
+            store,
+            redis_info,
+            listening_port,
+        }
+    }
+
+    /// Follows whichever master `REPLICAOF` last named, forever.
+    pub async fn run(self) {
+        let mut master = self
+            .redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .subscribe_master();
+        loop {
+            let current = master.borrow_and_update().clone();
+            if let Some(current) = current {
+                tokio::select! {
+                    () = self.follow(&current) => {}
+                    changed = master.changed() => if changed.is_err() {
+                        return;
+                    },
+                }
+            } else if master.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Follows `master` in the background, synchronising again whenever the link drops.
+    ///
+    /// Failed attempts are retried after a delay doubling up to `MAX_RETRY_DELAY`, while
+    /// clients keep reading the dataset as it was.
+    async fn follow(&self, master: &ReplicaOf) {
+        let mut delay = MIN_RETRY_DELAY;
+        loop {
+            match self.handshake(master).await {
+                Ok(connection) => {
+                    delay = MIN_RETRY_DELAY;
+                    self.set_link_state(LinkState::Connected);
+                    let error =
+                        Self::follow_master(connection, &self.store, &self.redis_info).await;
+                    eprintln!("Lost link with master: {error}");
+                }
+                Err(e) => eprintln!("Cannot synchronise with master: {e}"),
+            }
+            self.set_link_state(LinkState::Connect);
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    fn set_link_state(&self, state: LinkState) {
+        self.redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .set_link_state(state);
+    }
+
+    /// Returns how long to wait on the master before giving up on the link.
+    fn timeout(&self) -> Duration {
+        self.redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .replication_config()
+            .timeout()
+    }
+
+    /// Synchronises with the master and returns the link on which it propagates writes.
+    ///
+    /// A replica that already followed the master asks to continue from the offset it
+    /// reached, and only loads a new snapshot if the master cannot resume from there.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `MasterLinkError` if the master cannot be reached, answers unexpectedly or
+    /// stays silent for longer than `repl-timeout`.
+    async fn handshake(&self, master: &ReplicaOf) -> Result<Connection, MasterLinkError> {
+        let address = master.to_string();
+        println!("Connecting to master at {address}");
+        let stream = tokio::time::timeout(self.timeout(), TcpStream::connect(&address))
+            .await
+            .map_err(|_| MasterLinkError::Timeout)??;
+        let mut connection = Connection::new(stream);
+
+        self.set_link_state(LinkState::Handshake);
+        self.send_ping(&mut connection).await?;
+        self.send_replconf(&mut connection).await?;
+        let (replid, offset) = self
+            .redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .psync_position()
+            .unzip();
+        match self.send_psync(&mut connection, replid, offset).await? {
+            Resync::Full(replid, offset) => {
+                self.set_link_state(LinkState::Transfer);
+                self.receive_rdb(&mut connection).await?;
+                self.redis_info
+                    .lock()
+                    .expect("Poisonned redis_info")
+                    .set_master_replication(replid, offset);
+            }
+            Resync::Continue(replid) => {
+                let mut info = self.redis_info.lock().expect("Poisonned redis_info");
+                if let Some(replid) = replid.filter(|replid| replid != info.master_replid()) {
+                    println!("Master replication ID changed to {replid}");
+                    info.shift_replication_id(replid);
+                }
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Applies the writes the master propagates, without replying to them, and reports the
+    /// offset reached every `ACK_PERIOD`, until the link drops or the master stays silent
+    /// for longer than `repl-timeout`.
+    ///
+    /// The offset advances once a command is applied, so an acknowledgement asked by
+    /// `REPLCONF GETACK` does not count the `GETACK` itself.
+    async fn follow_master(
+        mut connection: Connection,
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+    ) -> MasterLinkError {
+        connection.mute();
+        let timeout = redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .replication_config()
+            .timeout();
+        let mut last_io = Instant::now();
+        let mut ack = tokio::time::interval(ACK_PERIOD);
+        loop {
+            let frame = tokio::select! {
+                frame = connection.read_raw_frame() => frame,
+                _ = ack.tick() => {
+                    if last_io.elapsed() > timeout {
+                        return MasterLinkError::Timeout;
+                    }
+                    send_ack(redis_info, &mut connection);
+                    continue;
+                }
+            };
+            let (frame, raw) = match frame {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return MasterLinkError::Closed,
+                Err(e) => return MasterLinkError::Connection(e),
+            };
+            last_io = Instant::now();
+            apply(&frame, &mut connection, store, redis_info);
+            let mut info = redis_info.lock().expect("Poisonned redis_info");
+            info.replicate(&raw);
+            info.touch_master_link();
+        }
+    }
+
+    /// Sends a command to the master and waits for its reply, at most `repl-timeout`.
+    async fn request(
+        &self,
+        connection: &mut Connection,
+        command: &(impl ToRedisBytes + Sync),
+    ) -> Result<Type, MasterLinkError> {
+        connection.respond(command);
+        let reply = tokio::time::timeout(self.timeout(), connection.read_frame())
+            .await
+            .map_err(|_| MasterLinkError::Timeout)??
+            .ok_or(MasterLinkError::Closed)?;
+        self.redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .touch_master_link();
+        Ok(reply)
+    }
+
+    async fn send_ping(&self, connection: &mut Connection) -> Result<(), MasterLinkError> {
+        match self.request(connection, &RedisCommands::Ping).await? {
+            Type::SimpleString(reply) if reply == SimpleString::from("PONG") => {
+                println!("Master responded with {}", RedisResponse::Pong);
+                Ok(())
+            }
+            invalid_answer => Err(MasterLinkError::UnexpectedReply(
+                "PING",
+                format!("{invalid_answer:?}"),
+            )),
+        }
+    }
+    async fn send_replconf(&self, connection: &mut Connection) -> Result<(), MasterLinkError> {
+        for repl_conf_command in [
+            Array::from_string(&format!("REPLCONF listening-port {}", self.listening_port)),
+            Array::from_string("REPLCONF capa psync2"),
+        ] {
+            println!("Sending to master : '{repl_conf_command}'");
+            match self.request(connection, &repl_conf_command).await? {
+                Type::SimpleString(reply) if reply == SimpleString::from("OK") => {
+                    println!("Master responded REPLCONF with {}", RedisResponse::Ok);
+                }
+                invalid_answer => {
+                    return Err(MasterLinkError::UnexpectedReply(
+                        "REPLCONF",
+                        format!("{invalid_answer:?}"),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Asks the master to continue the stream from `offset` of `replication_id`, or for a
+    /// full resynchronisation when both are unknown.
+    async fn send_psync(
+        &self,
+        connection: &mut Connection,
+        replication_id: Option<ReplicationId>,
+        offset: Option<Offset>,
+    ) -> Result<Resync, MasterLinkError> {
+        let replication_id = replication_id.unwrap_or_else(|| ReplicationId::parse(None));
+        let offset = offset.unwrap_or_else(|| Offset::parse(None));
+        let command = RedisCommands::Psync(replication_id, offset);
+        println!(
+            "replica sending PSync command: '{}'",
+            String::from_utf8_lossy(&command.to_redis_bytes())
+        );
+        let reply = match self.request(connection, &command).await? {
+            Type::SimpleString(reply) => reply,
+            invalid_answer => {
+                return Err(MasterLinkError::UnexpectedReply(
+                    "PSYNC",
+                    format!("{invalid_answer:?}"),
+                ))
+            }
+        };
+        println!("Master responded with {}", reply.data());
+        match reply
+            .data()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            ["FULLRESYNC", replid, offset] => Ok(Resync::Full(
+                ReplicationId::parse(Some((*replid).to_string())),
+                Offset::parse(Some(offset.parse().unwrap_or_default())),
+            )),
+            ["CONTINUE"] => Ok(Resync::Continue(None)),
+            ["CONTINUE", replid] => Ok(Resync::Continue(Some(ReplicationId::parse(Some(
+                (*replid).to_string(),
+            ))))),
+            _ => Err(MasterLinkError::UnexpectedReply(
+                "PSYNC",
+                reply.data().to_string(),
+            )),
+        }
+    }
+
+    /// Reads the snapshot the master sends after `FULLRESYNC`, saves it as our own RDB file
+    /// like Redis does, and replaces the dataset with it.
+    ///
+    /// The whole transfer must complete within `repl-timeout`.
+    async fn receive_rdb(&self, connection: &mut Connection) -> Result<(), MasterLinkError> {
+        let payload = tokio::time::timeout(self.timeout(), connection.read_rdb_payload())
+            .await
+            .map_err(|_| MasterLinkError::Timeout)??
+            .ok_or(MasterLinkError::Closed)?;
+        println!("Received {} bytes of RDB from master", payload.len());
+        let snapshot = rdb::decoder::decode(&payload)?;
+        let mut keyspace = Keyspace::new();
+        let loaded = snapshot.load_into(&mut keyspace);
+        let path = self
+            .redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .persistence()
+            .rdb_path();
+        match atomic_file::write(&path, &payload) {
+            Ok(()) => keyspace.mark_saved(keyspace.dirty()),
+            Err(e) => eprintln!("Cannot save RDB received from master: {e}"),
+        }
+        let mut store = self.store.lock().expect("Poisonned store");
+        keyspace.set_replica(store.is_replica());
+        *store = keyspace;
+        // The append-only files restart from the new dataset, before the stream appends to them.
+        if let Some(aof) = self
+            .redis_info
+            .lock()
+            .expect("Poisonned redis_info")
+            .aof_mut()
+        {
+            if let Err(e) = aof.reset(Snapshot::from_keyspace(&store)) {
+                eprintln!("Cannot rewrite the append only file after synchronisation: {e}");
+            }
+        }
+        drop(store);
+        println!("Loaded {loaded} keys from master");
+        Ok(())
+    }
+}
+
+/// Executes a command of the master's stream: the writes it propagates, `PING` and
+/// `REPLCONF`. Anything else, such as `REPLICAOF` or `CONFIG`, is refused rather than
+/// applied to this replica.
+fn apply(
+    frame: &Type,
+    connection: &mut Connection,
+    store: &RedisStore,
+    redis_info: &Arc<Mutex<RedisInfo>>,
+) {
+    match RedisInstance::parse_redis_command(frame) {
+        Ok(command)
+            if command.is_write()
+                || matches!(command, RedisCommands::Ping | RedisCommands::Replconf(..)) =>
+        {
+            RedisInstance::dispatch(command, connection, store, redis_info);
+        }
+        Ok(command) => {
+            eprintln!("Refusing {command} sent by master");
+            connection.respond(&ClientHandlerError::NotAllowedHere(command.to_string()));
+        }
+        Err(e) => eprintln!("Cannot parse command {frame:?} from master: {e}"),
+    }
+}
+
+/// Tells the master the offset this replica processed, even on the muted link.
+pub fn send_ack(redis_info: &Arc<Mutex<RedisInfo>>, connection: &mut Connection) {
+    let offset = redis_info
+        .lock()
+        .expect("Poisonned redis_info")
+        .master_repl_offset()
+        .value();
+    connection.send(&Array::from_string(&format!("REPLCONF ACK {offset}")));
+}
+
+/// How a master answered `PSYNC`.
+enum Resync {
+    /// `FULLRESYNC <replid> <offset>`: a snapshot follows, then the stream from `offset`.
+    Full(ReplicationId, Offset),
+    /// `CONTINUE [<replid>]`: the stream resumes where the replica stopped, under a new
+    /// replication ID if the master changed it.
+    Continue(Option<ReplicationId>),
+}
+
+/// Why a replica lost, or could not establish, its link with the master.
+#[derive(Debug)]
+pub enum MasterLinkError {
+    Io(std::io::Error),
+    Connection(ConnectionError),
+    Closed,
+    Timeout,
+    /// The master answered a command of the handshake unexpectedly.
+    UnexpectedReply(&'static str, String),
+    Rdb(RdbError),
+}
+impl From<std::io::Error> for MasterLinkError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+impl From<ConnectionError> for MasterLinkError {
+    fn from(error: ConnectionError) -> Self {
+        Self::Connection(error)
+    }
+}
+impl From<RdbError> for MasterLinkError {
+    fn from(error: RdbError) -> Self {
+        Self::Rdb(error)
+    }
+}
+impl Display for MasterLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Connection(error) => write!(f, "{error}"),
+            Self::Closed => write!(f, "Master closed the connection"),
+            Self::Timeout => write!(f, "Timeout waiting for the master"),
+            Self::UnexpectedReply(command, reply) => {
+                write!(f, "Unexpected reply to {command} from master: {reply}")
+            }
+            Self::Rdb(error) => write!(f, "Cannot load RDB sent by master: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{redis_info::Role, Config, MasterConfig};
+
+    #[tokio::test]
+    async fn test_apply_refuses_commands_that_are_not_propagated() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut connection = Connection::new(stream);
+        connection.mute();
+        let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
+        let config = MasterConfig::from_args(&[]).unwrap();
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&Config::Master(config))));
+        let master = ReplicaOf::new("127.0.0.1".parse().unwrap(), "6380".parse().unwrap());
+        redis_info.lock().unwrap().set_master(master.clone());
+
+        for command in [
+            "REPLICAOF 127.0.0.1 6381",
+            "REPLICAOF NO ONE",
+            "CONFIG SET maxmemory 1",
+            "SAVE",
+            "WAIT 1 0",
+            "SET key value",
+        ] {
+            apply(
+                &Type::Array(Array::from_string(command)),
+                &mut connection,
+                &store,
+                &redis_info,
+            );
+        }
+        let info = redis_info.lock().unwrap();
+        assert_eq!(*info.role(), Role::Replica);
+        assert_eq!(*info.subscribe_master().borrow(), Some(master));
+        assert_eq!(info.config().maxmemory(), 0);
+        assert_eq!(info.rdb().last_failure(), None);
+        drop(info);
+        assert!(store.lock().unwrap().get(b"key").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_apply_deletes_expired_keys_on_master_del() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut connection = Connection::new(stream);
+        connection.mute();
+        let mut keyspace = Keyspace::new();
+        keyspace.set_replica(true);
+        let store: RedisStore = Arc::new(Mutex::new(keyspace));
+        let config = MasterConfig::from_args(&["--save", ""]).unwrap();
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&Config::Master(config))));
+        let master = ReplicaOf::new("127.0.0.1".parse().unwrap(), "6380".parse().unwrap());
+        redis_info.lock().unwrap().set_master(master);
+
+        for command in ["SET stale value PX 1", "GET stale"] {
+            apply(
+                &Type::Array(Array::from_string(command)),
+                &mut connection,
+                &store,
+                &redis_info,
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(store.lock().unwrap().get(b"stale").is_none());
+        assert_eq!(store.lock().unwrap().len(), 1);
+        apply(
+            &Type::Array(Array::from_string("DEL stale")),
+            &mut connection,
+            &store,
+            &redis_info,
+        );
+        assert!(store.lock().unwrap().is_empty());
+    }
+}
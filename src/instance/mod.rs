@@ -1,11 +1,15 @@
-use std::net::TcpListener;
+use std::future::Future;
+
+use tokio::net::TcpListener;
 
 #[allow(clippy::module_name_repetitions)]
-pub mod master_instance;
-#[allow(clippy::module_name_repetitions)]
-pub mod replica_instance;
+pub mod redis_instance;
 
+pub mod active_expire;
 pub mod client_handler;
+pub mod connection;
+pub mod master_link;
+pub mod persistence;
 
 /// Trait for running a Redis instance.
 pub trait Run {
@@ -19,7 +23,7 @@ pub trait Run {
     /// # Errors
     ///
     /// If the instance fails to run, an `Error` is returned.
-    fn run(&self);
+    fn run(&self) -> impl Future<Output = ()> + Send;
 }
 
 /// Trait for listening to incoming connections.
@@ -34,5 +38,5 @@ pub trait Listen {
     /// # Errors
     ///
     /// If the listener fails to bind to the address, an `Error` is returned.
-    fn listen(&self) -> TcpListener;
+    fn listen(&self) -> impl Future<Output = TcpListener> + Send;
 }
@@ -0,0 +1,408 @@
+//! RDB snapshots of the keyspace: `SAVE`, `BGSAVE` and the `save` schedule, along with the
+//! `appendfsync everysec` flushes and the rewrites of the append-only files.
+//!
+//! A background save copies the keyspace under the store lock, then encodes and writes the
+//! snapshot on a blocking thread so that clients are served meanwhile. Writes made during
+//! the save stay counted in the dirty counter for the next one. A rewrite of the
+//! append-only files works the same way, writes made meanwhile going to a new incremental
+//! file.
+
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    aof::Aof,
+    atomic_file,
+    rdb::{encoder, Snapshot},
+    redis_info::RedisInfo,
+    resp::{SimpleError, ToRedisBytes},
+    store::unix_time_millis,
+    RedisStore,
+};
+
+const SCHEDULE_PERIOD: Duration = Duration::from_millis(100);
+/// Delay before a scheduled save is retried after a failure.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+
+/// Saves the keyspace in the foreground, blocking the calling client.
+///
+/// Other clients are still served while the snapshot is written, so the save is marked in
+/// progress for background saves to wait for it.
+pub fn save(store: &RedisStore, redis_info: &Arc<Mutex<RedisInfo>>) -> Result<(), SaveError> {
+    let path = {
+        let mut info = redis_info.lock().map_err(|_| SaveError::PoisonedStore)?;
+        if info.rdb().save_in_progress() {
+            return Err(SaveError::InProgress);
+        }
+        info.rdb_mut().start_save();
+        info.persistence().rdb_path()
+    };
+    let (snapshot, dirty) = match snapshot(store, redis_info) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            if let Ok(mut info) = redis_info.lock() {
+                info.rdb_mut().finish_save(false);
+            }
+            return Err(e);
+        }
+    };
+    let result = atomic_file::write(&path, &encoder::encode(&snapshot));
+    finish(store, redis_info, dirty, Saver::Foreground, result.is_ok())?;
+    result.map_err(SaveError::Io)
+}
+
+/// Starts saving the keyspace on a blocking thread.
+pub fn background_save(
+    store: &RedisStore,
+    redis_info: &Arc<Mutex<RedisInfo>>,
+) -> Result<(), SaveError> {
+    let path = {
+        let mut info = redis_info.lock().map_err(|_| SaveError::PoisonedStore)?;
+        if info.rdb().save_in_progress() {
+            return Err(SaveError::InProgress);
+        }
+        info.rdb_mut().start_bgsave();
+        info.persistence().rdb_path()
+    };
+    let (snapshot, dirty) = match snapshot(store, redis_info) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            if let Ok(mut info) = redis_info.lock() {
+                info.rdb_mut().finish_bgsave(false);
+            }
+            return Err(e);
+        }
+    };
+    let store = store.clone();
+    let redis_info = redis_info.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = atomic_file::write(&path, &encoder::encode(&snapshot));
+        if let Err(e) = &result {
+            eprintln!("Background saving error: {e}");
+        }
+        let _ = finish(
+            &store,
+            &redis_info,
+            dirty,
+            Saver::Background,
+            result.is_ok(),
+        );
+    });
+    Ok(())
+}
+
+/// Starts a background save whenever a `save` point is reached or a `BGSAVE SCHEDULE` is
+/// pending, forever.
+pub async fn save_schedule(store: RedisStore, redis_info: Arc<Mutex<RedisInfo>>) {
+    let mut interval = tokio::time::interval(SCHEDULE_PERIOD);
+    loop {
+        interval.tick().await;
+        let Some(due) = save_due(&store, &redis_info) else {
+            eprintln!("Save schedule stopped: poisoned store");
+            return;
+        };
+        if due {
+            if let Err(e) = background_save(&store, &redis_info) {
+                eprintln!("Cannot start scheduled background save: {e}");
+            }
+        }
+    }
+}
+
+/// Flushes the writes appended to the append-only file to disk every second, forever.
+///
+/// The flush runs on a blocking thread with `RedisInfo` unlocked, so that writes keep being
+/// appended meanwhile.
+pub async fn fsync_schedule(redis_info: Arc<Mutex<RedisInfo>>) {
+    let mut interval = tokio::time::interval(FSYNC_PERIOD);
+    loop {
+        interval.tick().await;
+        let pending = {
+            let Ok(mut info) = redis_info.lock() else {
+                eprintln!("Append only file fsync stopped: poisoned redis_info");
+                return;
+            };
+            info.aof_mut().map(Aof::pending_fsync)
+        };
+        let file = match pending {
+            Some(Ok(Some(file))) => file,
+            Some(Err(e)) => {
+                eprintln!("Cannot fsync the append only file: {e}");
+                continue;
+            }
+            Some(Ok(None)) | None => continue,
+        };
+        if let Ok(Err(e)) = tokio::task::spawn_blocking(move || file.sync_data()).await {
+            eprintln!("Cannot fsync the append only file: {e}");
+        }
+    }
+}
+
+/// Starts rewriting the append-only files, the new base file being written on a blocking
+/// thread.
+pub fn background_rewrite(
+    store: &RedisStore,
+    redis_info: &Arc<Mutex<RedisInfo>>,
+) -> Result<(), RewriteError> {
+    let keyspace = store.lock().map_err(|_| RewriteError::PoisonedStore)?;
+    let mut info = redis_info.lock().map_err(|_| RewriteError::PoisonedStore)?;
+    let aof = info.aof_mut().ok_or(RewriteError::Disabled)?;
+    if aof.rewrite_in_progress() {
+        return Err(RewriteError::InProgress);
+    }
+    let rewrite = aof.start_rewrite().map_err(RewriteError::Io)?;
+    drop(info);
+    let snapshot = Snapshot::from_keyspace(&keyspace);
+    drop(keyspace);
+    let redis_info = redis_info.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = rewrite.write(snapshot);
+        if let Err(e) = &result {
+            eprintln!("Background append only file rewriting error: {e}");
+        }
+        if let Some(aof) = redis_info
+            .lock()
+            .as_mut()
+            .ok()
+            .and_then(|info| info.aof_mut())
+        {
+            aof.finish_rewrite(&rewrite, result.is_ok());
+        }
+    });
+    Ok(())
+}
+
+/// Starts a background rewrite whenever the append-only files grew past
+/// `auto-aof-rewrite-percentage` and `auto-aof-rewrite-min-size`, forever.
+pub async fn rewrite_schedule(store: RedisStore, redis_info: Arc<Mutex<RedisInfo>>) {
+    let mut interval = tokio::time::interval(SCHEDULE_PERIOD);
+    loop {
+        interval.tick().await;
+        let Ok(info) = redis_info.lock() else {
+            eprintln!("Rewrite schedule stopped: poisoned redis_info");
+            return;
+        };
+        let (percentage, min_size) = info.persistence().auto_aof_rewrite();
+        let due = info.aof().is_some_and(|aof| {
+            aof.rewrite_due(percentage, min_size)
+                && aof
+                    .last_rewrite_failure()
+                    .is_none_or(|failure| failure.elapsed() >= RETRY_DELAY)
+        });
+        drop(info);
+        if due {
+            println!("Starting automatic rewriting of the append only file");
+            if let Err(e) = background_rewrite(&store, &redis_info) {
+                eprintln!("Cannot start automatic append only file rewriting: {e}");
+            }
+        }
+    }
+}
+
+/// Returns whether a background save should start now, `None` if a lock is poisoned.
+fn save_due(store: &RedisStore, redis_info: &Arc<Mutex<RedisInfo>>) -> Option<bool> {
+    let changes = store.lock().ok()?.dirty();
+    let info = redis_info.lock().ok()?;
+    let rdb = info.rdb();
+    if rdb.save_in_progress() {
+        return Some(false);
+    }
+    if rdb.bgsave_scheduled() {
+        return Some(true);
+    }
+    if rdb
+        .last_failure()
+        .is_some_and(|failure| failure.elapsed() < RETRY_DELAY)
+    {
+        return Some(false);
+    }
+    let since_save = unix_time_millis() / 1000 - rdb.last_save();
+    let elapsed = Duration::from_secs(u64::try_from(since_save).unwrap_or(0));
+    Some(
+        info.persistence()
+            .save_points()
+            .iter()
+            .any(|point| point.is_due(changes, elapsed)),
+    )
+}
+
+/// Copies the keyspace with the replication offset it is at, returning the snapshot and the
+/// dirty counter it covers.
+fn snapshot(
+    store: &RedisStore,
+    redis_info: &Arc<Mutex<RedisInfo>>,
+) -> Result<(Snapshot, u64), SaveError> {
+    let keyspace = store.lock().map_err(|_| SaveError::PoisonedStore)?;
+    let mut snapshot = Snapshot::from_keyspace(&keyspace);
+    let dirty = keyspace.dirty();
+    let info = redis_info.lock().map_err(|_| SaveError::PoisonedStore)?;
+    if let Some((replid, offset)) = info.rdb_replication() {
+        snapshot.set_replication(&replid, &offset);
+    }
+    drop(info);
+    drop(keyspace);
+    Ok((snapshot, dirty))
+}
+
+/// Who wrote a snapshot.
+#[derive(Debug, Clone, Copy)]
+enum Saver {
+    /// `SAVE`.
+    Foreground,
+    /// `BGSAVE` or the `save` schedule.
+    Background,
+}
+
+/// Records the outcome of a save, clearing the `dirty` changes it persisted.
+fn finish(
+    store: &RedisStore,
+    redis_info: &Arc<Mutex<RedisInfo>>,
+    dirty: u64,
+    saver: Saver,
+    succeeded: bool,
+) -> Result<(), SaveError> {
+    if succeeded {
+        store
+            .lock()
+            .map_err(|_| SaveError::PoisonedStore)?
+            .mark_saved(dirty);
+    }
+    let mut info = redis_info.lock().map_err(|_| SaveError::PoisonedStore)?;
+    match saver {
+        Saver::Foreground => info.rdb_mut().finish_save(succeeded),
+        Saver::Background => info.rdb_mut().finish_bgsave(succeeded),
+    }
+    drop(info);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    InProgress,
+    Io(std::io::Error),
+    PoisonedStore,
+}
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InProgress => write!(f, "Background save already in progress"),
+            Self::Io(e) => write!(f, "Error saving DB on disk: {e}"),
+            Self::PoisonedStore => write!(f, "Store lock poisoned"),
+        }
+    }
+}
+
+impl ToRedisBytes for SaveError {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        SimpleError::from(format!("ERR {self}").as_str()).to_redis_bytes()
+    }
+}
+
+#[derive(Debug)]
+pub enum RewriteError {
+    Disabled,
+    InProgress,
+    Io(std::io::Error),
+    PoisonedStore,
+}
+impl Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "Append only file is disabled, set appendonly yes"),
+            Self::InProgress => {
+                write!(
+                    f,
+                    "Background append only file rewriting already in progress"
+                )
+            }
+            Self::Io(e) => write!(f, "Cannot start append only file rewriting: {e}"),
+            Self::PoisonedStore => write!(f, "Store lock poisoned"),
+        }
+    }
+}
+
+impl ToRedisBytes for RewriteError {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        SimpleError::from(format!("ERR {self}").as_str()).to_redis_bytes()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{rdb, server_config::Offset, Config, Keyspace, MasterConfig, RedisValue};
+
+    fn setup(dir: &std::path::Path) -> (RedisStore, Arc<Mutex<RedisInfo>>) {
+        let dir = dir.to_str().unwrap();
+        let config = MasterConfig::from_args(&["--dir", dir, "--save", ""]).unwrap();
+        let redis_info = Arc::new(Mutex::new(RedisInfo::new(&Config::Master(config))));
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(
+            Bytes::from("key"),
+            RedisValue::new(Bytes::from("value"), None),
+        );
+        (Arc::new(Mutex::new(keyspace)), redis_info)
+    }
+
+    #[test]
+    fn test_save_writes_snapshot_and_clears_dirty() {
+        let dir = std::env::temp_dir().join(format!("save-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (store, redis_info) = setup(&dir);
+        assert_eq!(store.lock().unwrap().dirty(), 1);
+        save(&store, &redis_info).unwrap();
+        assert_eq!(store.lock().unwrap().dirty(), 0);
+        let (keyspace, replication) = rdb::load_keyspace(&dir.join("dump.rdb"));
+        assert_eq!(keyspace.len(), 1);
+        let info = redis_info.lock().unwrap();
+        assert_eq!(
+            replication,
+            Some((info.master_replid().to_owned(), Offset::parse(Some(0))))
+        );
+        drop(info);
+        assert!(redis_info
+            .lock()
+            .unwrap()
+            .rdb()
+            .info(0)
+            .contains("rdb_saves:1\r\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_refused_during_background_save() {
+        let (store, redis_info) = setup(&std::env::temp_dir());
+        redis_info.lock().unwrap().rdb_mut().start_bgsave();
+        assert!(matches!(
+            save(&store, &redis_info),
+            Err(SaveError::InProgress)
+        ));
+        assert!(matches!(
+            background_save(&store, &redis_info),
+            Err(SaveError::InProgress)
+        ));
+    }
+
+    #[test]
+    fn test_background_save_refused_during_save() {
+        let (store, redis_info) = setup(&std::env::temp_dir());
+        redis_info.lock().unwrap().rdb_mut().start_save();
+        assert!(matches!(
+            background_save(&store, &redis_info),
+            Err(SaveError::InProgress)
+        ));
+        assert!(matches!(
+            save(&store, &redis_info),
+            Err(SaveError::InProgress)
+        ));
+        assert_eq!(save_due(&store, &redis_info), Some(false));
+        assert!(!redis_info.lock().unwrap().rdb().bgsave_in_progress());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    aof::{self, Aof},
+    rdb::{self, encoder, Snapshot},
+    redis_commands::{CommandError, RedisCommands},
+    redis_info::{RedisInfo, Role},
+    replication::{encode_command, PING_PERIOD},
+    resp::{redis_response::RedisResponse, RDBFile, SimpleString, ToRedisBytes},
+    server_config::{persistence::PersistenceConfig, Offset, ReplicationId},
+    ClientHandler, Config, Keyspace, Listen, RedisStore, ReplicaOf,
+};
+
+use super::{
+    active_expire::active_expire,
+    client_handler::{ClientHandlerError, CommonCommands},
+    connection::Connection,
+    master_link::{self, Follower},
+    persistence::{fsync_schedule, rewrite_schedule, save_schedule},
+    Run,
+};
+use std::{
+    io::Error,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+/// A Redis server, whose role changes at runtime with `REPLICAOF`.
+pub struct RedisInstance {
+    store: RedisStore,
+    config: Config,
+    redis_info: Arc<Mutex<RedisInfo>>,
+}
+impl CommonCommands for RedisInstance {
+    fn match_redis_command(
+        redis_command: RedisCommands,
+        connection: &mut Connection,
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+    ) {
+        let current_instance_role;
+        {
+            let current_instance_role_lock = redis_info.lock().expect("Poisonned redis_info");
+            current_instance_role = current_instance_role_lock.role().clone();
+        }
+        match &redis_command {
+            RedisCommands::Replconf(option, _) if option.eq_ignore_ascii_case("getack") => {
+                master_link::send_ack(redis_info, connection);
+            }
+            RedisCommands::Replconf(option, value) => {
+                Self::replconf(option, value, redis_info, connection);
+            }
+            RedisCommands::Psync(_, _) if current_instance_role == Role::Replica => {
+                Self::respond(&RedisResponse::Null, connection);
+            }
+            RedisCommands::Psync(replid, offset) => {
+                Self::psync(replid, offset, store, redis_info, connection);
+            }
+            RedisCommands::Replicaof(master) => {
+                Self::replicaof(master.clone(), store, redis_info, connection);
+            }
+            RedisCommands::FullResync(a, b) => {
+                println!(
+                    "Received {}",
+                    String::from_utf8_lossy(
+                        &RedisCommands::FullResync(a.clone(), b.clone()).to_redis_bytes()
+                    )
+                );
+            }
+            command => {
+                Self::respond(
+                    &ClientHandlerError::NotAllowedHere(command.to_string()),
+                    connection,
+                );
+            }
+        }
+        println!("Instance {current_instance_role}: Matched command '{redis_command}'");
+    }
+}
+
+impl ClientHandler for RedisInstance {}
+
+impl RedisInstance {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        let (mut keyspace, replication, aof) = Self::load(config.persistence());
+        // Keys found expired while loading are deleted again on every load, there is
+        // nothing to propagate.
+        keyspace.take_expired();
+        keyspace.set_replica(matches!(config, Config::Replica(_)));
+        let store: RedisStore = Arc::new(Mutex::new(keyspace));
+        let mut redis_info = RedisInfo::new(&config);
+        if let Some((replid, offset)) = replication {
+            redis_info.restore_replication(replid, offset);
+        }
+        if let Some(aof) = aof {
+            redis_info.enable_aof(aof);
+        }
+        let redis_info = Arc::new(Mutex::new(redis_info));
+        Self {
+            store,
+            config,
+            redis_info,
+        }
+    }
+    /// Loads the dataset the server starts with: from the append-only file when it is
+    /// enabled, otherwise from the RDB file along with the replication position it records.
+    ///
+    /// Missing append-only files are created from the RDB file, so that turning them on
+    /// keeps the dataset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the append-only files cannot be created or opened.
+    fn load(
+        persistence: &PersistenceConfig,
+    ) -> (Keyspace, Option<(ReplicationId, Offset)>, Option<Aof>) {
+        if !persistence.appendonly() {
+            let (keyspace, replication) = rdb::load_keyspace(&persistence.rdb_path());
+            return (keyspace, replication, None);
+        }
+        let (keyspace, replication, aof) = aof::load_keyspace(persistence).map_or_else(
+            || {
+                let (keyspace, replication) = rdb::load_keyspace(&persistence.rdb_path());
+                let aof = Aof::create(persistence, Snapshot::from_keyspace(&keyspace));
+                println!(
+                    "Created append only files in {}",
+                    persistence.aof_dir().display()
+                );
+                (keyspace, replication, aof.map_err(|e| e.to_string()))
+            },
+            |keyspace| {
+                let aof = Aof::open(persistence).map_err(|e| e.to_string());
+                (keyspace, None, aof)
+            },
+        );
+        let aof = aof.unwrap_or_else(|e| {
+            panic!(
+                "Cannot open append only files in {}: {e}",
+                persistence.aof_dir().display()
+            )
+        });
+        println!(
+            "Append only file enabled, appendfsync {}",
+            persistence.appendfsync()
+        );
+        (keyspace, replication, Some(aof))
+    }
+    /// Follows a new master, or with `NO ONE` stops following one and becomes a master
+    /// keeping the dataset.
+    ///
+    /// The dataset of a replica keeps its expired keys until its master deletes them, a
+    /// master deletes them itself.
+    fn replicaof(
+        master: Option<ReplicaOf>,
+        store: &RedisStore,
+        redis_info: &Arc<Mutex<RedisInfo>>,
+        connection: &mut Connection,
+    ) {
+        let mut keyspace = store.lock().expect("Poisonned store");
+        let mut info = redis_info.lock().expect("Poisonned redis_info");
+        keyspace.set_replica(master.is_some());
+        drop(keyspace);
+        match master {
+            None => {
+                if *info.role() == Role::Replica {
+                    info.promote();
+                    println!(
+                        "MASTER MODE enabled, replication ID {}",
+                        info.master_replid()
+                    );
+                }
+                drop(info);
+                Self::respond(&RedisResponse::Ok, connection);
+            }
+            Some(master) => {
+                let changed = info.set_master(master.clone());
+                drop(info);
+                if changed {
+                    println!("REPLICAOF {master} enabled");
+                    Self::respond(&RedisResponse::Ok, connection);
+                } else {
+                    Self::respond(
+                        &SimpleString::from("OK Already connected to specified master"),
+                        connection,
+                    );
+                }
+            }
+        }
+    }
+    /// Records what a replica tells about itself. Acknowledgements get no reply, the
+    /// replica not reading any on its link.
+    fn replconf(
+        option: &str,
+        value: &str,
+        server_info: &Arc<Mutex<RedisInfo>>,
+        connection: &mut Connection,
+    ) {
+        match option.to_lowercase().as_str() {
+            "ack" => {
+                if let Ok(offset) = value.parse::<i64>() {
+                    server_info
+                        .lock()
+                        .expect("Poisonned store when opening server info")
+                        .replicas_mut()
+                        .acknowledge(connection.session().id(), offset);
+                }
+            }
+            "listening-port" => match value.parse::<u16>() {
+                Ok(port) => {
+                    connection.session_mut().set_listening_port(port);
+                    Self::respond(&RedisResponse::Ok, connection);
+                }
+                Err(_) => Self::respond(&CommandError::NotAnInteger, connection),
+            },
+            _ => Self::respond(&RedisResponse::Ok, connection),
+        }
+    }
+    /// Answers `PSYNC`, then turns the connection into a replication link.
+    ///
+    /// A replica asking for an offset still in the backlog gets `CONTINUE` followed by the
+    /// commands it missed. Any other gets `FULLRESYNC` followed by a snapshot of the
+    /// keyspace, taken with the replica attached under the store lock, so that it receives
+    /// exactly the writes applied after the snapshot.
+    fn psync(
+        replid: &ReplicationId,
+        offset: &Offset,
+        store: &RedisStore,
+        server_info: &Arc<Mutex<RedisInfo>>,
+        connection: &mut Connection,
+    ) {
+        println!("Received PYSNC command");
+        let address = format!(
+            "{}:{}",
+            connection.peer_ip(),
+            connection.session().listening_port().unwrap_or_default()
+        );
+
+        let keyspace = match store.lock() {
+            Ok(keyspace) => keyspace,
+            Err(e) => {
+                eprintln!("Error locking store: {e}");
+                Self::respond(&RedisResponse::Null, connection);
+                return;
+            }
+        };
+        let mut server_info_locked = server_info
+            .lock()
+            .expect("Poisonned store when opening server info");
+        if let Some(replication_stream) = server_info_locked.partial_resync(
+            replid,
+            offset.value(),
+            connection.session().id(),
+            address.clone(),
+        ) {
+            let replid = server_info_locked.master_replid().to_owned();
+            drop(server_info_locked);
+            drop(keyspace);
+            println!("Partial resynchronisation accepted from offset {offset}");
+            Self::respond(
+                &SimpleString::from(format!("CONTINUE {replid}").as_str()),
+                connection,
+            );
+            connection.attach_replication_stream(replication_stream);
+            return;
+        }
+        let mut snapshot = Snapshot::from_keyspace(&keyspace);
+        let replid = server_info_locked.master_replid().to_owned();
+        let offset = server_info_locked.master_repl_offset().to_owned();
+        snapshot.set_replication(&replid, &offset);
+        let replication_stream =
+            server_info_locked.attach_replica(connection.session().id(), address);
+        drop(server_info_locked);
+        drop(keyspace);
+        let command = RedisCommands::FullResync(replid, offset);
+        Self::respond(&command, connection);
+        let rdb_file = RDBFile::new(encoder::encode(&snapshot).into());
+        Self::respond(&rdb_file, connection);
+        connection.attach_replication_stream(replication_stream);
+    }
+}
+
+impl Run for RedisInstance {
+    /// Runs the Redis instance.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the instance runs successfully, otherwise returns an `Error`.
+    type Error = Error;
+    async fn run(&self) {
+        let follower = Follower::new(
+            self.store.clone(),
+            self.redis_info.clone(),
+            self.config.port().get(),
+        );
+        tokio::spawn(follower.run());
+        let listener = self.listen().await;
+        tokio::spawn(active_expire(self.store.clone(), self.redis_info.clone()));
+        tokio::spawn(save_schedule(self.store.clone(), self.redis_info.clone()));
+        tokio::spawn(fsync_schedule(self.redis_info.clone()));
+        tokio::spawn(rewrite_schedule(
+            self.store.clone(),
+            self.redis_info.clone(),
+        ));
+        tokio::spawn(ping_replicas(self.redis_info.clone()));
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("Cannot accept connection: {e}");
+                    continue;
+                }
+            };
+            let store = self.store.clone();
+            let redis_info = self.redis_info.clone();
+            tokio::spawn(Self::handle(redis_info, store, stream));
+        }
+    }
+}
+
+/// Pings the replicas every `PING_PERIOD` through the replication stream, so that they can
+/// tell an idle link from a dead one.
+async fn ping_replicas(redis_info: Arc<Mutex<RedisInfo>>) {
+    let ping = encode_command(&[b"PING"]);
+    let mut interval = tokio::time::interval(PING_PERIOD);
+    loop {
+        interval.tick().await;
+        let Ok(mut info) = redis_info.lock() else {
+            eprintln!("Replica pings stopped: poisoned redis_info");
+            return;
+        };
+        if *info.role() == Role::Master && info.replicas().len() > 0 {
+            info.feed_replicas(&ping);
+        }
+    }
+}
+
+impl Listen for RedisInstance {
+    /// Listens to incoming connections and returns a `TcpListener`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `TcpListener` if the listening is successful, otherwise returns an `Error`.
+    type Error = Error;
+    async fn listen(&self) -> TcpListener {
+        let address = SocketAddr::new(*self.config.bind().get(), self.config.port().get());
+        println!("Listening on {address}");
+
+        TcpListener::bind(address).await.expect("Cannot listen")
+    }
+}
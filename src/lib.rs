@@ -1,15 +1,21 @@
+mod aof;
+mod atomic_file;
+mod command_table;
+mod glob;
 mod instance;
+mod rdb;
 mod redis_commands;
 mod redis_info;
+mod replication;
 mod resp;
 mod server_config;
 mod store;
 
 pub use instance::client_handler::ClientHandler;
-pub use instance::{master_instance::MasterInstance, replica_instance::ReplicaInstance};
+pub use instance::redis_instance::RedisInstance;
 pub use instance::{Listen, Run};
 pub use server_config::{
-    host::Host, port::Port, replica::ReplicaOf, server::Config, server::MasterConfig,
-    server::ReplicaConfig, RedisStore,
+    host::Host, port::Port, replica::ReplicaOf, server::Config, server::ConfigError,
+    server::MasterConfig, server::ReplicaConfig, RedisStore,
 };
-pub use store::RedisValue;
+pub use store::{Keyspace, RedisValue};
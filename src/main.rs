@@ -1,16 +1,16 @@
-use redis_starter_rust::{Config, MasterInstance, ReplicaInstance, Run};
+use redis_starter_rust::{Config, RedisInstance, Run};
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let server_config = Config::from_args(&args.iter().map(String::as_str).collect::<Vec<&str>>());
-    match server_config {
-        Config::Replica(config) => {
-            let redis_server = ReplicaInstance::new(config);
-            redis_server.run();
-        }
-        Config::Master(config) => {
-            let redis_server = MasterInstance::new(config);
-            redis_server.run();
-        }
-    }
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let server_config =
+        match Config::from_args(&args.iter().map(String::as_str).collect::<Vec<&str>>()) {
+            Ok(config) => config,
+            Err(error) => {
+                eprintln!("*** FATAL CONFIG FILE ERROR ***\n{error}");
+                std::process::exit(1);
+            }
+        };
+    let redis_server = RedisInstance::new(server_config);
+    redis_server.run().await;
 }
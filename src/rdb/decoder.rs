@@ -0,0 +1,365 @@
+//! Decoding of RDB files.
+
+use bytes::Bytes;
+
+use super::{
+    crc64::crc64, lzf, Entry, RdbError, Snapshot, MAGIC, OPCODE_AUX, OPCODE_EOF, OPCODE_EXPIRETIME,
+    OPCODE_EXPIRETIME_MS, OPCODE_FREQ, OPCODE_FUNCTION2, OPCODE_FUNCTION_PRE_GA, OPCODE_IDLE,
+    OPCODE_MODULE_AUX, OPCODE_RESIZEDB, OPCODE_SELECTDB, OPCODE_SLOT_INFO, TYPE_STRING,
+};
+
+/// Oldest and newest RDB versions this decoder understands.
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u32> = 1..=12;
+/// First version ending with a CRC64 checksum.
+const CHECKSUM_VERSION: u32 = 5;
+
+/// Decodes a complete RDB file.
+///
+/// # Errors
+///
+/// Returns an `RdbError` if the data is not a valid RDB file, or holds a record this
+/// decoder does not know how to skip.
+pub fn decode(data: &[u8]) -> Result<Snapshot, RdbError> {
+    let mut reader = Reader { data, position: 0 };
+    let version = reader.header()?;
+    let mut snapshot = Snapshot {
+        version,
+        ..Snapshot::default()
+    };
+    let mut db = 0;
+    let mut expiration = None;
+    loop {
+        match reader.byte()? {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = reader.length()?,
+            OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OPCODE_AUX => {
+                let field = reader.string()?;
+                let value = reader.string()?;
+                snapshot.aux_fields.push((field, value));
+            }
+            OPCODE_EXPIRETIME => {
+                let seconds = u32::from_le_bytes(reader.array()?);
+                expiration = Some(i64::from(seconds) * 1000);
+            }
+            OPCODE_EXPIRETIME_MS => expiration = Some(i64::from_le_bytes(reader.array()?)),
+            OPCODE_IDLE => {
+                reader.length()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.length()?;
+                }
+            }
+            OPCODE_FUNCTION2 => {
+                reader.string()?;
+            }
+            opcode @ (OPCODE_MODULE_AUX | OPCODE_FUNCTION_PRE_GA) => {
+                return Err(RdbError::UnsupportedOpcode(opcode));
+            }
+            TYPE_STRING => {
+                let key = reader.string()?;
+                let value = reader.string()?;
+                snapshot.entries.push(Entry {
+                    db,
+                    key,
+                    value,
+                    expiration: expiration.take(),
+                });
+            }
+            value_type => {
+                reader.string()?;
+                reader.skip_value(value_type)?;
+                expiration = None;
+                snapshot.skipped += 1;
+            }
+        }
+    }
+    if version >= CHECKSUM_VERSION {
+        let end = reader.position;
+        let expected = u64::from_le_bytes(reader.array()?);
+        let computed = crc64(0, &data[..end]);
+        // A zero checksum means the file was written with checksums disabled.
+        if expected != 0 && expected != computed {
+            return Err(RdbError::ChecksumMismatch { expected, computed });
+        }
+    }
+    Ok(snapshot)
+}
+
+/// A length, or the special encoding of a string when the two high bits are set.
+enum Length {
+    Length(u64),
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn header(&mut self) -> Result<u32, RdbError> {
+        if self.bytes(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err(RdbError::InvalidHeader);
+        }
+        let version = self
+            .bytes(4)
+            .ok()
+            .and_then(|version| std::str::from_utf8(version).ok())
+            .and_then(|version| version.parse().ok())
+            .ok_or(RdbError::InvalidHeader)?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(RdbError::UnsupportedVersion(version));
+        }
+        Ok(version)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], RdbError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .ok_or(RdbError::UnexpectedEnd)?;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(RdbError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RdbError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn byte(&mut self) -> Result<u8, RdbError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn length_or_encoding(&mut self) -> Result<Length, RdbError> {
+        let first = self.byte()?;
+        let length = match first >> 6 {
+            0b00 => u64::from(first & 0x3f),
+            0b01 => u64::from(u16::from_be_bytes([first & 0x3f, self.byte()?])),
+            0b10 if first == 0x80 => u64::from(u32::from_be_bytes(self.array()?)),
+            0b10 if first == 0x81 => u64::from_be_bytes(self.array()?),
+            0b10 => return Err(RdbError::InvalidEncoding(first)),
+            _ => return Ok(Length::Encoded(first & 0x3f)),
+        };
+        Ok(Length::Length(length))
+    }
+
+    fn length(&mut self) -> Result<u64, RdbError> {
+        match self.length_or_encoding()? {
+            Length::Length(length) => Ok(length),
+            Length::Encoded(encoding) => Err(RdbError::InvalidEncoding(encoding)),
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, RdbError> {
+        usize::try_from(self.length()?).map_err(|_| RdbError::UnexpectedEnd)
+    }
+
+    fn string(&mut self) -> Result<Bytes, RdbError> {
+        let string = match self.length_or_encoding()? {
+            Length::Length(length) => {
+                let length = usize::try_from(length).map_err(|_| RdbError::UnexpectedEnd)?;
+                Bytes::copy_from_slice(self.bytes(length)?)
+            }
+            Length::Encoded(0) => Bytes::from(i8::from_le_bytes(self.array()?).to_string()),
+            Length::Encoded(1) => Bytes::from(i16::from_le_bytes(self.array()?).to_string()),
+            Length::Encoded(2) => Bytes::from(i32::from_le_bytes(self.array()?).to_string()),
+            Length::Encoded(3) => {
+                let compressed_length = self.usize()?;
+                let length = self.usize()?;
+                let compressed = self.bytes(compressed_length)?;
+                Bytes::from(lzf::decompress(compressed, length).ok_or(RdbError::InvalidLzf)?)
+            }
+            Length::Encoded(encoding) => return Err(RdbError::InvalidEncoding(encoding)),
+        };
+        Ok(string)
+    }
+
+    /// Skips a score of the legacy sorted set encoding, a string with special lengths for
+    /// NaN and infinities.
+    fn skip_double(&mut self) -> Result<(), RdbError> {
+        let length = self.byte()?;
+        if length < 253 {
+            self.bytes(usize::from(length))?;
+        }
+        Ok(())
+    }
+
+    /// Skips the value of a key holding a type other than a string.
+    fn skip_value(&mut self, value_type: u8) -> Result<(), RdbError> {
+        match value_type {
+            // Lists and sets.
+            1 | 2 | 14 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                }
+            }
+            // Sorted sets with scores as strings.
+            3 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.skip_double()?;
+                }
+            }
+            // Hashes.
+            4 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.string()?;
+                }
+            }
+            // Sorted sets with binary scores.
+            5 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.bytes(8)?;
+                }
+            }
+            // Zipmaps, ziplists, intsets and listpacks, each stored as a single string.
+            9..=13 | 16 | 17 | 20 => {
+                self.string()?;
+            }
+            // Quicklists of listpacks, each node prefixed by its container type.
+            18 => {
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.string()?;
+                }
+            }
+            _ => return Err(RdbError::UnsupportedType(value_type)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Appends the trailer of an RDB file, with its checksum, to `body`.
+    fn with_trailer(mut body: Vec<u8>) -> Vec<u8> {
+        body.push(OPCODE_EOF);
+        let checksum = crc64(0, &body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        body
+    }
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_empty_file_from_redis() {
+        let data = hex("524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2");
+        let snapshot = decode(&data).unwrap();
+        assert_eq!(snapshot.version, 11);
+        assert_eq!(snapshot.aux("redis-ver").unwrap(), "7.2.0");
+        assert_eq!(snapshot.aux("redis-bits").unwrap(), "64");
+        assert_eq!(snapshot.aux("ctime").unwrap(), "1706821741");
+        assert!(snapshot.entries.is_empty());
+    }
+
+    #[test]
+    fn test_decode_keys() {
+        let mut body = b"REDIS0011".to_vec();
+        body.extend_from_slice(b"\xfe\x00\xfb\x03\x01");
+        // A plain string.
+        body.extend_from_slice(b"\x00\x03foo\x03bar");
+        // Integer encoded values, with millisecond and second expirations.
+        body.extend_from_slice(b"\xfc\x00\x68\xe5\xcf\x8b\x01\x00\x00");
+        body.extend_from_slice(b"\x00\x03int\xc1\x39\x30");
+        body.extend_from_slice(b"\xfd\x00\x2f\x68\x59");
+        body.extend_from_slice(b"\x00\x03neg\xc0\xfe");
+        // An LZF compressed value.
+        body.extend_from_slice(b"\x00\x03lzf\xc3\x05\x0d\x00a\xe0\x03\x00");
+        // A key of another database.
+        body.extend_from_slice(b"\xfe\x01\x00\x01k\x01v");
+        let snapshot = decode(&with_trailer(body)).unwrap();
+
+        let entry = |db, key: &str, value: &str, expiration| Entry {
+            db,
+            key: Bytes::from(key.to_string()),
+            value: Bytes::from(value.to_string()),
+            expiration,
+        };
+        assert_eq!(
+            snapshot.entries,
+            vec![
+                entry(0, "foo", "bar", None),
+                entry(0, "int", "12345", Some(1_700_000_000_000)),
+                entry(0, "neg", "-2", Some(1_500_000_000_000)),
+                entry(0, "lzf", "aaaaaaaaaaaaa", None),
+                entry(1, "k", "v", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_skips_other_types() {
+        let mut body = b"REDIS0011".to_vec();
+        // A hash with one field, then a listpack encoded set.
+        body.extend_from_slice(b"\x04\x01h\x01\x01f\x01v");
+        body.extend_from_slice(b"\x14\x01s\x02lp");
+        body.extend_from_slice(b"\x00\x01k\x01v");
+        let snapshot = decode(&with_trailer(body)).unwrap();
+        assert_eq!(snapshot.skipped, 2);
+        assert_eq!(snapshot.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(
+            decode(b"RESP0011\xff"),
+            Err(RdbError::InvalidHeader)
+        ));
+        assert!(matches!(
+            decode(b"REDIS0099\xff"),
+            Err(RdbError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            decode(b"REDIS0011\x00\x03foo"),
+            Err(RdbError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            decode(b"REDIS0011\x15\x01k"),
+            Err(RdbError::UnsupportedType(21))
+        ));
+        assert!(matches!(
+            decode(b"REDIS0011\xf9"),
+            Err(RdbError::UnsupportedOpcode(0xf9))
+        ));
+        // An LZF string declaring a 64-bit uncompressed length.
+        assert!(matches!(
+            decode(b"REDIS0011\x00\x01k\xc3\x02\x81\xff\xff\xff\xff\xff\xff\xff\xff\x00a"),
+            Err(RdbError::InvalidLzf)
+        ));
+
+        let mut data = with_trailer(b"REDIS0011\x00\x01k\x01v".to_vec());
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(matches!(
+            decode(&data),
+            Err(RdbError::ChecksumMismatch { .. })
+        ));
+
+        let mut data = b"REDIS0011\x00\x01k\x01v\xff".to_vec();
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(decode(&data).unwrap().entries.len(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+//! Encoding of RDB files.
+
+use super::{
+    crc64::crc64, Snapshot, MAGIC, OPCODE_AUX, OPCODE_EOF, OPCODE_EXPIRETIME_MS, OPCODE_RESIZEDB,
+    OPCODE_SELECTDB, TYPE_STRING,
+};
+
+/// Encodes a snapshot as a complete RDB file, checksum included.
+#[must_use]
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut output = MAGIC.to_vec();
+    output.extend_from_slice(format!("{:04}", snapshot.version).as_bytes());
+    for (field, value) in &snapshot.aux_fields {
+        output.push(OPCODE_AUX);
+        string(&mut output, field);
+        string(&mut output, value);
+    }
+    let mut databases = snapshot
+        .entries
+        .iter()
+        .map(|entry| entry.db)
+        .collect::<Vec<_>>();
+    databases.sort_unstable();
+    databases.dedup();
+    for db in databases {
+        let entries = snapshot.entries.iter().filter(|entry| entry.db == db);
+        let (size, expires) = entries.clone().fold((0, 0), |(size, expires), entry| {
+            (size + 1, expires + u64::from(entry.expiration.is_some()))
+        });
+        output.push(OPCODE_SELECTDB);
+        length(&mut output, db);
+        output.push(OPCODE_RESIZEDB);
+        length(&mut output, size);
+        length(&mut output, expires);
+        for entry in entries {
+            if let Some(expiration) = entry.expiration {
+                output.push(OPCODE_EXPIRETIME_MS);
+                output.extend_from_slice(&expiration.to_le_bytes());
+            }
+            output.push(TYPE_STRING);
+            string(&mut output, &entry.key);
+            string(&mut output, &entry.value);
+        }
+    }
+    output.push(OPCODE_EOF);
+    let checksum = crc64(0, &output);
+    output.extend_from_slice(&checksum.to_le_bytes());
+    output
+}
+
+fn length(output: &mut Vec<u8>, length: u64) {
+    if length < 1 << 6 {
+        output.push(length.to_le_bytes()[0]);
+    } else if length < 1 << 14 {
+        let [high, low] = u16::try_from(length).unwrap_or_default().to_be_bytes();
+        output.extend_from_slice(&[0x40 | high, low]);
+    } else if let Ok(length) = u32::try_from(length) {
+        output.push(0x80);
+        output.extend_from_slice(&length.to_be_bytes());
+    } else {
+        output.push(0x81);
+        output.extend_from_slice(&length.to_be_bytes());
+    }
+}
+
+/// Writes a string, using the integer encodings when it is the canonical representation of
+/// a 32 bits integer, as Redis does.
+fn string(output: &mut Vec<u8>, string: &[u8]) {
+    let integer = std::str::from_utf8(string)
+        .ok()
+        .and_then(|text| text.parse::<i32>().ok().filter(|n| n.to_string() == text));
+    match integer {
+        Some(n) if i8::try_from(n).is_ok() => {
+            output.push(0xc0);
+            output.extend_from_slice(&i8::try_from(n).unwrap_or_default().to_le_bytes());
+        }
+        Some(n) if i16::try_from(n).is_ok() => {
+            output.push(0xc1);
+            output.extend_from_slice(&i16::try_from(n).unwrap_or_default().to_le_bytes());
+        }
+        Some(n) => {
+            output.push(0xc2);
+            output.extend_from_slice(&n.to_le_bytes());
+        }
+        None => {
+            length(output, u64::try_from(string.len()).unwrap_or(u64::MAX));
+            output.extend_from_slice(string);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::rdb::{decoder::decode, Entry};
+
+    #[test]
+    fn test_encode_round_trip() {
+        let entry = |db, key: &str, value: Vec<u8>, expiration| Entry {
+            db,
+            key: Bytes::from(key.to_string()),
+            value: Bytes::from(value),
+            expiration,
+        };
+        let snapshot = Snapshot {
+            version: 11,
+            aux_fields: vec![(Bytes::from("redis-ver"), Bytes::from("7.2.0"))],
+            entries: vec![
+                entry(0, "small", b"-12".to_vec(), None),
+                entry(0, "medium", b"1234".to_vec(), Some(1_700_000_000_000)),
+                entry(0, "large", b"-2147483648".to_vec(), None),
+                entry(0, "padded", b"007".to_vec(), None),
+                entry(0, "long", vec![b'x'; 20_000], None),
+                entry(3, "other", b"db".to_vec(), None),
+            ],
+            skipped: 0,
+        };
+        let data = encode(&snapshot);
+        assert_eq!(decode(&data).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_encode_empty_snapshot() {
+        let snapshot = Snapshot {
+            version: 11,
+            ..Snapshot::default()
+        };
+        let data = encode(&snapshot);
+        assert_eq!(&data[..10], b"REDIS0011\xff");
+        assert_eq!(data.len(), 18);
+        assert_eq!(decode(&data).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_encode_integers() {
+        let mut output = vec![];
+        string(&mut output, b"5");
+        string(&mut output, b"300");
+        string(&mut output, b"70000");
+        string(&mut output, b"+5");
+        assert_eq!(
+            output,
+            b"\xc0\x05\xc1\x2c\x01\xc2\x70\x11\x01\x00\x02+5".to_vec()
+        );
+    }
+}
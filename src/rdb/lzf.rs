@@ -0,0 +1,86 @@
+//! Decompression of the LZF compressed strings found in RDB files.
+
+/// The most bytes a single input byte can decompress to: a back reference of 3 bytes
+/// produces up to 264 bytes.
+const MAX_EXPANSION: usize = 88;
+
+/// Decompresses `input` into a buffer of `length` bytes.
+///
+/// Returns `None` if the data is corrupt or does not decompress to exactly `length` bytes.
+/// The `length` read from a file is not trusted with an allocation larger than `input` can
+/// decompress to.
+pub fn decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    if length > input.len().saturating_mul(MAX_EXPANSION) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(length);
+    let mut position = 0;
+    while position < input.len() {
+        let control = usize::from(input[position]);
+        position += 1;
+        if control < 32 {
+            // A run of `control + 1` literal bytes.
+            let literal = input.get(position..=position + control)?;
+            output.extend_from_slice(literal);
+            position += control + 1;
+        } else {
+            // A back reference of `len + 2` bytes, `offset + 1` bytes behind.
+            let mut len = control >> 5;
+            if len == 7 {
+                len += usize::from(*input.get(position)?);
+                position += 1;
+            }
+            let offset = ((control & 0x1f) << 8) + usize::from(*input.get(position)?) + 1;
+            position += 1;
+            let start = output.len().checked_sub(offset)?;
+            // The reference may overlap the bytes it produces, so copy one byte at a time.
+            for index in start..start + len + 2 {
+                output.push(output[index]);
+            }
+        }
+        if output.len() > length {
+            return None;
+        }
+    }
+    (output.len() == length).then_some(output)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_literals() {
+        assert_eq!(decompress(b"\x02abc", 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_decompress_back_reference() {
+        // "abc" then a 6 bytes reference 3 bytes behind, overlapping its own output.
+        assert_eq!(decompress(b"\x02abc\x80\x02", 9).unwrap(), b"abcabcabc");
+        // A long reference: `len` 7 plus the extra byte 3, 12 bytes.
+        assert_eq!(
+            decompress(b"\x00a\xe0\x03\x00", 13).unwrap(),
+            b"aaaaaaaaaaaaa"
+        );
+    }
+
+    #[test]
+    fn test_decompress_corrupt() {
+        assert!(decompress(b"\x05ab", 6).is_none());
+        assert!(decompress(b"\x20\x05", 3).is_none());
+        assert!(decompress(b"\x02abc", 4).is_none());
+    }
+
+    #[test]
+    fn test_decompress_huge_length() {
+        assert!(decompress(b"\x02abc", usize::MAX).is_none());
+        assert!(decompress(b"\x00a\xe0\xff\x00", 5 * MAX_EXPANSION + 1).is_none());
+        // The longest reference still decompresses.
+        assert_eq!(
+            decompress(b"\x00a\xe0\xff\x00", 265).unwrap(),
+            vec![b'a'; 265]
+        );
+    }
+}
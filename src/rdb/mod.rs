@@ -0,0 +1,292 @@
+//! RDB snapshots, the binary format Redis persists its dataset in.
+//!
+//! A file starts with the `REDIS` magic and a four digit version, followed by opcodes
+//! (auxiliary fields, database selection, expirations) and key/value pairs, and ends with
+//! `0xFF` and a CRC64 of everything before it.
+
+pub mod crc64;
+pub mod decoder;
+pub mod encoder;
+mod lzf;
+
+use std::{fmt::Display, path::Path};
+
+use bytes::Bytes;
+
+use crate::{
+    redis_info::REDIS_VERSION,
+    server_config::{Offset, ReplicationId},
+    store::{instant_from_unix_millis, unix_time_millis},
+    Keyspace, RedisValue,
+};
+
+pub const MAGIC: &[u8] = b"REDIS";
+/// Version of the files written by this server, the one of Redis 7.2.
+pub const RDB_VERSION: u32 = 11;
+
+pub const OPCODE_SLOT_INFO: u8 = 0xf4;
+pub const OPCODE_FUNCTION2: u8 = 0xf5;
+pub const OPCODE_FUNCTION_PRE_GA: u8 = 0xf6;
+pub const OPCODE_FREQ: u8 = 0xf7;
+pub const OPCODE_IDLE: u8 = 0xf8;
+pub const OPCODE_MODULE_AUX: u8 = 0xf9;
+pub const OPCODE_AUX: u8 = 0xfa;
+pub const OPCODE_RESIZEDB: u8 = 0xfb;
+pub const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+pub const OPCODE_EXPIRETIME: u8 = 0xfd;
+pub const OPCODE_SELECTDB: u8 = 0xfe;
+pub const OPCODE_EOF: u8 = 0xff;
+
+pub const TYPE_STRING: u8 = 0;
+
+/// A key read from a snapshot.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub db: u64,
+    pub key: Bytes,
+    pub value: Bytes,
+    /// Unix time in milliseconds at which the key expires.
+    pub expiration: Option<i64>,
+}
+
+/// The content of an RDB file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub version: u32,
+    pub aux_fields: Vec<(Bytes, Bytes)>,
+    pub entries: Vec<Entry>,
+    /// Number of keys holding a type other than strings, which this server cannot store.
+    pub skipped: usize,
+}
+impl Snapshot {
+    /// Captures the keys of `keyspace` that have not expired.
+    ///
+    /// Values are reference counted, so this only copies pointers and the snapshot can be
+    /// encoded after the keyspace is unlocked.
+    #[must_use]
+    pub fn from_keyspace(keyspace: &Keyspace) -> Self {
+        let aux_field =
+            |field: &str, value: String| (Bytes::from(field.to_string()), Bytes::from(value));
+        Self {
+            version: RDB_VERSION,
+            aux_fields: vec![
+                aux_field("redis-ver", REDIS_VERSION.to_string()),
+                aux_field("redis-bits", (usize::BITS).to_string()),
+                aux_field("ctime", (unix_time_millis() / 1000).to_string()),
+                aux_field("aof-base", "0".to_string()),
+            ],
+            entries: keyspace
+                .iter()
+                .map(|(key, value)| Entry {
+                    db: 0,
+                    key: key.clone(),
+                    value: value.value().clone(),
+                    expiration: value.expiration_unix_millis(),
+                })
+                .collect(),
+            skipped: 0,
+        }
+    }
+
+    /// Records the replication ID and offset the keyspace was captured at, so that the
+    /// instance loading it can resume replication from there.
+    pub fn set_replication(&mut self, replid: &ReplicationId, offset: &Offset) {
+        for (field, value) in [
+            ("repl-stream-db", "0".to_string()),
+            ("repl-id", replid.to_string()),
+            ("repl-offset", offset.to_string()),
+        ] {
+            self.aux_fields
+                .push((Bytes::from(field), Bytes::from(value)));
+        }
+    }
+
+    /// Returns the replication ID and offset recorded by `set_replication`, if any.
+    #[must_use]
+    pub fn replication(&self) -> Option<(ReplicationId, Offset)> {
+        let replid = String::from_utf8(self.aux("repl-id")?.to_vec()).ok()?;
+        let offset = std::str::from_utf8(self.aux("repl-offset")?)
+            .ok()?
+            .parse()
+            .ok()?;
+        Some((
+            ReplicationId::parse(Some(replid)),
+            Offset::parse(Some(offset)),
+        ))
+    }
+
+    /// Returns the value of an auxiliary field, such as `redis-ver`.
+    #[must_use]
+    pub fn aux(&self, name: &str) -> Option<&Bytes> {
+        self.aux_fields
+            .iter()
+            .find(|(field, _)| field.as_ref() == name.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the value of an auxiliary field, replacing the previous one if any.
+    pub fn set_aux(&mut self, name: &str, value: &str) {
+        let value = Bytes::from(value.to_string());
+        match self
+            .aux_fields
+            .iter_mut()
+            .find(|(field, _)| field.as_ref() == name.as_bytes())
+        {
+            Some((_, previous)) => *previous = value,
+            None => self.aux_fields.push((Bytes::from(name.to_string()), value)),
+        }
+    }
+
+    /// Inserts the keys of database 0 that have not expired yet into `keyspace`, and returns
+    /// how many were inserted.
+    pub fn load_into(self, keyspace: &mut Keyspace) -> usize {
+        let mut loaded = 0;
+        for entry in self.entries.into_iter().filter(|entry| entry.db == 0) {
+            let expiration = match entry.expiration {
+                None => None,
+                Some(expiration) => match instant_from_unix_millis(expiration) {
+                    None => continue,
+                    expiration => expiration,
+                },
+            };
+            keyspace.insert(entry.key, RedisValue::expiring_at(entry.value, expiration));
+            loaded += 1;
+        }
+        loaded
+    }
+}
+
+/// Reads the snapshot at `path`, `None` if there is no file.
+///
+/// # Errors
+///
+/// Returns an `RdbError` if the file cannot be read or is not a valid RDB file.
+pub fn load_file(path: &Path) -> Result<Option<Snapshot>, RdbError> {
+    match std::fs::read(path) {
+        Ok(data) => decoder::decode(&data).map(Some),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(RdbError::Io(error)),
+    }
+}
+
+/// Builds the keyspace the server starts with from the snapshot at `path`, along with the
+/// replication ID and offset the snapshot recorded.
+///
+/// # Panics
+///
+/// Panics if the file exists but cannot be loaded, as Redis refuses to start on a corrupt
+/// snapshot rather than silently dropping the dataset.
+#[must_use]
+pub fn load_keyspace(path: &Path) -> (Keyspace, Option<(ReplicationId, Offset)>) {
+    let mut keyspace = Keyspace::new();
+    let mut replication = None;
+    match load_file(path) {
+        Ok(None) => println!("No RDB file at {}, starting empty", path.display()),
+        Ok(Some(snapshot)) => {
+            replication = snapshot.replication();
+            if let Some(version) = snapshot.aux("redis-ver") {
+                println!(
+                    "Loading RDB produced by version {}",
+                    String::from_utf8_lossy(version)
+                );
+            }
+            let skipped = snapshot.skipped;
+            let loaded = snapshot.load_into(&mut keyspace);
+            keyspace.mark_saved(keyspace.dirty());
+            println!("Loaded {loaded} keys from {}", path.display());
+            if skipped > 0 {
+                eprintln!("Skipped {skipped} keys of unsupported types");
+            }
+        }
+        Err(error) => panic!("Cannot load RDB file {}: {error}", path.display()),
+    }
+    (keyspace, replication)
+}
+
+#[derive(Debug)]
+pub enum RdbError {
+    Io(std::io::Error),
+    /// The file does not start with `REDIS` and a version number.
+    InvalidHeader,
+    UnsupportedVersion(u32),
+    /// The file ends in the middle of a record.
+    UnexpectedEnd,
+    /// A string uses an unknown special encoding.
+    InvalidEncoding(u8),
+    /// An LZF compressed string cannot be decompressed.
+    InvalidLzf,
+    UnsupportedOpcode(u8),
+    UnsupportedType(u8),
+    ChecksumMismatch {
+        expected: u64,
+        computed: u64,
+    },
+}
+impl From<std::io::Error> for RdbError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+impl Display for RdbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::InvalidHeader => write!(f, "Wrong signature trying to load DB from file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Can't handle RDB format version {version}")
+            }
+            Self::UnexpectedEnd => write!(f, "Unexpected EOF reading RDB file"),
+            Self::InvalidEncoding(encoding) => {
+                write!(f, "Unknown RDB string encoding type {encoding}")
+            }
+            Self::InvalidLzf => write!(f, "Invalid LZF compressed string"),
+            Self::UnsupportedOpcode(opcode) => write!(f, "Unsupported RDB opcode {opcode:#04x}"),
+            Self::UnsupportedType(value_type) => {
+                write!(f, "Unsupported RDB object type {value_type}")
+            }
+            Self::ChecksumMismatch { expected, computed } => write!(
+                f,
+                "Wrong RDB checksum expected: ({expected:#018x}) got: ({computed:#018x})"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::atomic_file;
+
+    #[test]
+    fn test_write_and_load_file() {
+        let dir = std::env::temp_dir().join(format!("rdb-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(
+            Bytes::from("plain"),
+            RedisValue::new(Bytes::from("v"), None),
+        );
+        keyspace.insert(
+            Bytes::from("volatile"),
+            RedisValue::new(Bytes::from("42"), Some(Duration::from_secs(90))),
+        );
+        let mut snapshot = Snapshot::from_keyspace(&keyspace);
+        let replid = ReplicationId::random();
+        snapshot.set_replication(&replid, &Offset::parse(Some(1234)));
+        atomic_file::write(&path, &encoder::encode(&snapshot)).unwrap();
+
+        let (mut loaded, replication) = load_keyspace(&path);
+        assert_eq!(replication, Some((replid, Offset::parse(Some(1234)))));
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.dirty(), 0);
+        assert_eq!(loaded.get(b"volatile").unwrap().value(), "42");
+        assert!(loaded.get(b"volatile").unwrap().expiration().is_some());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
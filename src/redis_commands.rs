@@ -1,125 +1,736 @@
-use crate::resp::{Array, BulkString, SimpleString, ToRedisBytes, Type};
+use crate::command_table::{self, CommandDescriptor};
+use crate::replication;
+use crate::resp::{
+    decoder::ProtocolError, Array, BulkString, SimpleError, SimpleString, ToRedisBytes, Type,
+};
 use crate::server_config::{Offset, ReplicationId};
+use crate::{Host, Port, ReplicaOf};
+use bytes::Bytes;
 use std::fmt::Display;
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum RedisCommands {
     Ping,
     Echo(Vec<BulkString>),
-    Get(String),
-    Set((String, String, Option<Duration>)),
+    Get(Bytes),
+    /// `SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    /// PXAT unix-time-milliseconds | KEEPTTL]`
+    Set(Bytes, Bytes, SetOptions),
     Info(String),
     Replconf(String, String),
     Psync(ReplicationId, Offset),
     FullResync(ReplicationId, Offset),
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+    Hello(Option<i64>, Option<(Bytes, Bytes)>, Option<Bytes>),
+    Command(CommandQuery),
+    /// `EXPIRE`, `PEXPIRE`, `EXPIREAT` and `PEXPIREAT` with their `NX | XX | GT | LT` flags.
+    Expire(Bytes, ExpireTime, Vec<ExpireCondition>),
+    /// `TTL` and `PTTL`
+    Ttl(Bytes, TimeUnit),
+    /// `EXPIRETIME` and `PEXPIRETIME`
+    ExpireTime(Bytes, TimeUnit),
+    Persist(Bytes),
+    /// `DEL key [key ...]`
+    Del(Vec<Bytes>),
+    Save,
+    /// `BGSAVE [SCHEDULE]`
+    Bgsave(bool),
+    Bgrewriteaof,
+    Lastsave,
+    /// `WAIT numreplicas timeout`, a timeout of `None` blocking until enough replicas
+    /// acknowledged.
+    Wait(i64, Option<Duration>),
+    /// `REPLICAOF host port` and its alias `SLAVEOF`, `None` for `REPLICAOF NO ONE`.
+    Replicaof(Option<ReplicaOf>),
+    Config(ConfigCommand),
 }
+
+/// Expiration given to a key by the `EXPIRE` family, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireTime {
+    /// `EXPIRE` and `PEXPIRE`: relative to now, possibly negative.
+    In(i64),
+    /// `EXPIREAT` and `PEXPIREAT`: as a Unix time, possibly in the past.
+    At(i64),
+}
+
+/// Condition that must hold for the `EXPIRE` family to change the expiration of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    /// `NX`: the key has no expiration.
+    Nx,
+    /// `XX`: the key has an expiration.
+    Xx,
+    /// `GT`: the new expiration is after the current one, a key without expiration never
+    /// matches.
+    Gt,
+    /// `LT`: the new expiration is before the current one, a key without expiration always
+    /// matches.
+    Lt,
+}
+
+/// Unit in which times are read or reported, for commands coming in both flavours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+/// Options of `SET`, each group being mutually exclusive.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SetOptions {
+    pub condition: Option<SetCondition>,
+    pub expiration: Option<SetExpiration>,
+    /// Whether the previous value is returned instead of `OK`.
+    pub get: bool,
+}
+
+impl SetOptions {
+    /// Returns the options as given to `SET`, expirations in milliseconds.
+    fn arguments(&self) -> Vec<Bytes> {
+        let mut arguments = vec![];
+        match self.condition {
+            Some(SetCondition::NotExists) => arguments.push(word("NX")),
+            Some(SetCondition::Exists) => arguments.push(word("XX")),
+            None => {}
+        }
+        if self.get {
+            arguments.push(word("GET"));
+        }
+        match self.expiration {
+            Some(SetExpiration::In(duration)) => {
+                arguments.extend([word("PX"), text(&duration.as_millis())]);
+            }
+            Some(SetExpiration::At(time)) => {
+                let unix = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                arguments.extend([word("PXAT"), text(&unix.as_millis())]);
+            }
+            Some(SetExpiration::KeepTtl) => arguments.push(word("KEEPTTL")),
+            None => {}
+        }
+        arguments
+    }
+}
+
+/// When `SET` is allowed to write the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// `NX`: only if the key does not exist.
+    NotExists,
+    /// `XX`: only if the key already exists.
+    Exists,
+}
+
+/// The expiration `SET` gives to the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetExpiration {
+    /// `EX` and `PX`: the key expires after this duration.
+    In(Duration),
+    /// `EXAT` and `PXAT`: the key expires at this point in time.
+    At(SystemTime),
+    /// `KEEPTTL`: the key keeps the expiration it had before being overwritten.
+    KeepTtl,
+}
+
+/// What `COMMAND` is asked to report about the command table.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandQuery {
+    /// `COMMAND`
+    All,
+    /// `COMMAND COUNT`
+    Count,
+    /// `COMMAND INFO [command-name ...]`, every command when no name is given.
+    Info(Vec<String>),
+    /// `COMMAND DOCS [command-name ...]`, every command when no name is given.
+    Docs(Vec<String>),
+}
+
+impl CommandQuery {
+    /// Returns the arguments following `COMMAND`.
+    fn arguments(&self) -> Vec<Bytes> {
+        let (subcommand, names) = match self {
+            Self::All => return vec![],
+            Self::Count => return vec![word("COUNT")],
+            Self::Info(names) => ("INFO", names),
+            Self::Docs(names) => ("DOCS", names),
+        };
+        std::iter::once(word(subcommand))
+            .chain(names.iter().map(|name| text(name)))
+            .collect()
+    }
+}
+
+/// The subcommands of `CONFIG`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// `CONFIG GET parameter [parameter ...]`, each parameter being a glob-style pattern.
+    Get(Vec<String>),
+    /// `CONFIG SET parameter value [parameter value ...]`
+    Set(Vec<(String, String)>),
+    /// `CONFIG REWRITE`
+    Rewrite,
+    /// `CONFIG RESETSTAT`
+    Resetstat,
+}
+impl ConfigCommand {
+    /// Returns the arguments following `CONFIG`.
+    fn arguments(&self) -> Vec<Bytes> {
+        match self {
+            Self::Get(patterns) => std::iter::once(word("GET"))
+                .chain(patterns.iter().map(|pattern| text(pattern)))
+                .collect(),
+            Self::Set(changes) => std::iter::once(word("SET"))
+                .chain(
+                    changes
+                        .iter()
+                        .flat_map(|(name, value)| [text(name), text(value)]),
+                )
+                .collect(),
+            Self::Rewrite => vec![word("REWRITE")],
+            Self::Resetstat => vec![word("RESETSTAT")],
+        }
+    }
+}
+
+/// A keyword of a command, as an argument.
+const fn word(word: &'static str) -> Bytes {
+    Bytes::from_static(word.as_bytes())
+}
+
+/// A value of a command, as an argument.
+fn text(value: &dyn Display) -> Bytes {
+    Bytes::from(value.to_string())
+}
+
 impl ToRedisBytes for RedisCommands {
     fn to_redis_bytes(&self) -> Vec<u8> {
-        match self {
-            Self::Ping => format!("*1\r\n${}\r\n{}\r\n", "PING".len(), "PING")
+        if let Self::FullResync(replication_id, offset) = self {
+            return format!("+FULLRESYNC {replication_id} {offset}\r\n")
                 .as_bytes()
-                .to_vec(),
-            Self::Psync(replication_id, offset) => format!(
-                "*3\r\n$5\r\nPSYNC\r\n${}\r\n{replication_id}\r\n${}\r\n{offset}\r\n",
-                replication_id.len(),
-                offset.len()
-            )
-            .as_bytes()
-            .to_vec(),
-            Self::FullResync(replication_id, offset) => {
-                format!("+FULLRESYNC {replication_id} {offset}\r\n")
-                    .as_bytes()
-                    .to_vec()
-            }
-            Self::Echo(_) => todo!(),
-            Self::Get(_) => todo!(),
-            Self::Set(_) => todo!(),
-            Self::Info(_) => todo!(),
-            Self::Replconf(_, _) => todo!(),
+                .to_vec();
         }
+        let arguments = self.arguments();
+        let arguments = arguments.iter().map(Bytes::as_ref).collect::<Vec<_>>();
+        replication::encode_command(&arguments).to_vec()
     }
 }
 impl RedisCommands {
-    pub fn parse(command: &Type) -> Self {
+    /// Parses a command sent by a client.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CommandError` if the command is unknown or its arguments are invalid.
+    pub fn parse(command: &Type) -> Result<Self, CommandError> {
         match command {
             Type::Array(array) => Self::handle_array(array),
-            Type::BulkString(bulkstring) => Self::handle_bulkstring(bulkstring),
             Type::SimpleString(simplestring) => Self::handle_simplestring(simplestring),
+            _ => Err(ProtocolError::ExpectedBulkStrings.into()),
         }
     }
 
-    fn handle_array(array: &Array) -> Self {
-        let bulkstrings = array.bulkstrings();
-
-        let command = bulkstrings.first().expect("No command found");
-        match command.to_string().to_lowercase().as_str() {
-            "ping" => Self::Ping,
-            "echo" => Self::Echo(bulkstrings.get(1..).unwrap_or_default().to_vec()),
-            "get" => Self::Get(bulkstrings.get(1).expect("No key found").to_string()),
-            "set" => {
-                let key = bulkstrings.get(1).expect("Set has no key").to_string();
-                let value = bulkstrings.get(2).expect("Set has no value").to_string();
-                let expiration = if bulkstrings.get(3).is_some() {
-                    Some(Duration::from_millis(
-                        bulkstrings
-                            .get(4)
-                            .expect("Set has expiration parameter but no expiration value")
-                            .to_string()
-                            .parse::<u64>()
-                            .expect("Set has expiration parameter and value but value cannot be parsed as signed integer"),
-                    ))
-                } else {
-                    None
-                };
-                Self::Set((key, value, expiration))
+    fn handle_array(array: &Array) -> Result<Self, CommandError> {
+        let bulkstrings = array
+            .to_bulkstrings()
+            .ok_or(ProtocolError::ExpectedBulkStrings)?;
+        let Some((command, arguments)) = bulkstrings.split_first() else {
+            return Err(CommandError::UnknownCommand(String::new(), vec![]));
+        };
+        let name = command.to_string().to_lowercase();
+        let Some(descriptor) = command_table::lookup(&name) else {
+            return Err(CommandError::UnknownCommand(name, arguments.to_vec()));
+        };
+        if !descriptor.accepts(bulkstrings.len()) {
+            return Err(CommandError::WrongArity(name));
+        }
+        (descriptor.parse)(arguments)
+    }
+
+    pub fn parse_set(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        Ok(Self::Set(
+            key(arguments, "set")?,
+            argument(arguments, 1, "set")?.data().clone(),
+            Self::parse_set_options(&arguments[2..])?,
+        ))
+    }
+    pub fn parse_replconf(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        Ok(Self::Replconf(
+            argument(arguments, 0, "replconf")?.to_string(),
+            argument(arguments, 1, "replconf")?.to_string(),
+        ))
+    }
+    pub fn parse_psync(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        let replication_id =
+            ReplicationId::parse(Some(argument(arguments, 0, "psync")?.to_string()));
+        let replication_offset =
+            Offset::parse(Some(parse_integer(argument(arguments, 1, "psync")?)?));
+        Ok(Self::Psync(replication_id, replication_offset))
+    }
+    pub fn parse_bgsave(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        match arguments {
+            [] => Ok(Self::Bgsave(false)),
+            [option] if option.to_string().eq_ignore_ascii_case("schedule") => {
+                Ok(Self::Bgsave(true))
             }
-            "info" => Self::Info(
-                bulkstrings
-                    .get(1)
-                    .expect("Info requested but no category given")
-                    .to_string(),
-            ),
-            "replconf" => {
-                let command = bulkstrings
-                    .get(1)
-                    .expect("Replfconf has no command")
-                    .to_string();
-                let value = bulkstrings
-                    .get(2)
-                    .expect("Replconf has no value")
-                    .to_string();
-                Self::Replconf(command, value)
-            }
-            "psync" => {
-                let replication_id = ReplicationId::parse(Some(
-                    bulkstrings
-                        .get(1)
-                        .expect("Psync has no replication ID")
-                        .to_string(),
-                ));
-                let replication_offset = Offset::parse(Some(
-                    bulkstrings
-                        .get(2)
-                        .expect("Psync command has no replication offset")
-                        .to_string()
-                        .parse::<i8>()
-                        .expect("Psync has replication offset but cannot be parsed as integer"),
-                ));
-                Self::Psync(replication_id, replication_offset)
-            }
-            command => unimplemented!("Command '{command}' cannot be parsed"),
-        }
-    }
-    fn handle_simplestring(simplestring: &SimpleString) -> Self {
+            _ => Err(CommandError::Syntax),
+        }
+    }
+    pub fn parse_wait(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        let numreplicas = parse_integer(argument(arguments, 0, "wait")?)?;
+        let timeout = match parse_integer::<i64>(argument(arguments, 1, "wait")?)? {
+            0 => None,
+            timeout => Some(Duration::from_millis(
+                u64::try_from(timeout).map_err(|_| CommandError::NegativeTimeout)?,
+            )),
+        };
+        Ok(Self::Wait(numreplicas, timeout))
+    }
+    pub fn parse_replicaof(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        let host = argument(arguments, 0, "replicaof")?.to_string();
+        let port = argument(arguments, 1, "replicaof")?.to_string();
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(Self::Replicaof(None));
+        }
+        let port = port
+            .parse::<Port>()
+            .map_err(|_| CommandError::InvalidMaster("port"))?;
+        let host = host
+            .parse::<Host>()
+            .map_err(|_| CommandError::InvalidMaster("host"))?;
+        Ok(Self::Replicaof(Some(ReplicaOf::new(host, port))))
+    }
+    pub fn parse_command(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        Ok(Self::Command(Self::parse_command_query(arguments)?))
+    }
+    pub fn parse_config(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        Ok(Self::Config(Self::parse_config_command(arguments)?))
+    }
+    fn parse_set_options(arguments: &[BulkString]) -> Result<SetOptions, CommandError> {
+        let mut options = SetOptions::default();
+        let mut arguments = arguments.iter();
+        while let Some(option) = arguments.next() {
+            let option = option.to_string().to_lowercase();
+            match option.as_str() {
+                "nx" | "xx" => {
+                    let condition = if option == "nx" {
+                        SetCondition::NotExists
+                    } else {
+                        SetCondition::Exists
+                    };
+                    if options
+                        .condition
+                        .is_some_and(|current| current != condition)
+                    {
+                        return Err(CommandError::Syntax);
+                    }
+                    options.condition = Some(condition);
+                }
+                "get" => options.get = true,
+                "keepttl" | "ex" | "px" | "exat" | "pxat" => {
+                    if options.expiration.is_some() {
+                        return Err(CommandError::Syntax);
+                    }
+                    options.expiration = Some(if option == "keepttl" {
+                        SetExpiration::KeepTtl
+                    } else {
+                        let time = arguments.next().ok_or(CommandError::Syntax)?;
+                        parse_expiration(&option, time, "set")?
+                    });
+                }
+                _ => return Err(CommandError::Syntax),
+            }
+        }
+        Ok(options)
+    }
+    pub fn parse_expire(name: &str, arguments: &[BulkString]) -> Result<Self, CommandError> {
+        let [key, time, options @ ..] = arguments else {
+            return Err(CommandError::WrongArity(name.to_string()));
+        };
+        let time: i64 = parse_integer(time)?;
+        let milliseconds = if name.starts_with('p') {
+            Some(time)
+        } else {
+            time.checked_mul(1000)
+        }
+        .ok_or_else(|| CommandError::InvalidExpireTime(name.to_string()))?;
+        let time = if name.ends_with("at") {
+            ExpireTime::At(milliseconds)
+        } else {
+            ExpireTime::In(milliseconds)
+        };
+        let mut conditions = vec![];
+        for option in options {
+            let condition = match option.to_string().to_lowercase().as_str() {
+                "nx" => ExpireCondition::Nx,
+                "xx" => ExpireCondition::Xx,
+                "gt" => ExpireCondition::Gt,
+                "lt" => ExpireCondition::Lt,
+                _ => return Err(CommandError::UnsupportedOption(option.to_string())),
+            };
+            if !conditions.contains(&condition) {
+                conditions.push(condition);
+            }
+        }
+        let has = |condition| conditions.contains(&condition);
+        if has(ExpireCondition::Nx)
+            && (has(ExpireCondition::Xx) || has(ExpireCondition::Gt) || has(ExpireCondition::Lt))
+        {
+            return Err(CommandError::IncompatibleOptions("NX and XX, GT or LT"));
+        }
+        if has(ExpireCondition::Gt) && has(ExpireCondition::Lt) {
+            return Err(CommandError::IncompatibleOptions("GT and LT"));
+        }
+        Ok(Self::Expire(key.data().clone(), time, conditions))
+    }
+    fn parse_command_query(arguments: &[BulkString]) -> Result<CommandQuery, CommandError> {
+        let Some((subcommand, names)) = arguments.split_first() else {
+            return Ok(CommandQuery::All);
+        };
+        let names = names.iter().map(ToString::to_string).collect();
+        let subcommand = subcommand.to_string();
+        match subcommand.to_lowercase().as_str() {
+            "count" if arguments.len() == 1 => Ok(CommandQuery::Count),
+            "info" => Ok(CommandQuery::Info(names)),
+            "docs" => Ok(CommandQuery::Docs(names)),
+            _ => Err(CommandError::UnknownSubcommand(
+                subcommand,
+                "COMMAND".to_string(),
+            )),
+        }
+    }
+    fn parse_config_command(arguments: &[BulkString]) -> Result<ConfigCommand, CommandError> {
+        let Some((subcommand, arguments)) = arguments.split_first() else {
+            return Err(CommandError::WrongArity("config".to_string()));
+        };
+        let subcommand = subcommand.to_string();
+        let lowercase = subcommand.to_lowercase();
+        let wrong_arity = || CommandError::WrongArity(format!("config|{lowercase}"));
+        let arguments = arguments
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        match lowercase.as_str() {
+            "get" if arguments.is_empty() => Err(wrong_arity()),
+            "get" => Ok(ConfigCommand::Get(arguments)),
+            "set" if arguments.is_empty() || arguments.len() % 2 != 0 => Err(wrong_arity()),
+            "set" => Ok(ConfigCommand::Set(
+                arguments
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+            )),
+            "rewrite" if arguments.is_empty() => Ok(ConfigCommand::Rewrite),
+            "resetstat" if arguments.is_empty() => Ok(ConfigCommand::Resetstat),
+            "rewrite" | "resetstat" => Err(wrong_arity()),
+            _ => Err(CommandError::UnknownSubcommand(
+                subcommand,
+                "CONFIG".to_string(),
+            )),
+        }
+    }
+    pub fn parse_hello(arguments: &[BulkString]) -> Result<Self, CommandError> {
+        let mut arguments = arguments.iter();
+        let protover = arguments.next().map(parse_integer).transpose()?;
+        let mut auth = None;
+        let mut setname = None;
+        while let Some(option) = arguments.next() {
+            match option.to_string().to_lowercase().as_str() {
+                "auth" => {
+                    let (Some(username), Some(password)) = (arguments.next(), arguments.next())
+                    else {
+                        return Err(CommandError::Syntax);
+                    };
+                    auth = Some((username.data().clone(), password.data().clone()));
+                }
+                "setname" => {
+                    let name = arguments.next().ok_or(CommandError::Syntax)?;
+                    setname = Some(name.data().clone());
+                }
+                _ => return Err(CommandError::Syntax),
+            }
+        }
+        Ok(Self::Hello(protover, auth, setname))
+    }
+    fn handle_simplestring(simplestring: &SimpleString) -> Result<Self, CommandError> {
         let command = simplestring.data().trim().to_lowercase();
         match command.as_str() {
-            "ping" => Self::Ping,
-            _ => unimplemented!("Simplestring command is unimplemented"),
+            "ping" => Ok(Self::Ping),
+            _ => Err(CommandError::UnknownCommand(command, vec![])),
         }
     }
-    fn handle_bulkstring(_bulkstring: &BulkString) -> Self {
-        unimplemented!("Handle bulkstring in RedisCommand")
+
+    /// Returns the entry of the command table describing this command, `None` for replies
+    /// such as `FULLRESYNC` that are not commands a client can send.
+    pub fn descriptor(&self) -> Option<&'static CommandDescriptor> {
+        let name = match self {
+            Self::Ping => "ping",
+            Self::Echo(_) => "echo",
+            Self::Get(_) => "get",
+            Self::Set(..) => "set",
+            Self::Info(_) => "info",
+            Self::Replconf(_, _) => "replconf",
+            Self::Psync(_, _) => "psync",
+            Self::Hello(_, _, _) => "hello",
+            Self::Command(_) => "command",
+            Self::Expire(..) => "expire",
+            Self::Ttl(..) => "ttl",
+            Self::ExpireTime(..) => "expiretime",
+            Self::Persist(_) => "persist",
+            Self::Del(_) => "del",
+            Self::Save => "save",
+            Self::Bgsave(_) => "bgsave",
+            Self::Bgrewriteaof => "bgrewriteaof",
+            Self::Lastsave => "lastsave",
+            Self::Wait(..) => "wait",
+            Self::Replicaof(_) => "replicaof",
+            Self::Config(_) => "config",
+            Self::FullResync(_, _) => return None,
+        };
+        command_table::lookup(name)
+    }
+
+    /// Returns the arguments of the command as a client would send them, times being
+    /// given in milliseconds. `FULLRESYNC` is a reply and has none.
+    fn arguments(&self) -> Vec<Bytes> {
+        let time_unit = |unit: &TimeUnit, seconds, milliseconds| match unit {
+            TimeUnit::Seconds => word(seconds),
+            TimeUnit::Milliseconds => word(milliseconds),
+        };
+        let mut arguments = vec![];
+        match self {
+            Self::Ping => arguments.push(word("PING")),
+            Self::Echo(messages) => {
+                arguments.push(word("ECHO"));
+                arguments.extend(messages.iter().map(|message| message.data().clone()));
+            }
+            Self::Get(key) => arguments.extend([word("GET"), key.clone()]),
+            Self::Set(key, value, options) => {
+                arguments.extend([word("SET"), key.clone(), value.clone()]);
+                arguments.extend(options.arguments());
+            }
+            Self::Info(section) => arguments.extend([word("INFO"), text(section)]),
+            Self::Replconf(option, value) => {
+                arguments.extend([word("REPLCONF"), text(option), text(value)]);
+            }
+            Self::Psync(replication_id, offset) => {
+                arguments.extend([word("PSYNC"), text(replication_id), text(offset)]);
+            }
+            Self::FullResync(_, _) => {}
+            Self::Hello(protover, auth, setname) => {
+                arguments.push(word("HELLO"));
+                arguments.extend(protover.map(|protover| text(&protover)));
+                if let Some((username, password)) = auth {
+                    arguments.extend([word("AUTH"), username.clone(), password.clone()]);
+                }
+                if let Some(name) = setname {
+                    arguments.extend([word("SETNAME"), name.clone()]);
+                }
+            }
+            Self::Command(query) => {
+                arguments.push(word("COMMAND"));
+                arguments.extend(query.arguments());
+            }
+            Self::Expire(key, time, conditions) => {
+                let (name, milliseconds) = match time {
+                    ExpireTime::In(milliseconds) => ("PEXPIRE", milliseconds),
+                    ExpireTime::At(milliseconds) => ("PEXPIREAT", milliseconds),
+                };
+                arguments.extend([word(name), key.clone(), text(milliseconds)]);
+                arguments.extend(conditions.iter().map(|condition| match condition {
+                    ExpireCondition::Nx => word("NX"),
+                    ExpireCondition::Xx => word("XX"),
+                    ExpireCondition::Gt => word("GT"),
+                    ExpireCondition::Lt => word("LT"),
+                }));
+            }
+            Self::Ttl(key, unit) => arguments.extend([time_unit(unit, "TTL", "PTTL"), key.clone()]),
+            Self::ExpireTime(key, unit) => {
+                arguments.extend([time_unit(unit, "EXPIRETIME", "PEXPIRETIME"), key.clone()]);
+            }
+            Self::Persist(key) => arguments.extend([word("PERSIST"), key.clone()]),
+            Self::Del(keys) => {
+                arguments.push(word("DEL"));
+                arguments.extend(keys.iter().cloned());
+            }
+            Self::Save => arguments.push(word("SAVE")),
+            Self::Bgsave(schedule) => {
+                arguments.push(word("BGSAVE"));
+                if *schedule {
+                    arguments.push(word("SCHEDULE"));
+                }
+            }
+            Self::Bgrewriteaof => arguments.push(word("BGREWRITEAOF")),
+            Self::Lastsave => arguments.push(word("LASTSAVE")),
+            Self::Wait(numreplicas, timeout) => arguments.extend([
+                word("WAIT"),
+                text(numreplicas),
+                text(&timeout.map_or(0, |timeout| timeout.as_millis())),
+            ]),
+            Self::Replicaof(Some(master)) => arguments.extend([
+                word("REPLICAOF"),
+                text(master.host_address()),
+                text(master.port()),
+            ]),
+            Self::Replicaof(None) => arguments.extend([word("REPLICAOF"), word("NO"), word("ONE")]),
+            Self::Config(subcommand) => {
+                arguments.push(word("CONFIG"));
+                arguments.extend(subcommand.arguments());
+            }
+        }
+        arguments
+    }
+
+    /// Returns whether the command may modify the dataset.
+    pub fn is_write(&self) -> bool {
+        self.descriptor().is_some_and(CommandDescriptor::is_write)
+    }
+}
+
+/// Returns the argument at `index` of `command`, the name excluded.
+fn argument<'a>(
+    arguments: &'a [BulkString],
+    index: usize,
+    command: &str,
+) -> Result<&'a BulkString, CommandError> {
+    arguments
+        .get(index)
+        .ok_or_else(|| CommandError::WrongArity(command.to_string()))
+}
+
+/// Returns the key given as the first argument of `command`.
+pub fn key(arguments: &[BulkString], command: &str) -> Result<Bytes, CommandError> {
+    Ok(argument(arguments, 0, command)?.data().clone())
+}
+
+fn parse_integer<T: FromStr>(argument: &BulkString) -> Result<T, CommandError> {
+    argument
+        .to_string()
+        .parse::<T>()
+        .map_err(|_| CommandError::NotAnInteger)
+}
+
+/// Parses the time given to an `EX`, `PX`, `EXAT` or `PXAT` option of `command`.
+fn parse_expiration(
+    option: &str,
+    time: &BulkString,
+    command: &str,
+) -> Result<SetExpiration, CommandError> {
+    let invalid = || CommandError::InvalidExpireTime(command.to_string());
+    let time = u64::try_from(parse_integer::<i64>(time)?)
+        .ok()
+        .filter(|&time| time > 0)
+        .ok_or_else(invalid)?;
+    let duration = match option {
+        "ex" | "exat" => Duration::from_secs(time),
+        _ => Duration::from_millis(time),
+    };
+    if duration.as_millis() > i64::MAX.unsigned_abs().into() {
+        return Err(invalid());
+    }
+    match option {
+        "ex" | "px" => Ok(SetExpiration::In(duration)),
+        _ => UNIX_EPOCH
+            .checked_add(duration)
+            .map(SetExpiration::At)
+            .ok_or_else(invalid),
+    }
+}
+
+/// Why a command sent by a client cannot be executed.
+///
+/// Each error is answered with the standard Redis error reply and leaves the connection open.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The command could not be read out of the frame.
+    Protocol(ProtocolError),
+    /// No command with this name exists, its arguments are kept for the error message.
+    UnknownCommand(String, Vec<BulkString>),
+    /// The command was called with too few or too many arguments.
+    WrongArity(String),
+    /// The subcommand, first field, does not exist for the container command, second field.
+    UnknownSubcommand(String, String),
+    /// The command was called against a key holding another type of value.
+    WrongType,
+    Syntax,
+    /// The expiration given to the command, the field, is zero, negative or too large.
+    InvalidExpireTime(String),
+    /// The command does not know this option.
+    UnsupportedOption(String),
+    /// Options that cannot be combined were given together.
+    IncompatibleOptions(&'static str),
+    NotAnInteger,
+    NegativeTimeout,
+    /// `HELLO` was asked for a protocol version other than 2 or 3.
+    UnsupportedProtocol,
+    /// `AUTH` credentials are not valid.
+    WrongPass,
+    /// `REPLICAOF` was given a host or port, the field, that cannot be parsed.
+    InvalidMaster(&'static str),
+}
+impl CommandError {
+    /// Returns the error code sent as the first word of the error reply.
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::WrongType => "WRONGTYPE",
+            Self::UnsupportedProtocol => "NOPROTO",
+            Self::WrongPass => "WRONGPASS",
+            Self::Protocol(_)
+            | Self::UnknownCommand(_, _)
+            | Self::WrongArity(_)
+            | Self::UnknownSubcommand(_, _)
+            | Self::Syntax
+            | Self::InvalidExpireTime(_)
+            | Self::UnsupportedOption(_)
+            | Self::IncompatibleOptions(_)
+            | Self::NotAnInteger
+            | Self::NegativeTimeout
+            | Self::InvalidMaster(_) => "ERR",
+        }
+    }
+}
+impl From<ProtocolError> for CommandError {
+    fn from(error: ProtocolError) -> Self {
+        Self::Protocol(error)
+    }
+}
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Protocol(error) => write!(f, "{error}"),
+            Self::UnknownCommand(command, arguments) => {
+                write!(f, "unknown command '{command}', with args beginning with: ")?;
+                for argument in arguments {
+                    write!(f, "'{argument}' ")?;
+                }
+                Ok(())
+            }
+            Self::WrongArity(command) => {
+                write!(f, "wrong number of arguments for '{command}' command")
+            }
+            Self::UnknownSubcommand(subcommand, command) => {
+                write!(f, "unknown subcommand '{subcommand}'. Try {command} HELP.")
+            }
+            Self::WrongType => write!(f, "Operation against a key holding the wrong kind of value"),
+            Self::Syntax => write!(f, "syntax error"),
+            Self::InvalidExpireTime(command) => {
+                write!(f, "invalid expire time in '{command}' command")
+            }
+            Self::UnsupportedOption(option) => write!(f, "Unsupported option {option}"),
+            Self::IncompatibleOptions(options) => {
+                write!(f, "{options} options at the same time are not compatible")
+            }
+            Self::NotAnInteger => write!(f, "value is not an integer or out of range"),
+            Self::NegativeTimeout => write!(f, "timeout is negative"),
+            Self::UnsupportedProtocol => write!(f, "unsupported protocol version"),
+            Self::WrongPass => write!(f, "invalid username-password pair or user is disabled."),
+            Self::InvalidMaster(field) => write!(f, "Invalid master {field}"),
+        }
+    }
+}
+impl ToRedisBytes for CommandError {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        SimpleError::from(format!("{} {self}", self.code()).as_str()).to_redis_bytes()
     }
 }
 impl Display for RedisCommands {
@@ -128,11 +739,25 @@ impl Display for RedisCommands {
             Self::Ping => write!(f, "Ping"),
             Self::Echo(_) => write!(f, "Echo"),
             Self::Get(_) => write!(f, "Get"),
-            Self::Set(_) => write!(f, "Set"),
+            Self::Set(..) => write!(f, "Set"),
             Self::Info(_) => write!(f, "Info"),
             Self::Replconf(_, _) => write!(f, "Replconf"),
             Self::Psync(_, _) => write!(f, "Psync"),
             Self::FullResync(_, _) => write!(f, "FullResync"),
+            Self::Hello(_, _, _) => write!(f, "Hello"),
+            Self::Command(_) => write!(f, "Command"),
+            Self::Expire(..) => write!(f, "Expire"),
+            Self::Ttl(..) => write!(f, "Ttl"),
+            Self::ExpireTime(..) => write!(f, "ExpireTime"),
+            Self::Persist(_) => write!(f, "Persist"),
+            Self::Del(_) => write!(f, "Del"),
+            Self::Save => write!(f, "Save"),
+            Self::Bgsave(_) => write!(f, "Bgsave"),
+            Self::Bgrewriteaof => write!(f, "Bgrewriteaof"),
+            Self::Lastsave => write!(f, "Lastsave"),
+            Self::Wait(..) => write!(f, "Wait"),
+            Self::Replicaof(_) => write!(f, "Replicaof"),
+            Self::Config(_) => write!(f, "Config"),
         }
     }
 }
@@ -141,11 +766,69 @@ impl Display for RedisCommands {
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
+    use crate::resp::decoder::{decode, Decoded};
+
+    #[test]
+    fn test_to_redis_bytes_round_trips() {
+        for command in [
+            "PING",
+            "ECHO Hello World",
+            "GET mykey",
+            "SET key value",
+            "SET key value NX GET PX 1500",
+            "SET key value XX EXAT 1700000000",
+            "SET key value KEEPTTL",
+            "INFO replication",
+            "REPLCONF listening-port 6380",
+            "PSYNC ? -1",
+            "HELLO 3 AUTH default secret SETNAME name",
+            "COMMAND",
+            "COMMAND COUNT",
+            "COMMAND INFO get set",
+            "COMMAND DOCS",
+            "EXPIRE key 10 NX",
+            "PEXPIREAT key 1700000000000 XX GT",
+            "TTL key",
+            "PTTL key",
+            "EXPIRETIME key",
+            "PEXPIRETIME key",
+            "PERSIST key",
+            "DEL key other",
+            "SAVE",
+            "BGSAVE SCHEDULE",
+            "BGREWRITEAOF",
+            "LASTSAVE",
+            "WAIT 2 500",
+            "WAIT 1 0",
+            "REPLICAOF 127.0.0.1 6379",
+            "REPLICAOF NO ONE",
+            "CONFIG GET maxmemory save",
+            "CONFIG SET maxmemory 1mb appendonly yes",
+            "CONFIG REWRITE",
+            "CONFIG RESETSTAT",
+        ] {
+            let command = RedisCommands::parse(&Type::Array(Array::from_string(command))).unwrap();
+            let encoded = command.to_redis_bytes();
+            let Ok(Decoded::Frame(frame, length)) = decode(&encoded) else {
+                panic!("{command} was encoded as an incomplete frame");
+            };
+            assert_eq!(length, encoded.len());
+            assert_eq!(RedisCommands::parse(&frame).unwrap(), command);
+        }
+        let fullresync = RedisCommands::FullResync(
+            ReplicationId::parse(Some("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string())),
+            Offset::parse(Some(0)),
+        );
+        assert_eq!(
+            fullresync.to_redis_bytes(),
+            b"+FULLRESYNC 8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb 0\r\n"
+        );
+    }
 
     #[test]
     fn test_parse_ping_command() {
         let command = Type::SimpleString(SimpleString::from_bytes(b"+PING\r\n\r\n"));
-        let result = RedisCommands::parse(&command);
+        let result = RedisCommands::parse(&command).unwrap();
         assert_eq!(result, RedisCommands::Ping);
     }
 
@@ -156,7 +839,7 @@ mod tests {
             BulkString::from("Hello"),
             BulkString::from("World"),
         ]));
-        let result = RedisCommands::parse(&command);
+        let result = RedisCommands::parse(&command).unwrap();
         assert_eq!(
             result,
             RedisCommands::Echo(vec![BulkString::from("Hello"), BulkString::from("World"),])
@@ -169,8 +852,8 @@ mod tests {
             BulkString::from("GET"),
             BulkString::from("mykey"),
         ]));
-        let result = RedisCommands::parse(&command);
-        assert_eq!(result, RedisCommands::Get("mykey".to_string()));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(result, RedisCommands::Get(Bytes::from("mykey")));
     }
 
     #[test]
@@ -182,24 +865,138 @@ mod tests {
             BulkString::from("EX"),
             BulkString::from("1000"),
         ]));
-        let result = RedisCommands::parse(&command);
+        let result = RedisCommands::parse(&command).unwrap();
         assert_eq!(
             result,
-            RedisCommands::Set((
-                "mykey".to_string(),
-                "myvalue".to_string(),
-                Some(Duration::from_millis(1000))
+            RedisCommands::Set(
+                Bytes::from("mykey"),
+                Bytes::from("myvalue"),
+                SetOptions {
+                    expiration: Some(SetExpiration::In(Duration::from_secs(1000))),
+                    ..SetOptions::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        let command = Type::Array(Array::from_string("SET lock token NX PX 30500"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Set(
+                Bytes::from("lock"),
+                Bytes::from("token"),
+                SetOptions {
+                    condition: Some(SetCondition::NotExists),
+                    expiration: Some(SetExpiration::In(Duration::from_millis(30500))),
+                    get: false,
+                }
+            )
+        );
+
+        let command = Type::Array(Array::from_string("set k v keepttl xx get"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Set(
+                Bytes::from("k"),
+                Bytes::from("v"),
+                SetOptions {
+                    condition: Some(SetCondition::Exists),
+                    expiration: Some(SetExpiration::KeepTtl),
+                    get: true,
+                }
+            )
+        );
+
+        let command = Type::Array(Array::from_string("SET k v EXAT 1700000000"));
+        let Ok(RedisCommands::Set(_, _, options)) = RedisCommands::parse(&command) else {
+            panic!("SET with EXAT should parse");
+        };
+        assert_eq!(
+            options.expiration,
+            Some(SetExpiration::At(
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000)
             ))
         );
     }
 
+    #[test]
+    fn test_parse_set_invalid_options() {
+        for command in [
+            "SET k v NX XX",
+            "SET k v EX 10 PX 100",
+            "SET k v EX 10 KEEPTTL",
+            "SET k v PX",
+            "SET k v FOO",
+        ] {
+            let result = RedisCommands::parse(&Type::Array(Array::from_string(command)));
+            assert_eq!(result, Err(CommandError::Syntax), "{command}");
+        }
+        let command = Type::Array(Array::from_string("SET k v EX 0"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(
+            result.to_redis_bytes(),
+            b"-ERR invalid expire time in 'set' command\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_parse_expire_commands() {
+        let command = Type::Array(Array::from_string("EXPIRE session 60 xx GT"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Expire(
+                Bytes::from("session"),
+                ExpireTime::In(60_000),
+                vec![ExpireCondition::Xx, ExpireCondition::Gt]
+            )
+        );
+
+        let command = Type::Array(Array::from_string("PEXPIREAT session -5"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Expire(Bytes::from("session"), ExpireTime::At(-5), vec![])
+        );
+
+        let command = Type::Array(Array::from_string("PTTL session"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Ttl(Bytes::from("session"), TimeUnit::Milliseconds)
+        );
+
+        for (command, error) in [
+            (
+                "EXPIRE k 10 NX GT",
+                "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+            ),
+            (
+                "EXPIRE k 10 GT LT",
+                "-ERR GT and LT options at the same time are not compatible\r\n",
+            ),
+            ("EXPIRE k 10 FOO", "-ERR Unsupported option FOO\r\n"),
+            (
+                "EXPIRE k 9223372036854775807",
+                "-ERR invalid expire time in 'expire' command\r\n",
+            ),
+        ] {
+            let result = RedisCommands::parse(&Type::Array(Array::from_string(command)));
+            assert_eq!(result.unwrap_err().to_redis_bytes(), error.as_bytes());
+        }
+    }
+
     #[test]
     fn test_parse_info_command() {
         let command = Type::Array(Array::from(vec![
             BulkString::from("INFO"),
             BulkString::from("server"),
         ]));
-        let result = RedisCommands::parse(&command);
+        let result = RedisCommands::parse(&command).unwrap();
         assert_eq!(result, RedisCommands::Info("server".to_string()));
     }
 
@@ -210,10 +1007,177 @@ mod tests {
             BulkString::from("listening-port"),
             BulkString::from("1234"),
         ]));
-        let result = RedisCommands::parse(&command);
+        let result = RedisCommands::parse(&command).unwrap();
         assert_eq!(
             result,
             RedisCommands::Replconf("listening-port".to_string(), "1234".to_string())
         );
     }
+
+    #[test]
+    fn test_parse_wait_command() {
+        let command = Type::Array(Array::from_string("WAIT 2 500"));
+        assert_eq!(
+            RedisCommands::parse(&command).unwrap(),
+            RedisCommands::Wait(2, Some(Duration::from_millis(500)))
+        );
+        let command = Type::Array(Array::from_string("WAIT 1 0"));
+        assert_eq!(
+            RedisCommands::parse(&command).unwrap(),
+            RedisCommands::Wait(1, None)
+        );
+        let command = Type::Array(Array::from_string("WAIT 1 -1"));
+        assert_eq!(
+            RedisCommands::parse(&command),
+            Err(CommandError::NegativeTimeout)
+        );
+    }
+
+    #[test]
+    fn test_parse_replicaof_command() {
+        let command = Type::Array(Array::from_string("REPLICAOF 127.0.0.1 6380"));
+        assert_eq!(
+            RedisCommands::parse(&command).unwrap(),
+            RedisCommands::Replicaof(Some(ReplicaOf::new(
+                "127.0.0.1".parse().unwrap(),
+                Port::new(6380).unwrap()
+            )))
+        );
+        let command = Type::Array(Array::from_string("SLAVEOF no one"));
+        assert_eq!(
+            RedisCommands::parse(&command).unwrap(),
+            RedisCommands::Replicaof(None)
+        );
+        let command = Type::Array(Array::from_string("REPLICAOF 127.0.0.1 port"));
+        assert_eq!(
+            RedisCommands::parse(&command),
+            Err(CommandError::InvalidMaster("port"))
+        );
+    }
+
+    #[test]
+    fn test_parse_config_command() {
+        let parse = |command: &str| RedisCommands::parse(&Type::Array(Array::from_string(command)));
+        assert_eq!(
+            parse("CONFIG GET max* save").unwrap(),
+            RedisCommands::Config(ConfigCommand::Get(vec![
+                "max*".to_string(),
+                "save".to_string()
+            ]))
+        );
+        assert_eq!(
+            parse("config set maxmemory 1mb appendonly yes").unwrap(),
+            RedisCommands::Config(ConfigCommand::Set(vec![
+                ("maxmemory".to_string(), "1mb".to_string()),
+                ("appendonly".to_string(), "yes".to_string())
+            ]))
+        );
+        assert_eq!(
+            parse("CONFIG REWRITE").unwrap(),
+            RedisCommands::Config(ConfigCommand::Rewrite)
+        );
+        assert_eq!(
+            parse("CONFIG RESETSTAT").unwrap(),
+            RedisCommands::Config(ConfigCommand::Resetstat)
+        );
+        assert_eq!(
+            parse("CONFIG SET maxmemory"),
+            Err(CommandError::WrongArity("config|set".to_string()))
+        );
+        assert_eq!(
+            parse("CONFIG GET"),
+            Err(CommandError::WrongArity("config|get".to_string()))
+        );
+        assert_eq!(
+            parse("CONFIG FOO"),
+            Err(CommandError::UnknownSubcommand(
+                "FOO".to_string(),
+                "CONFIG".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_hello_command() {
+        let command = Type::Array(Array::from_string(
+            "HELLO 3 AUTH default secret SETNAME app",
+        ));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Hello(
+                Some(3),
+                Some((Bytes::from("default"), Bytes::from("secret"))),
+                Some(Bytes::from("app"))
+            )
+        );
+
+        let command = Type::Array(Array::from_string("HELLO"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(result, RedisCommands::Hello(None, None, None));
+    }
+
+    #[test]
+    fn test_parse_command_command() {
+        let command = Type::Array(Array::from_string("COMMAND"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(result, RedisCommands::Command(CommandQuery::All));
+
+        let command = Type::Array(Array::from_string("command info get SET"));
+        let result = RedisCommands::parse(&command).unwrap();
+        assert_eq!(
+            result,
+            RedisCommands::Command(CommandQuery::Info(vec![
+                "get".to_string(),
+                "SET".to_string()
+            ]))
+        );
+
+        let command = Type::Array(Array::from_string("COMMAND COUNT extra"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(
+            result.to_redis_bytes(),
+            b"-ERR unknown subcommand 'COUNT'. Try COMMAND HELP.\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_write_commands() {
+        let set = RedisCommands::Set(Bytes::from("k"), Bytes::from("v"), SetOptions::default());
+        assert!(set.is_write());
+        assert!(!RedisCommands::Get(Bytes::from("k")).is_write());
+        assert!(!RedisCommands::Ping.is_write());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let command = Type::Array(Array::from_string("GET"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(
+            result.to_redis_bytes(),
+            b"-ERR wrong number of arguments for 'get' command\r\n".to_vec()
+        );
+
+        let command = Type::Array(Array::from_string("GET a b"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(result, CommandError::WrongArity("get".to_string()));
+
+        let command = Type::Array(Array::from_string("FOO a b"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(
+            result.to_redis_bytes(),
+            b"-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n".to_vec()
+        );
+
+        let command = Type::Array(Array::from_string("SET k v PX soon"));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(result, CommandError::NotAnInteger);
+
+        let command = Type::Array(Array::from(vec![Type::Integer(1)]));
+        let result = RedisCommands::parse(&command).unwrap_err();
+        assert_eq!(
+            result,
+            CommandError::Protocol(ProtocolError::ExpectedBulkStrings)
+        );
+    }
 }
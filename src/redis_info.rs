@@ -1,31 +1,344 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tokio::sync::watch;
 
 use crate::{
+    aof::{self, Aof},
+    rdb::Snapshot,
+    replication::{self, Backlog, LinkState, MasterLink, Replicas, ReplicationStream},
     resp::BulkString,
-    server_config::{Offset, ReplicationId},
-    Config,
+    server_config::{
+        persistence::PersistenceConfig,
+        registry::{self, SetError},
+        replication::ReplicationConfig,
+        Offset, ReplicationId,
+    },
+    store::unix_time_millis,
+    Config, Keyspace, ReplicaOf,
 };
 
-#[derive(Debug, Clone)]
+/// Redis version this server reports to clients, matching the RDB format it speaks.
+pub const REDIS_VERSION: &str = "7.2.0";
+
+#[derive(Debug)]
 pub struct RedisInfo {
     role: Role,
     master_replid: ReplicationId,
     master_repl_offset: Offset,
+    /// Replication ID this instance used before changing history, and the offset up to
+    /// which replicas following it may still resume.
+    master_replid2: ReplicationId,
+    second_repl_offset: Offset,
+    /// The live configuration, which `CONFIG SET` changes.
+    config: Config,
+    rdb: RdbStatus,
+    /// File the writes are appended to, with `appendonly yes`.
+    aof: Option<Aof>,
+    replicas: Replicas,
+    /// Created once there is a stream to keep: when the first replica attaches, or when a
+    /// replica synchronised with its master.
+    backlog: Option<Backlog>,
+    /// Link of a replica to its master.
+    master_link: Option<MasterLink>,
+    /// The master to follow, watched by the task keeping the link.
+    master: watch::Sender<Option<ReplicaOf>>,
 }
 impl RedisInfo {
     pub fn new(server_config: &Config) -> Self {
-        match server_config {
-            Config::Master(_) => Self {
-                role: Role::Master,
-                master_replid: ReplicationId::parse(Some("Master".to_string())),
-                master_repl_offset: Offset::parse(Some(0)),
-            },
-            Config::Replica(_) => Self {
-                role: Role::Replica,
-                master_replid: ReplicationId::parse(None),
-                master_repl_offset: Offset::parse(None),
-            },
+        let (role, master) = match server_config {
+            Config::Master(_) => (Role::Master, None),
+            Config::Replica(config) => (Role::Replica, Some(config.replica_of().clone())),
+        };
+        Self {
+            role,
+            master_replid: ReplicationId::random(),
+            master_repl_offset: Offset::parse(Some(0)),
+            master_replid2: ReplicationId::zero(),
+            second_repl_offset: Offset::parse(None),
+            config: server_config.clone(),
+            rdb: RdbStatus::new(),
+            aof: None,
+            replicas: Replicas::default(),
+            backlog: None,
+            master_link: master.clone().map(MasterLink::new),
+            master: watch::channel(master).0,
+        }
+    }
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+    pub const fn persistence(&self) -> &PersistenceConfig {
+        self.config.persistence()
+    }
+    /// Applies the parameters of `CONFIG SET` to the live configuration, all of them or,
+    /// if one is invalid or cannot be applied, none.
+    ///
+    /// Turning `appendonly` on writes the append-only files from `keyspace`, and turning it
+    /// off flushes them and stops appending.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SetError` if a parameter is unknown, immutable or invalid, or if the
+    /// append-only files cannot be written, the previous configuration being restored.
+    pub fn set_config(
+        &mut self,
+        changes: &[(String, String)],
+        keyspace: &Keyspace,
+    ) -> Result<(), SetError> {
+        let config = registry::set(&self.config, changes)?;
+        let previous = std::mem::replace(&mut self.config, config);
+        self.apply_config(keyspace).map_err(|reason| {
+            self.config = previous;
+            let _ = self.apply_config(keyspace);
+            SetError::Failed {
+                name: "appendonly".to_string(),
+                reason,
+            }
+        })
+    }
+    /// Brings the append-only file and the backlog in line with the configuration.
+    fn apply_config(&mut self, keyspace: &Keyspace) -> Result<(), String> {
+        let persistence = self.config.persistence();
+        match (persistence.appendonly(), &mut self.aof) {
+            (true, None) => {
+                let aof = Aof::create(persistence, Snapshot::from_keyspace(keyspace))
+                    .map_err(|e| format!("Cannot create append only files: {e}"))?;
+                self.aof = Some(aof);
+            }
+            (false, Some(aof)) => {
+                if let Ok(Some(file)) = aof.pending_fsync() {
+                    let _ = file.sync_data();
+                }
+                self.aof = None;
+            }
+            (true, Some(aof)) => aof.configure(persistence),
+            (false, None) => {}
+        }
+        if let Some(backlog) = &mut self.backlog {
+            backlog.resize(self.config.replication().backlog_size());
+        }
+        Ok(())
+    }
+    /// Resets the counters `CONFIG RESETSTAT` clears.
+    pub const fn reset_stats(&mut self) {
+        self.rdb.reset_stats();
+        if let Some(aof) = &mut self.aof {
+            aof.reset_stats();
+        }
+    }
+    pub const fn rdb(&self) -> &RdbStatus {
+        &self.rdb
+    }
+    pub const fn rdb_mut(&mut self) -> &mut RdbStatus {
+        &mut self.rdb
+    }
+    /// Starts appending the writes to `aof`.
+    pub fn enable_aof(&mut self, aof: Aof) {
+        self.aof = Some(aof);
+    }
+    pub const fn aof(&self) -> Option<&Aof> {
+        self.aof.as_ref()
+    }
+    pub const fn aof_mut(&mut self) -> Option<&mut Aof> {
+        self.aof.as_mut()
+    }
+    /// Returns the `aof_*` fields of `INFO persistence`.
+    pub fn aof_info(&self) -> String {
+        self.aof
+            .as_ref()
+            .map_or_else(|| aof::DISABLED_INFO.to_string(), Aof::info)
+    }
+    pub const fn replicas(&self) -> &Replicas {
+        &self.replicas
+    }
+    pub const fn replicas_mut(&mut self) -> &mut Replicas {
+        &mut self.replicas
+    }
+    /// Registers a replica, creating the backlog if it is the first one, and returns the
+    /// stream of commands to forward to it.
+    pub fn attach_replica(&mut self, id: u64, address: String) -> ReplicationStream {
+        if self.backlog.is_none() {
+            self.backlog = Some(Backlog::new(
+                self.config.replication().backlog_size(),
+                self.master_repl_offset.value(),
+            ));
+        }
+        self.replicas.attach(id, address)
+    }
+    /// Records a write applied to the keyspace: it is appended to the append-only file, if
+    /// any, and fed to the replicas.
+    ///
+    /// Callers hold the store lock while propagating, so that the file and the replicas
+    /// get the writes in the order they were applied. The store is always locked before
+    /// `RedisInfo`.
+    pub fn propagate(&mut self, command: &Bytes) {
+        if let Some(aof) = &mut self.aof {
+            aof.append(command);
         }
+        self.feed_replicas(command);
+    }
+    /// Propagates a `DEL` for each of the `expired` keys deleted by a master, before the
+    /// command that found them expired is propagated.
+    pub fn propagate_expired(&mut self, expired: &[Bytes]) {
+        for key in expired {
+            self.propagate(&replication::encode_command(&[b"DEL", key]));
+        }
+    }
+    /// Forwards a command to the replicas and the backlog, advancing the replication offset.
+    ///
+    /// Until a replica attached there is no stream and the offset stays put, like Redis.
+    /// A replica applying its master's stream does not feed it further, it `replicate`s
+    /// the stream instead.
+    pub fn feed_replicas(&mut self, command: &Bytes) {
+        if self.role != Role::Master {
+            return;
+        }
+        let Some(backlog) = &mut self.backlog else {
+            return;
+        };
+        backlog.append(command);
+        self.replicas.propagate(command);
+        self.master_repl_offset.advance(command.len());
+    }
+    /// Registers a replica that asked `PSYNC replid offset` and returns its stream, starting
+    /// with the part of the backlog it missed, or `None` if it needs a full resynchronisation.
+    ///
+    /// A replica of the history this instance had before its replication ID changed may
+    /// resume up to `second_repl_offset`.
+    pub fn partial_resync(
+        &mut self,
+        replid: &ReplicationId,
+        offset: i64,
+        id: u64,
+        address: String,
+    ) -> Option<ReplicationStream> {
+        let known = *replid == self.master_replid
+            || (*replid == self.master_replid2 && offset <= self.second_repl_offset.value());
+        if !known {
+            return None;
+        }
+        let history = self.backlog.as_ref()?.since(offset)?;
+        Some(self.replicas.resume(id, address, history))
+    }
+    /// Adopts the replication ID and offset a master announced with `FULLRESYNC`, starting
+    /// a new backlog of its stream.
+    pub fn set_master_replication(&mut self, replid: ReplicationId, offset: Offset) {
+        self.backlog = Some(Backlog::new(
+            self.config.replication().backlog_size(),
+            offset.value(),
+        ));
+        self.master_replid = replid;
+        self.master_repl_offset = offset;
+        self.master_replid2 = ReplicationId::zero();
+        self.second_repl_offset = Offset::parse(None);
+    }
+    /// Resumes the history a snapshot loaded at startup was taken at, so that replicas of
+    /// this instance, or its master, can continue the stream from there.
+    pub fn restore_replication(&mut self, replid: ReplicationId, offset: Offset) {
+        self.backlog = Some(Backlog::new(
+            self.config.replication().backlog_size(),
+            offset.value(),
+        ));
+        self.master_replid = replid;
+        self.master_repl_offset = offset;
+    }
+    /// Returns the replication ID and offset a replica asks its master to continue from,
+    /// `None` until it followed one.
+    pub fn psync_position(&self) -> Option<(ReplicationId, Offset)> {
+        self.backlog.as_ref()?;
+        Some((
+            self.master_replid.clone(),
+            Offset::parse(Some(self.master_repl_offset.value() + 1)),
+        ))
+    }
+    /// Returns the replication ID and offset to record in a snapshot, `None` for a replica
+    /// that never followed its master.
+    pub fn rdb_replication(&self) -> Option<(ReplicationId, Offset)> {
+        if self.role == Role::Replica && self.backlog.is_none() {
+            return None;
+        }
+        Some((self.master_replid.clone(), self.master_repl_offset.clone()))
+    }
+    /// Moves the link to the master to `state`.
+    pub fn set_link_state(&mut self, state: LinkState) {
+        if let Some(link) = &mut self.master_link {
+            link.set_state(state);
+        }
+    }
+    /// Records that the master just sent something on the link.
+    pub fn touch_master_link(&mut self) {
+        if let Some(link) = &mut self.master_link {
+            link.touch();
+        }
+    }
+    pub const fn replication_config(&self) -> &ReplicationConfig {
+        self.config.replication()
+    }
+    /// Returns whether writes from clients are refused, as on a read only replica.
+    pub const fn refuses_writes(&self) -> bool {
+        matches!(self.role, Role::Replica) && self.config.replication().read_only()
+    }
+    /// Returns a handle on the master to follow, which changes with `REPLICAOF`.
+    pub fn subscribe_master(&self) -> watch::Receiver<Option<ReplicaOf>> {
+        self.master.subscribe()
+    }
+    /// Returns the master this instance currently replicates, `None` on a master.
+    pub fn master(&self) -> Option<ReplicaOf> {
+        self.master.borrow().clone()
+    }
+    /// Makes this instance a replica of `master`, returning `false` if it already is.
+    ///
+    /// The dataset is kept until the master sends its own. The replicas of this instance
+    /// are disconnected, and a former master asks its new master to continue from its own
+    /// history, which succeeds if that master used to be its replica.
+    pub fn set_master(&mut self, master: ReplicaOf) -> bool {
+        if self
+            .master_link
+            .as_ref()
+            .is_some_and(|link| *link.master() == master)
+        {
+            return false;
+        }
+        if self.backlog.is_none() {
+            self.backlog = Some(Backlog::new(
+                self.config.replication().backlog_size(),
+                self.master_repl_offset.value(),
+            ));
+        }
+        self.role = Role::Replica;
+        self.replicas = Replicas::default();
+        self.master_link = Some(MasterLink::new(master.clone()));
+        self.master.send_replace(Some(master));
+        true
+    }
+    /// Turns a replica into a master, under a new replication ID since its history may now
+    /// diverge from the one of its former master. Its replicas can still resume the former
+    /// history up to the current offset.
+    pub fn promote(&mut self) {
+        self.role = Role::Master;
+        self.master_link = None;
+        self.master.send_replace(None);
+        self.shift_replication_id(ReplicationId::random());
+    }
+    /// Switches to a new replication ID, keeping the current one as the secondary ID so
+    /// that replicas of the current history can still resume from the backlog.
+    pub fn shift_replication_id(&mut self, replid: ReplicationId) {
+        self.master_replid2 = std::mem::replace(&mut self.master_replid, replid);
+        self.second_repl_offset = Offset::parse(Some(self.master_repl_offset.value() + 1));
+    }
+    /// Records a command of the master's stream processed by this replica.
+    pub fn replicate(&mut self, command: &[u8]) {
+        if self.role != Role::Replica {
+            return;
+        }
+        if let Some(backlog) = &mut self.backlog {
+            backlog.append(command);
+        }
+        self.master_repl_offset.advance(command.len());
     }
     pub fn to_bulk_string(&self) -> BulkString {
         BulkString::from(format!("{self}").as_str())
@@ -43,8 +356,167 @@ impl RedisInfo {
 impl Display for RedisInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "role:{}\r\n", self.role)?;
+        if let Some(link) = &self.master_link {
+            write!(f, "{link}")?;
+            write!(f, "slave_repl_offset:{}\r\n", self.master_repl_offset)?;
+            write!(
+                f,
+                "slave_read_only:{}\r\n",
+                u8::from(self.config.replication().read_only())
+            )?;
+        }
+        write!(f, "connected_slaves:{}\r\n", self.replicas.len())?;
+        write!(f, "{}", self.replicas)?;
         write!(f, "master_replid:{}\r\n", self.master_replid)?;
+        write!(f, "master_replid2:{}\r\n", self.master_replid2)?;
         write!(f, "master_repl_offset:{}\r\n", self.master_repl_offset)?;
+        write!(f, "second_repl_offset:{}\r\n", self.second_repl_offset)?;
+        match &self.backlog {
+            Some(backlog) => write!(
+                f,
+                "repl_backlog_active:1\r\n\
+                 repl_backlog_size:{}\r\n\
+                 repl_backlog_first_byte_offset:{}\r\n\
+                 repl_backlog_histlen:{}\r\n",
+                backlog.size(),
+                backlog.first_offset(),
+                backlog.histlen()
+            ),
+            None => write!(
+                f,
+                "repl_backlog_active:0\r\n\
+                 repl_backlog_size:{}\r\n\
+                 repl_backlog_first_byte_offset:0\r\n\
+                 repl_backlog_histlen:0\r\n",
+                self.config.replication().backlog_size()
+            ),
+        }
+    }
+}
+
+/// State of the RDB snapshots, reported in the `# Persistence` section of `INFO`.
+#[derive(Debug, Clone)]
+pub struct RdbStatus {
+    /// Unix time in seconds of the last successful save, or of the startup.
+    last_save: i64,
+    last_bgsave_ok: bool,
+    last_bgsave_duration: Option<Duration>,
+    bgsave_started: Option<Instant>,
+    /// Whether a `SAVE` is writing a snapshot in the foreground.
+    saving: bool,
+    /// When the last failed save was attempted, to delay retries of scheduled saves.
+    last_failure: Option<Instant>,
+    /// Whether a `BGSAVE SCHEDULE` is waiting for the running save to finish.
+    bgsave_scheduled: bool,
+    saves: u64,
+}
+impl RdbStatus {
+    pub fn new() -> Self {
+        Self {
+            last_save: unix_time_millis() / 1000,
+            last_bgsave_ok: true,
+            last_bgsave_duration: None,
+            bgsave_started: None,
+            saving: false,
+            last_failure: None,
+            bgsave_scheduled: false,
+            saves: 0,
+        }
+    }
+    pub const fn last_save(&self) -> i64 {
+        self.last_save
+    }
+    pub const fn last_failure(&self) -> Option<Instant> {
+        self.last_failure
+    }
+    pub const fn bgsave_in_progress(&self) -> bool {
+        self.bgsave_started.is_some()
+    }
+    /// Whether a snapshot is being written, by `SAVE` or in the background.
+    pub const fn save_in_progress(&self) -> bool {
+        self.saving || self.bgsave_in_progress()
+    }
+    pub const fn bgsave_scheduled(&self) -> bool {
+        self.bgsave_scheduled
+    }
+    pub const fn schedule_bgsave(&mut self, scheduled: bool) {
+        self.bgsave_scheduled = scheduled;
+    }
+    pub fn start_bgsave(&mut self) {
+        self.bgsave_started = Some(Instant::now());
+        self.bgsave_scheduled = false;
+    }
+    pub const fn start_save(&mut self) {
+        self.saving = true;
+    }
+    pub fn finish_save(&mut self, succeeded: bool) {
+        self.saving = false;
+        self.record_save(succeeded);
+    }
+    pub fn finish_bgsave(&mut self, succeeded: bool) {
+        self.last_bgsave_duration = self.bgsave_started.take().map(|started| started.elapsed());
+        self.last_bgsave_ok = succeeded;
+        self.record_save(succeeded);
+    }
+    /// Clears the count of saves, for `CONFIG RESETSTAT`.
+    pub const fn reset_stats(&mut self) {
+        self.saves = 0;
+    }
+    /// Records the outcome of a save, foreground or background.
+    pub fn record_save(&mut self, succeeded: bool) {
+        if succeeded {
+            self.last_save = unix_time_millis() / 1000;
+            self.last_failure = None;
+            self.saves += 1;
+        } else {
+            self.last_failure = Some(Instant::now());
+        }
+    }
+    /// Returns the fields of `INFO persistence`, `changes` being the writes since the last
+    /// save.
+    pub fn info(&self, changes: u64) -> String {
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or(-1, |duration| {
+                i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+            })
+        };
+        format!(
+            "loading:0\r\n\
+             rdb_changes_since_last_save:{changes}\r\n\
+             rdb_bgsave_in_progress:{}\r\n\
+             rdb_last_save_time:{}\r\n\
+             rdb_last_bgsave_status:{}\r\n\
+             rdb_last_bgsave_time_sec:{}\r\n\
+             rdb_current_bgsave_time_sec:{}\r\n\
+             rdb_saves:{}\r\n",
+            u8::from(self.bgsave_in_progress()),
+            self.last_save,
+            if self.last_bgsave_ok { "ok" } else { "err" },
+            seconds(self.last_bgsave_duration),
+            seconds(self.bgsave_started.map(|started| started.elapsed())),
+            self.saves,
+        )
+    }
+}
+
+/// Fields of the `# Stats` section of `INFO`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    expired_keys: u64,
+    expired_stale_perc: f64,
+}
+impl Stats {
+    pub fn new(keyspace: &Keyspace) -> Self {
+        Self {
+            expired_keys: keyspace.expired_keys(),
+            expired_stale_perc: keyspace.expired_stale_perc(),
+        }
+    }
+}
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expired_keys:{}\r\n", self.expired_keys)?;
+        write!(f, "expired_stale_perc:{:.2}\r\n", self.expired_stale_perc)?;
         Ok(())
     }
 }
@@ -62,3 +534,161 @@ impl Display for Role {
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::{server_config::persistence::AppendFsync, MasterConfig};
+
+    #[test]
+    fn test_rdb_status() {
+        let mut status = RdbStatus::new();
+        assert!(status.info(3).contains("rdb_changes_since_last_save:3\r\n"));
+        status.start_bgsave();
+        assert!(status.bgsave_in_progress());
+        assert!(status.info(0).contains("rdb_bgsave_in_progress:1\r\n"));
+        status.finish_bgsave(false);
+        assert!(!status.bgsave_in_progress());
+        assert!(status.last_failure().is_some());
+        let info = status.info(0);
+        assert!(info.contains("rdb_last_bgsave_status:err\r\n"));
+        assert!(info.contains("rdb_last_bgsave_time_sec:0\r\n"));
+        assert!(info.contains("rdb_saves:0\r\n"));
+        status.record_save(true);
+        assert!(status.info(0).contains("rdb_saves:1\r\n"));
+        status.start_save();
+        assert!(status.save_in_progress());
+        assert!(!status.bgsave_in_progress());
+        status.finish_save(true);
+        assert!(!status.save_in_progress());
+        assert!(status.info(0).contains("rdb_saves:2\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_partial_resync_from_backlog() {
+        let config = MasterConfig::from_args(&["--repl-backlog-size", "16"]).unwrap();
+        let mut info = RedisInfo::new(&Config::Master(config));
+        let replid = info.master_replid().to_owned();
+        info.propagate(&Bytes::from("lost"));
+        assert_eq!(info.master_repl_offset().value(), 0);
+
+        let _replica = info.attach_replica(1, "127.0.0.1:6380".to_string());
+        info.propagate(&Bytes::from("0123456789"));
+        info.propagate(&Bytes::from("abcdefghij"));
+        assert_eq!(info.master_repl_offset().value(), 20);
+        let mut resumed = info
+            .partial_resync(&replid, 5, 2, "127.0.0.1:6381".to_string())
+            .unwrap();
+        assert_eq!(
+            resumed.recv().await.unwrap(),
+            Bytes::from("456789abcdefghij")
+        );
+        assert!(info
+            .partial_resync(&replid, 4, 3, "127.0.0.1:6382".to_string())
+            .is_none());
+        let unknown = ReplicationId::parse(Some("unknown".to_string()));
+        assert!(info
+            .partial_resync(&unknown, 15, 3, "127.0.0.1:6382".to_string())
+            .is_none());
+
+        info.shift_replication_id(ReplicationId::parse(Some("next".to_string())));
+        assert!(info.to_string().contains("second_repl_offset:21\r\n"));
+        assert!(info
+            .partial_resync(&replid, 21, 3, "127.0.0.1:6382".to_string())
+            .is_some());
+        info.propagate(&Bytes::from("k"));
+        assert!(info
+            .partial_resync(&replid, 22, 4, "127.0.0.1:6383".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn test_set_config() {
+        let config = MasterConfig::from_args(&["--dir", "/dev/null"]).unwrap();
+        let mut info = RedisInfo::new(&Config::Master(config));
+        let keyspace = Keyspace::new();
+        let _replica = info.attach_replica(1, "127.0.0.1:6380".to_string());
+        let changes = |changes: &[(&str, &str)]| {
+            changes
+                .iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect::<Vec<_>>()
+        };
+        info.set_config(
+            &changes(&[("repl-backlog-size", "32"), ("replica-read-only", "no")]),
+            &keyspace,
+        )
+        .unwrap();
+        assert!(info.to_string().contains("repl_backlog_size:32\r\n"));
+        assert!(!info.replication_config().read_only());
+
+        let error = info
+            .set_config(
+                &changes(&[("appendfsync", "always"), ("appendonly", "yes")]),
+                &keyspace,
+            )
+            .unwrap_err();
+        assert!(matches!(error, SetError::Failed { name, .. } if name == "appendonly"));
+        assert_eq!(info.persistence().appendfsync(), AppendFsync::EverySec);
+        assert!(!info.persistence().appendonly());
+        assert!(info.aof().is_none());
+    }
+
+    #[test]
+    fn test_refuses_writes() {
+        let info = |args: &[&str]| RedisInfo::new(&Config::from_args(args).unwrap());
+        assert!(!info(&[]).refuses_writes());
+        assert!(!info(&["--replica-read-only", "no"]).refuses_writes());
+        assert!(info(&["--replicaof", "127.0.0.1 6380"]).refuses_writes());
+        assert!(
+            !info(&["--replicaof", "127.0.0.1 6380", "--replica-read-only", "no"]).refuses_writes()
+        );
+
+        let mut master = info(&["--dir", "/dev/null"]);
+        master.set_master(ReplicaOf::new(
+            "127.0.0.1".parse().unwrap(),
+            "6380".parse().unwrap(),
+        ));
+        assert!(master.refuses_writes());
+        master
+            .set_config(
+                &[("slave-read-only".to_string(), "no".to_string())],
+                &Keyspace::new(),
+            )
+            .unwrap();
+        assert!(!master.refuses_writes());
+        master
+            .set_config(
+                &[("replica-read-only".to_string(), "yes".to_string())],
+                &Keyspace::new(),
+            )
+            .unwrap();
+        master.promote();
+        assert!(!master.refuses_writes());
+    }
+
+    #[test]
+    fn test_role_changes() {
+        let mut info = RedisInfo::new(&Config::Master(MasterConfig::from_args(&[]).unwrap()));
+        let mut master = info.subscribe_master();
+        let replid = info.master_replid().to_owned();
+        let replica_of = ReplicaOf::new("127.0.0.1".parse().unwrap(), "6380".parse().unwrap());
+        assert!(info.set_master(replica_of.clone()));
+        assert!(!info.set_master(replica_of.clone()));
+        assert_eq!(*info.role(), Role::Replica);
+        assert_eq!(*master.borrow_and_update(), Some(replica_of));
+        assert_eq!(
+            info.psync_position(),
+            Some((replid.clone(), Offset::parse(Some(1))))
+        );
+
+        info.promote();
+        assert_eq!(*info.role(), Role::Master);
+        assert_eq!(*master.borrow_and_update(), None);
+        assert_ne!(*info.master_replid(), replid);
+        assert!(info
+            .to_string()
+            .contains(&format!("master_replid2:{replid}\r\n")));
+    }
+}
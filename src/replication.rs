@@ -0,0 +1,473 @@
+//! Links from a master to the replicas attached to it.
+//!
+//! Every write applied to the keyspace is propagated as a RESP command to each replica. The
+//! commands are pushed to a channel per replica, drained by the connection that served its
+//! `PSYNC`, so propagating never waits on a slow replica. Like Redis'
+//! `client-output-buffer-limit replica 256mb 64mb 60`, a replica whose channel holds more
+//! than 256MB, or more than 64MB for over a minute, is dropped rather than letting its
+//! channel grow without bound. Replicas report the offset they processed with
+//! `REPLCONF ACK`, which `WAIT` relies on.
+//!
+//! The latest bytes of the stream are kept in a backlog, so that a replica reconnecting
+//! after a short outage resumes from its offset instead of loading a whole new snapshot.
+//!
+//! On the other end, a replica tracks the state of its link to the master, which it
+//! reconnects to in the background whenever the link drops.
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Notify,
+};
+
+use crate::{
+    resp::{Array, BulkString, Protocol, Type},
+    ReplicaOf,
+};
+
+/// How often a replica reports its offset to its master.
+pub const ACK_PERIOD: Duration = Duration::from_secs(1);
+/// How often a master pings its replicas, so that they can tell an idle link from a dead
+/// one, like Redis' default `repl-ping-replica-period`.
+pub const PING_PERIOD: Duration = Duration::from_secs(10);
+
+/// Bytes queued for a replica past which it is dropped at once.
+const OUTPUT_HARD_LIMIT: usize = 256 * 1024 * 1024;
+/// Bytes queued for a replica past which it is dropped if they stay queued for
+/// `OUTPUT_SOFT_DURATION`.
+const OUTPUT_SOFT_LIMIT: usize = 64 * 1024 * 1024;
+const OUTPUT_SOFT_DURATION: Duration = Duration::from_mins(1);
+
+/// A replica attached to this instance.
+#[derive(Debug, Clone)]
+struct ReplicaLink {
+    /// Id of the client connection the replica synchronised on.
+    id: u64,
+    /// Address at which the replica listens, as `ip:port`.
+    address: String,
+    sender: UnboundedSender<Bytes>,
+    /// Bytes sent to the replica's stream that its connection did not take yet.
+    queued: Arc<AtomicUsize>,
+    /// Since when more than `OUTPUT_SOFT_LIMIT` bytes are queued.
+    over_soft_limit: Option<Instant>,
+    /// Offset of the replication stream the replica last acknowledged.
+    ack_offset: i64,
+    last_ack: Instant,
+}
+impl ReplicaLink {
+    /// Queues `command` for the replica, returning `false` if its connection was closed or
+    /// it lags so far behind that it must be dropped.
+    fn send(&mut self, command: Bytes) -> bool {
+        let queued = self.queued.fetch_add(command.len(), Ordering::Relaxed) + command.len();
+        let over_soft_limit = if queued > OUTPUT_SOFT_LIMIT {
+            *self.over_soft_limit.get_or_insert_with(Instant::now)
+        } else {
+            self.over_soft_limit = None;
+            Instant::now()
+        };
+        if queued > OUTPUT_HARD_LIMIT || over_soft_limit.elapsed() > OUTPUT_SOFT_DURATION {
+            eprintln!(
+                "Replica {} dropped for overcoming of output buffer limits",
+                self.address
+            );
+            return false;
+        }
+        self.sender.send(command).is_ok()
+    }
+}
+
+/// The commands propagated to a replica, drained by the connection it synchronised on.
+#[derive(Debug)]
+pub struct ReplicationStream {
+    receiver: UnboundedReceiver<Bytes>,
+    /// Bytes sent and not received yet, shared with the `ReplicaLink`.
+    queued: Arc<AtomicUsize>,
+}
+impl ReplicationStream {
+    /// Returns the next command to forward, or `None` once the replica was dropped and
+    /// the commands queued before are forwarded.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        let command = self.receiver.recv().await?;
+        self.queued.fetch_sub(command.len(), Ordering::Relaxed);
+        Some(command)
+    }
+}
+
+/// Replicas attached to this instance.
+#[derive(Debug, Clone, Default)]
+pub struct Replicas {
+    links: Vec<ReplicaLink>,
+    /// Woken up whenever a replica acknowledges an offset.
+    acks: Arc<Notify>,
+}
+impl Replicas {
+    /// Registers the replica synchronising on connection `id`, returning the stream of
+    /// commands to forward to it.
+    pub fn attach(&mut self, id: u64, address: String) -> ReplicationStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        self.links.push(ReplicaLink {
+            id,
+            address,
+            sender,
+            queued: queued.clone(),
+            over_soft_limit: None,
+            ack_offset: 0,
+            last_ack: Instant::now(),
+        });
+        ReplicationStream { receiver, queued }
+    }
+
+    /// Registers a replica resuming the stream, queuing the `history` it missed ahead of the
+    /// commands propagated from now on.
+    pub fn resume(&mut self, id: u64, address: String, history: Bytes) -> ReplicationStream {
+        let stream = self.attach(id, address);
+        if let Some(link) = self.links.last_mut() {
+            link.send(history);
+        }
+        stream
+    }
+
+    /// Sends `command` to every replica, forgetting those whose connection was closed and
+    /// those past the output buffer limits.
+    pub fn propagate(&mut self, command: &Bytes) {
+        self.links.retain_mut(|link| link.send(command.clone()));
+    }
+
+    /// Records that the replica on connection `id` processed the stream up to `offset`.
+    pub fn acknowledge(&mut self, id: u64, offset: i64) {
+        if let Some(link) = self.links.iter_mut().find(|link| link.id == id) {
+            link.ack_offset = offset;
+            link.last_ack = Instant::now();
+            self.acks.notify_waiters();
+        }
+    }
+
+    /// Returns how many replicas acknowledged at least `offset`.
+    pub fn acknowledged(&self, offset: i64) -> usize {
+        self.connected()
+            .filter(|link| link.ack_offset >= offset)
+            .count()
+    }
+
+    /// Returns a handle woken up on every acknowledgement.
+    pub fn acks(&self) -> Arc<Notify> {
+        self.acks.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.connected().count()
+    }
+
+    fn connected(&self) -> impl Iterator<Item = &ReplicaLink> {
+        self.links.iter().filter(|link| !link.sender.is_closed())
+    }
+}
+/// Lists the replicas as the `slave<n>` lines of `INFO replication`.
+impl Display for Replicas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, link) in self.connected().enumerate() {
+            let (ip, port) = link
+                .address
+                .rsplit_once(':')
+                .unwrap_or((link.address.as_str(), "0"));
+            write!(
+                f,
+                "slave{index}:ip={ip},port={port},state=online,offset={},lag={}\r\n",
+                link.ack_offset,
+                link.last_ack.elapsed().as_secs()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Circular buffer holding the latest bytes of the replication stream.
+///
+/// Offsets are those of `PSYNC`: a replica that processed the stream up to offset `n` asks
+/// to continue from `n + 1`.
+#[derive(Debug, Clone)]
+pub struct Backlog {
+    data: VecDeque<u8>,
+    size: usize,
+    /// Replication offset of the last byte written to the backlog.
+    offset: i64,
+}
+impl Backlog {
+    /// Creates an empty backlog of `size` bytes for a stream currently at `offset`.
+    pub fn new(size: usize, offset: i64) -> Self {
+        Self {
+            data: VecDeque::with_capacity(size.min(64 * 1024)),
+            size,
+            offset,
+        }
+    }
+
+    /// Appends bytes of the stream, dropping the oldest ones beyond the size of the backlog.
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+        let excess = self.data.len().saturating_sub(self.size);
+        self.data.drain(..excess);
+        self.offset = self
+            .offset
+            .saturating_add(i64::try_from(bytes.len()).unwrap_or(i64::MAX));
+    }
+
+    /// Returns the offset of the oldest byte still in the backlog.
+    pub fn first_offset(&self) -> i64 {
+        self.offset - self.histlen() + 1
+    }
+
+    /// Returns how many bytes of history the backlog holds.
+    pub fn histlen(&self) -> i64 {
+        i64::try_from(self.data.len()).unwrap_or(i64::MAX)
+    }
+
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Changes the size of the backlog, dropping the oldest bytes if it shrinks.
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        let excess = self.data.len().saturating_sub(size);
+        self.data.drain(..excess);
+    }
+
+    /// Returns the stream from `offset` on, `None` if the backlog no longer holds it.
+    pub fn since(&self, offset: i64) -> Option<Bytes> {
+        if offset < self.first_offset() || offset > self.offset + 1 {
+            return None;
+        }
+        let skip = usize::try_from(offset - self.first_offset()).ok()?;
+        Some(self.data.range(skip..).copied().collect())
+    }
+}
+
+/// Steps a replica goes through to follow its master.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Connecting to the master, or waiting before trying again.
+    Connect,
+    /// Exchanging `PING`, `REPLCONF` and `PSYNC`.
+    Handshake,
+    /// Receiving the snapshot of a full resynchronisation.
+    Transfer,
+    /// Applying the stream of writes.
+    Connected,
+}
+
+/// The link a replica keeps with its master, as reported by `INFO replication`.
+#[derive(Debug, Clone)]
+pub struct MasterLink {
+    master: ReplicaOf,
+    state: LinkState,
+    /// Last time the master sent anything.
+    last_io: Option<Instant>,
+    /// When the link last went down, `None` if it was never up.
+    down_since: Option<Instant>,
+}
+impl MasterLink {
+    pub const fn new(master: ReplicaOf) -> Self {
+        Self {
+            master,
+            state: LinkState::Connect,
+            last_io: None,
+            down_since: None,
+        }
+    }
+
+    pub const fn master(&self) -> &ReplicaOf {
+        &self.master
+    }
+
+    pub fn set_state(&mut self, state: LinkState) {
+        if self.state == LinkState::Connected && state != LinkState::Connected {
+            self.down_since = Some(Instant::now());
+        }
+        self.state = state;
+    }
+
+    /// Records that the master just sent something.
+    pub fn touch(&mut self) {
+        self.last_io = Some(Instant::now());
+    }
+}
+/// Describes the link with the `master_*` lines of `INFO replication`.
+impl Display for MasterLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let up = self.state == LinkState::Connected;
+        let seconds = |instant: Option<Instant>| {
+            instant.map_or(-1, |instant| {
+                i64::try_from(instant.elapsed().as_secs()).unwrap_or(i64::MAX)
+            })
+        };
+        write!(f, "master_host:{}\r\n", self.master.host_address())?;
+        write!(f, "master_port:{}\r\n", self.master.port())?;
+        write!(
+            f,
+            "master_link_status:{}\r\n",
+            if up { "up" } else { "down" }
+        )?;
+        write!(
+            f,
+            "master_last_io_seconds_ago:{}\r\n",
+            if up { seconds(self.last_io) } else { -1 }
+        )?;
+        write!(
+            f,
+            "master_sync_in_progress:{}\r\n",
+            u8::from(self.state == LinkState::Transfer)
+        )?;
+        if !up {
+            write!(
+                f,
+                "master_link_down_since_seconds:{}\r\n",
+                seconds(self.down_since)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a command as the array of bulk strings sent on the replication stream.
+pub fn encode_command(arguments: &[&[u8]]) -> Bytes {
+    let arguments = arguments
+        .iter()
+        .map(|argument| Type::BulkString(BulkString::from(*argument)))
+        .collect::<Vec<_>>();
+    Bytes::from(Type::Array(Array::from(arguments)).encode(Protocol::Resp2))
+}
+
+/// Encodes the `SET` that stores `value` at `key`, with its expiration as an absolute Unix
+/// time in milliseconds: a relative one would drift with the delay until it is applied.
+pub fn encode_set(key: &[u8], value: &[u8], expiration: Option<i64>) -> Bytes {
+    let expiration = expiration.map(|unix| unix.to_string());
+    let mut command: Vec<&[u8]> = vec![b"SET", key, value];
+    if let Some(expiration) = &expiration {
+        command.extend([b"PXAT".as_slice(), expiration.as_bytes()]);
+    }
+    encode_command(&command)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_propagate_to_attached_replicas() {
+        let mut replicas = Replicas::default();
+        let mut first = replicas.attach(1, "127.0.0.1:6380".to_string());
+        let second = replicas.attach(2, "127.0.0.1:6381".to_string());
+        assert_eq!(replicas.len(), 2);
+        drop(second);
+        assert_eq!(replicas.len(), 1);
+        let command = encode_command(&[b"SET", b"key", b"value"]);
+        assert_eq!(
+            command,
+            Bytes::from("*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n")
+        );
+        replicas.propagate(&command);
+        assert_eq!(
+            replicas.links[0].queued.load(Ordering::Relaxed),
+            command.len()
+        );
+        assert_eq!(first.recv().await.unwrap(), command);
+        assert_eq!(replicas.links[0].queued.load(Ordering::Relaxed), 0);
+        assert_eq!(replicas.links.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_replica_past_output_limits() {
+        let mut replicas = Replicas::default();
+        let command = Bytes::from_static(b"PING");
+        let mut stream = replicas.attach(1, "127.0.0.1:6380".to_string());
+        replicas.links[0]
+            .queued
+            .store(OUTPUT_SOFT_LIMIT, Ordering::Relaxed);
+        replicas.propagate(&command);
+        assert!(replicas.links[0].over_soft_limit.is_some());
+        replicas.links[0].over_soft_limit = Instant::now().checked_sub(OUTPUT_SOFT_DURATION);
+        replicas.propagate(&command);
+        assert!(replicas.links.is_empty());
+        assert_eq!(stream.recv().await.unwrap(), command);
+        assert_eq!(stream.recv().await, None);
+
+        let _stream = replicas.attach(2, "127.0.0.1:6381".to_string());
+        replicas.links[0]
+            .queued
+            .store(OUTPUT_HARD_LIMIT, Ordering::Relaxed);
+        replicas.propagate(&command);
+        assert!(replicas.links.is_empty());
+    }
+
+    #[test]
+    fn test_backlog_keeps_latest_bytes() {
+        let mut backlog = Backlog::new(8, 100);
+        assert_eq!(backlog.since(101), Some(Bytes::new()));
+        assert_eq!(backlog.since(100), None);
+        backlog.append(b"abcde");
+        assert_eq!((backlog.first_offset(), backlog.histlen()), (101, 5));
+        assert_eq!(backlog.since(103), Some(Bytes::from("cde")));
+        backlog.append(b"fghij");
+        assert_eq!((backlog.first_offset(), backlog.histlen()), (103, 8));
+        assert_eq!(backlog.since(102), None);
+        assert_eq!(backlog.since(103), Some(Bytes::from("cdefghij")));
+        assert_eq!(backlog.since(111), Some(Bytes::new()));
+        assert_eq!(backlog.since(112), None);
+    }
+
+    #[test]
+    fn test_master_link_info() {
+        let mut link = MasterLink::new(ReplicaOf::new(
+            "127.0.0.1".parse().unwrap(),
+            "6379".parse().unwrap(),
+        ));
+        assert_eq!(
+            link.to_string(),
+            "master_host:127.0.0.1\r\nmaster_port:6379\r\nmaster_link_status:down\r\n\
+             master_last_io_seconds_ago:-1\r\nmaster_sync_in_progress:0\r\n\
+             master_link_down_since_seconds:-1\r\n"
+        );
+        link.set_state(LinkState::Transfer);
+        assert!(link.to_string().contains("master_sync_in_progress:1\r\n"));
+        link.set_state(LinkState::Connected);
+        link.touch();
+        let info = link.to_string();
+        assert!(info.contains("master_link_status:up\r\n"));
+        assert!(info.contains("master_last_io_seconds_ago:0\r\n"));
+        assert!(!info.contains("master_link_down_since_seconds"));
+        link.set_state(LinkState::Connect);
+        assert!(link
+            .to_string()
+            .contains("master_link_down_since_seconds:0\r\n"));
+    }
+
+    #[test]
+    fn test_acknowledged_offsets() {
+        let mut replicas = Replicas::default();
+        let _first = replicas.attach(1, "127.0.0.1:6380".to_string());
+        let _second = replicas.attach(2, "127.0.0.1:6381".to_string());
+        assert_eq!(replicas.acknowledged(0), 2);
+        replicas.acknowledge(1, 120);
+        replicas.acknowledge(2, 80);
+        assert_eq!(replicas.acknowledged(100), 1);
+        assert_eq!(replicas.acknowledged(80), 2);
+        assert_eq!(
+            replicas.to_string(),
+            "slave0:ip=127.0.0.1,port=6380,state=online,offset=120,lag=0\r\n\
+             slave1:ip=127.0.0.1,port=6381,state=online,offset=80,lag=0\r\n"
+        );
+    }
+}
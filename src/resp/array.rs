@@ -1,61 +1,62 @@
 use std::fmt::Display;
 
-use super::{bulkstring::BulkString, ToRedisBytes};
+use super::{bulkstring::BulkString, Protocol, ToRedisBytes, Type};
 
-#[derive(Clone, Debug)]
+/// Represents an array in RESP protocol
+/// An array is a count of elements prefixed with '*' followed by the elements themselves,
+/// which can be of any RESP type, including other arrays.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Array {
-    bulkstrings: Vec<BulkString>,
+    elements: Vec<Type>,
 }
 impl Array {
-    pub const fn bulkstrings(&self) -> &Vec<BulkString> {
-        &self.bulkstrings
+    pub const fn elements(&self) -> &Vec<Type> {
+        &self.elements
     }
-    pub fn from_bytes(buf: &[u8]) -> Self {
-        let binding = String::from_utf8_lossy(buf);
-        let mut message = binding.split("\r\n").map(|s| s.trim().to_string());
-        let length = message
-            .next()
-            .expect("Resp array is missing length")
-            .replace('*', "");
-        let length = length
-            .parse::<usize>()
-            .expect("Resp array has length but is unparsable to int");
-
-        let mut bulks = vec![];
-        for _ in 0..length {
-            bulks.push(BulkString::build_from_iter(&mut message));
+    /// Returns the elements as bulk strings, as sent by clients for commands.
+    ///
+    /// Returns `None` if any element is not a bulk string.
+    pub fn to_bulkstrings(&self) -> Option<Vec<BulkString>> {
+        self.elements
+            .iter()
+            .map(|element| match element {
+                Type::BulkString(bulkstring) => Some(bulkstring.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        let mut buffer = format!("*{}\r\n", self.elements.len()).into_bytes();
+        for element in &self.elements {
+            buffer.extend_from_slice(&element.encode(protocol));
         }
-        Self { bulkstrings: bulks }
+        buffer
     }
     pub fn from_string(s: &str) -> Self {
         let bulkstrings: Vec<BulkString> = s.split_whitespace().map(BulkString::from).collect();
-        Self { bulkstrings }
+        Self::from(bulkstrings)
     }
 }
 impl From<Vec<BulkString>> for Array {
     fn from(bulkstrings: Vec<BulkString>) -> Self {
-        Self { bulkstrings }
+        Self {
+            elements: bulkstrings.into_iter().map(Type::BulkString).collect(),
+        }
+    }
+}
+impl From<Vec<Type>> for Array {
+    fn from(elements: Vec<Type>) -> Self {
+        Self { elements }
     }
 }
 impl Display for Array {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut result = String::new();
-        result.push_str(&format!("*{}\r\n", self.bulkstrings.len()));
-        for bulk in &self.bulkstrings {
-            result.push_str(
-                &bulk
-                    .to_redis_bytes()
-                    .iter()
-                    .map(|&c| c as char)
-                    .collect::<String>(),
-            );
-        }
-        write!(f, "{result}")
+        write!(f, "{}", String::from_utf8_lossy(&self.to_redis_bytes()))
     }
 }
 impl ToRedisBytes for Array {
     fn to_redis_bytes(&self) -> Vec<u8> {
-        format!("{self}").into_bytes()
+        self.encode(Protocol::Resp2)
     }
 }
 
@@ -63,21 +64,41 @@ impl ToRedisBytes for Array {
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
+    use crate::resp::{
+        decoder::{decode, Decoded},
+        SimpleString,
+    };
+
+    fn array_from_bytes(buf: &[u8]) -> Array {
+        match decode(buf).unwrap() {
+            Decoded::Frame(Type::Array(array), _) => array,
+            _ => panic!("Bytes are not a complete resp array"),
+        }
+    }
 
     #[test]
     fn test_array_from_string() {
         let array = Array::from_string("hello world");
-        assert_eq!(array.bulkstrings().len(), 2);
-        assert_eq!(array.bulkstrings()[0].to_string(), "hello");
-        assert_eq!(array.bulkstrings()[1].to_string(), "world");
+        assert_eq!(array.elements().len(), 2);
+        assert_eq!(array.to_bulkstrings().unwrap()[0].to_string(), "hello");
+        assert_eq!(array.to_bulkstrings().unwrap()[1].to_string(), "world");
     }
 
     #[test]
     fn test_array_from_bytes() {
-        let array = Array::from_bytes(b"*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n");
-        assert_eq!(array.bulkstrings().len(), 2);
-        assert_eq!(array.bulkstrings()[0].to_string(), "hello");
-        assert_eq!(array.bulkstrings()[1].to_string(), "world");
+        let array = array_from_bytes(b"*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n");
+        assert_eq!(array.elements().len(), 2);
+        assert_eq!(array.to_bulkstrings().unwrap()[0].to_string(), "hello");
+        assert_eq!(array.to_bulkstrings().unwrap()[1].to_string(), "world");
+    }
+
+    #[test]
+    fn test_array_from_bytes_keeps_whitespace_and_crlf() {
+        let array = array_from_bytes(b"*2\r\n$3\r\nSET\r\n$6\r\n a\r\nb \r\n");
+        assert_eq!(
+            array.to_bulkstrings().unwrap()[1].data().as_ref(),
+            b" a\r\nb "
+        );
     }
 
     #[test]
@@ -89,6 +110,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_nested_array_to_redis_bytes() {
+        let array = Array::from(vec![
+            Type::Integer(1),
+            Type::Array(Array::from_string("a")),
+            Type::Null,
+            Type::NullArray,
+            Type::SimpleString(SimpleString::from("OK")),
+        ]);
+        assert_eq!(
+            array.to_redis_bytes(),
+            b"*5\r\n:1\r\n*1\r\n$1\r\na\r\n$-1\r\n*-1\r\n+OK\r\n".to_vec()
+        );
+        assert!(array.to_bulkstrings().is_none());
+    }
+
     #[test]
     fn test_array_display() {
         let array = Array::from_string("hello world");
@@ -1,73 +1,66 @@
 use std::fmt::Display;
 
+use bytes::Bytes;
+
 use super::ToRedisBytes;
 
+/// Represents a bulk string in RESP protocol
+/// A bulk string is a binary safe payload prefixed by its length: `$<length>\r\n<data>\r\n`
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BulkString {
-    length: usize,
-    data: String,
+    data: Bytes,
 }
 impl BulkString {
     pub const fn length(&self) -> usize {
-        self.length
+        self.data.len()
     }
-    pub fn data(&self) -> String {
-        self.data.to_string()
+    pub const fn data(&self) -> &Bytes {
+        &self.data
     }
-    pub fn build_from_iter(message: &mut impl Iterator<Item = String>) -> Self {
-        let length = message
-            .next()
-            .expect("Bulkstring has no length")
-            .replace('$', "")
-            .parse::<usize>()
-            .expect("Bulkstring has length but cannot be parsed to int");
-        let data = message
-            .next()
-            .expect("Bulkstring has no data")
-            .trim()
-            .to_string();
-        Self { length, data }
+}
+impl From<Bytes> for BulkString {
+    fn from(data: Bytes) -> Self {
+        Self { data }
     }
-    pub fn _from_bytes(buf: &[u8]) -> Self {
-        let binding = String::from_utf8_lossy(buf);
-        let mut message = binding.lines();
-        let length = match message.next().expect("bulkstring bytes has no size") {
-            "+PONG" => {
-                return Self {
-                    length: 4,
-                    data: "PONG".to_string(),
-                }
-            }
-            s => s
-                .parse::<usize>()
-                .expect("Cannot parse bulkstring length as int"),
-        };
-        let data = message
-            .next()
-            .expect("No data found in bulkstring")
-            .trim()
-            .to_string();
-        Self { length, data }
+}
+impl From<&[u8]> for BulkString {
+    fn from(data: &[u8]) -> Self {
+        Self {
+            data: Bytes::copy_from_slice(data),
+        }
     }
 }
 impl From<&str> for BulkString {
     fn from(s: &str) -> Self {
-        Self {
-            length: s.len(),
-            data: s.to_string(),
-        }
+        Self::from(s.as_bytes())
     }
 }
 impl Display for BulkString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.data)
+        write!(f, "{}", String::from_utf8_lossy(&self.data))
     }
 }
 
 impl ToRedisBytes for BulkString {
     fn to_redis_bytes(&self) -> Vec<u8> {
-        format!("${}\r\n{}\r\n", self.length(), &self.data())
-            .as_bytes()
-            .to_vec()
+        let mut buffer = format!("${}\r\n", self.length()).into_bytes();
+        buffer.extend_from_slice(&self.data);
+        buffer.extend_from_slice(b"\r\n");
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulkstring_to_redis_bytes_is_binary_safe() {
+        let bulkstring = BulkString::from(&b"\x00 a\r\nb \xff"[..]);
+        assert_eq!(bulkstring.length(), 8);
+        assert_eq!(
+            bulkstring.to_redis_bytes(),
+            b"$8\r\n\x00 a\r\nb \xff\r\n".to_vec()
+        );
     }
 }
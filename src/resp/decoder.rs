@@ -0,0 +1,789 @@
+use std::fmt::Display;
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use super::{inline, Array, BulkString, SimpleError, SimpleString, ToRedisBytes, Type};
+
+const CRLF: &[u8] = b"\r\n";
+/// First bytes of every RESP2 and RESP3 frame, anything else is read as an inline command.
+const RESP_PREFIXES: &[u8] = b"*$+-:_#,(!=%|~>";
+/// Longest bulk string accepted, the default `proto-max-bulk-len` of Redis.
+const PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Longest inline command or RESP line accepted, like `PROTO_INLINE_MAX_SIZE` in Redis.
+const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+/// Most aggregates a frame may nest, so that building and dropping it stays cheap.
+const MAX_NESTING: usize = 128;
+/// Most elements an aggregate may declare.
+const MAX_MULTIBULK_LEN: i64 = i32::MAX as i64;
+
+/// Result of trying to decode a single frame from the head of a buffer.
+#[derive(Debug)]
+pub enum Decoded {
+    /// The buffer does not hold a complete frame yet, more bytes must be read.
+    Incomplete,
+    /// A complete frame was found, along with the number of bytes it spans.
+    Frame(Type, usize),
+}
+
+/// Decodes the first RESP frame found at the start of `buf`.
+///
+/// Nothing is consumed: the caller is told how many bytes the frame used and is
+/// responsible for advancing its own buffer.
+///
+/// # Errors
+///
+/// Returns a `ProtocolError` if the bytes are not valid RESP nor a valid inline command.
+pub fn decode(buf: &[u8]) -> Result<Decoded, ProtocolError> {
+    let mut progress = Progress::default();
+    Ok(progress.resume(buf)?.map_or(Decoded::Incomplete, |frame| {
+        Decoded::Frame(frame, progress.position)
+    }))
+}
+
+/// How far the frame at the head of a buffer has been decoded, kept between reads so that
+/// a frame arriving in many pieces is not decoded again from its start every time.
+#[derive(Debug, Default)]
+struct Progress {
+    /// Number of bytes of the buffer already decoded into `pending`.
+    position: usize,
+    /// The aggregates whose elements are being decoded, the innermost last.
+    pending: Vec<Pending>,
+}
+impl Progress {
+    /// Decodes as much of `buf` as possible from where the last call stopped, and returns
+    /// the frame once complete, `position` then being the number of bytes it spans.
+    ///
+    /// Aggregates are decoded with an explicit stack rather than recursively, so that a
+    /// deeply nested frame cannot exhaust the stack.
+    fn resume(&mut self, buf: &[u8]) -> Result<Option<Type>, ProtocolError> {
+        loop {
+            let mut cursor = Cursor {
+                buf,
+                position: self.position,
+            };
+            let item = match cursor.item(self.pending.is_empty()) {
+                Ok(item) => item,
+                Err(Failure::Incomplete) => return Ok(None),
+                Err(Failure::Invalid(error)) => return Err(error),
+            };
+            self.position = cursor.position;
+            let mut frame = match item {
+                Item::Blank => continue,
+                Item::Frame(frame) => frame,
+                Item::Aggregate(prefix, 0) => Pending::new(prefix, 0).into_frame(),
+                Item::Aggregate(prefix, length) => {
+                    if self.pending.len() >= MAX_NESTING {
+                        return Err(ProtocolError::TooDeeplyNested);
+                    }
+                    self.pending.push(Pending::new(prefix, length));
+                    continue;
+                }
+            };
+            loop {
+                let Some(parent) = self.pending.last_mut() else {
+                    return Ok(Some(frame));
+                };
+                parent.elements.push(frame);
+                if parent.elements.len() < parent.length {
+                    break;
+                }
+                frame = self
+                    .pending
+                    .pop()
+                    .expect("The parent is pending")
+                    .into_frame();
+            }
+        }
+    }
+}
+
+/// An aggregate waiting for the rest of its elements.
+#[derive(Debug)]
+struct Pending {
+    prefix: u8,
+    /// Number of elements expected, twice the number of pairs for maps and attributes.
+    length: usize,
+    elements: Vec<Type>,
+}
+impl Pending {
+    fn new(prefix: u8, length: usize) -> Self {
+        Self {
+            prefix,
+            length,
+            elements: Vec::with_capacity(length.min(1024)),
+        }
+    }
+
+    fn into_frame(self) -> Type {
+        let pairs = |elements: Vec<Type>| {
+            let mut elements = elements.into_iter();
+            let mut pairs = vec![];
+            while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+                pairs.push((key, value));
+            }
+            pairs
+        };
+        match self.prefix {
+            b'%' => Type::Map(pairs(self.elements)),
+            b'|' => Type::Attribute(pairs(self.elements)),
+            b'~' => Type::Set(self.elements),
+            b'>' => Type::Push(self.elements),
+            _ => Type::Array(Array::from(self.elements)),
+        }
+    }
+}
+
+/// Accumulates bytes read from a connection and hands out complete frames.
+///
+/// A single read may contain several pipelined commands, or only part of one: the
+/// decoder keeps whatever has not been consumed yet until the next read completes it.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: BytesMut,
+    progress: Progress,
+}
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends freshly read bytes to the pending buffer.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame along with the bytes it was decoded from, or `None`
+    /// if more bytes are needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ProtocolError` if the pending bytes are malformed. There is no way to
+    /// find where the next frame starts after that, so the pending bytes are discarded.
+    pub fn next_frame(&mut self) -> Result<Option<(Type, Bytes)>, ProtocolError> {
+        match self.progress.resume(&self.buffer) {
+            Ok(Some(frame)) => {
+                let length = std::mem::take(&mut self.progress).position;
+                Ok(Some((frame, self.buffer.split_to(length).freeze())))
+            }
+            Ok(None) => Ok(None),
+            Err(error) => {
+                self.buffer.clear();
+                self.progress = Progress::default();
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the RDB payload a master sends after `FULLRESYNC`, or `None` if more bytes
+    /// are needed.
+    ///
+    /// The payload is framed as `$<length>\r\n` like a bulk string, but is not followed by
+    /// CRLF. Newlines the master sends to keep the link alive while it prepares the
+    /// payload are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ProtocolError` if the pending bytes do not start with a payload length.
+    pub fn next_rdb_payload(&mut self) -> Result<Option<Bytes>, ProtocolError> {
+        let mut cursor = Cursor {
+            buf: &self.buffer,
+            position: 0,
+        };
+        match cursor.rdb_payload() {
+            Ok(payload) => {
+                self.buffer.advance(cursor.position);
+                Ok(Some(payload))
+            }
+            Err(Failure::Incomplete) => Ok(None),
+            Err(Failure::Invalid(error)) => {
+                self.buffer.clear();
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Malformed bytes received on a connection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The frame starts with a byte that is not a RESP type prefix.
+    UnexpectedPrefix(u8),
+    /// An aggregate length is not a valid integer.
+    InvalidMultibulkLength,
+    /// A bulk string or blob length is not a valid integer.
+    InvalidBulkLength,
+    /// Bulk data is not followed by CRLF right after its declared length.
+    BulkLengthMismatch,
+    InvalidInteger,
+    InvalidDouble,
+    InvalidBoolean,
+    /// A verbatim string has no `<format>:` header.
+    InvalidVerbatimString,
+    /// An inline command has an unterminated quoted argument.
+    UnbalancedQuotes,
+    /// A command was sent as something else than an array of bulk strings.
+    ExpectedBulkStrings,
+    /// Aggregates are nested deeper than `MAX_NESTING`.
+    TooDeeplyNested,
+    /// An inline command is longer than `PROTO_INLINE_MAX_SIZE`.
+    TooBigInlineRequest,
+    /// A line of a frame is longer than `PROTO_INLINE_MAX_SIZE`.
+    TooBigLine,
+}
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Protocol error: ")?;
+        match self {
+            Self::UnexpectedPrefix(prefix) => {
+                write!(f, "unexpected type prefix '{}'", prefix.escape_ascii())
+            }
+            Self::InvalidMultibulkLength => write!(f, "invalid multibulk length"),
+            Self::InvalidBulkLength => write!(f, "invalid bulk length"),
+            Self::BulkLengthMismatch => write!(f, "bulk data does not match its length"),
+            Self::InvalidInteger => write!(f, "invalid integer"),
+            Self::InvalidDouble => write!(f, "invalid double"),
+            Self::InvalidBoolean => write!(f, "invalid boolean"),
+            Self::InvalidVerbatimString => write!(f, "invalid verbatim string"),
+            Self::UnbalancedQuotes => write!(f, "unbalanced quotes in request"),
+            Self::ExpectedBulkStrings => write!(f, "expected an array of bulk strings"),
+            Self::TooDeeplyNested => write!(f, "too many nested aggregates"),
+            Self::TooBigInlineRequest => write!(f, "too big inline request"),
+            Self::TooBigLine => write!(f, "too big line"),
+        }
+    }
+}
+impl ToRedisBytes for ProtocolError {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        SimpleError::from(format!("ERR {self}").as_str()).to_redis_bytes()
+    }
+}
+
+/// Why a frame could not be read from the cursor.
+enum Failure {
+    Incomplete,
+    Invalid(ProtocolError),
+}
+impl From<ProtocolError> for Failure {
+    fn from(error: ProtocolError) -> Self {
+        Self::Invalid(error)
+    }
+}
+
+/// What `Cursor::item` read.
+enum Item {
+    /// An empty inline command line, which is skipped.
+    Blank,
+    Frame(Type),
+    /// The prefix of an aggregate and the number of elements that follow.
+    Aggregate(u8, usize),
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+impl Cursor<'_> {
+    /// Reads the next item of a frame: a frame that is not an aggregate, or the header of
+    /// an aggregate whose elements follow. At the top level, inline commands are read too.
+    fn item(&mut self, top_level: bool) -> Result<Item, Failure> {
+        let first = *self.buf.get(self.position).ok_or(Failure::Incomplete)?;
+        if top_level && !RESP_PREFIXES.contains(&first) {
+            let arguments = self.inline()?;
+            if arguments.is_empty() {
+                return Ok(Item::Blank);
+            }
+            return Ok(Item::Frame(Type::Array(Array::from(
+                arguments
+                    .into_iter()
+                    .map(BulkString::from)
+                    .collect::<Vec<BulkString>>(),
+            ))));
+        }
+        let frame = match self.byte()? {
+            prefix @ (b'*' | b'~' | b'>') => return self.aggregate(prefix, 1),
+            prefix @ (b'%' | b'|') => return self.aggregate(prefix, 2),
+            b'$' => self.bulkstring()?,
+            b'+' => Type::SimpleString(SimpleString::from(self.text()?.as_str())),
+            b'-' => Type::Error(SimpleError::from(self.text()?.as_str())),
+            b':' => Type::Integer(self.integer()?),
+            b'_' => self.line().map(|_| Type::Null)?,
+            b'#' => Type::Boolean(self.boolean()?),
+            b',' => Type::Double(self.double()?),
+            b'(' => Type::BigNumber(self.text()?),
+            b'!' => Type::BlobError(self.blob()?),
+            b'=' => self.verbatim()?,
+            prefix => return Err(ProtocolError::UnexpectedPrefix(prefix).into()),
+        };
+        Ok(Item::Frame(frame))
+    }
+
+    /// Reads the header of an aggregate of entries of `per_entry` elements each, a
+    /// negative length being a null array in RESP2.
+    fn aggregate(&mut self, prefix: u8, per_entry: usize) -> Result<Item, Failure> {
+        let length = self.length(ProtocolError::InvalidMultibulkLength)?;
+        if prefix == b'*' && length < 0 {
+            return Ok(Item::Frame(Type::NullArray));
+        }
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|_| length <= MAX_MULTIBULK_LEN)
+            .ok_or(ProtocolError::InvalidMultibulkLength)?;
+        Ok(Item::Aggregate(prefix, length * per_entry))
+    }
+
+    /// Reads an inline command line, blank lines having no arguments.
+    fn inline(&mut self) -> Result<Vec<Bytes>, Failure> {
+        let start = self.position;
+        let Some(end) = self.buf[start..].iter().position(|&byte| byte == b'\n') else {
+            if self.buf.len() - start > PROTO_INLINE_MAX_SIZE {
+                return Err(ProtocolError::TooBigInlineRequest.into());
+            }
+            return Err(Failure::Incomplete);
+        };
+        if end > PROTO_INLINE_MAX_SIZE {
+            return Err(ProtocolError::TooBigInlineRequest.into());
+        }
+        let end = end + start;
+        self.position = end + 1;
+        let line = &self.buf[start..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        Ok(inline::split_arguments(line).ok_or(ProtocolError::UnbalancedQuotes)?)
+    }
+
+    fn bulkstring(&mut self) -> Result<Type, Failure> {
+        let Ok(length) = usize::try_from(self.length(ProtocolError::InvalidBulkLength)?) else {
+            return Ok(Type::Null);
+        };
+        if length > PROTO_MAX_BULK_LEN {
+            return Err(ProtocolError::InvalidBulkLength.into());
+        }
+        self.data(length)
+            .map(|data| Type::BulkString(BulkString::from(data)))
+    }
+
+    fn blob(&mut self) -> Result<Bytes, Failure> {
+        let length = usize::try_from(self.length(ProtocolError::InvalidBulkLength)?)
+            .ok()
+            .filter(|length| *length <= PROTO_MAX_BULK_LEN)
+            .ok_or(ProtocolError::InvalidBulkLength)?;
+        self.data(length)
+    }
+
+    fn rdb_payload(&mut self) -> Result<Bytes, Failure> {
+        let mut prefix = self.byte()?;
+        while prefix == b'\n' {
+            prefix = self.byte()?;
+        }
+        if prefix != b'$' {
+            return Err(ProtocolError::UnexpectedPrefix(prefix).into());
+        }
+        let length = usize::try_from(self.length(ProtocolError::InvalidBulkLength)?)
+            .map_err(|_| ProtocolError::InvalidBulkLength)?;
+        let end = self.position + length;
+        if self.buf.len() < end {
+            return Err(Failure::Incomplete);
+        }
+        let payload = Bytes::copy_from_slice(&self.buf[self.position..end]);
+        self.position = end;
+        Ok(payload)
+    }
+
+    fn verbatim(&mut self) -> Result<Type, Failure> {
+        let blob = self.blob()?;
+        let separator = blob
+            .iter()
+            .position(|&byte| byte == b':')
+            .ok_or(ProtocolError::InvalidVerbatimString)?;
+        Ok(Type::VerbatimString {
+            format: String::from_utf8_lossy(&blob[..separator]).to_string(),
+            data: blob.slice(separator + 1..),
+        })
+    }
+
+    fn data(&mut self, length: usize) -> Result<Bytes, Failure> {
+        let end = self.position + length;
+        if self.buf.len() < end + CRLF.len() {
+            return Err(Failure::Incomplete);
+        }
+        if &self.buf[end..end + CRLF.len()] != CRLF {
+            return Err(ProtocolError::BulkLengthMismatch.into());
+        }
+        let data = Bytes::copy_from_slice(&self.buf[self.position..end]);
+        self.position = end + CRLF.len();
+        Ok(data)
+    }
+
+    fn boolean(&mut self) -> Result<bool, Failure> {
+        match self.line()? {
+            b"t" => Ok(true),
+            b"f" => Ok(false),
+            _ => Err(ProtocolError::InvalidBoolean.into()),
+        }
+    }
+
+    fn double(&mut self) -> Result<f64, Failure> {
+        Ok(self
+            .text()?
+            .parse::<f64>()
+            .map_err(|_| ProtocolError::InvalidDouble)?)
+    }
+
+    fn text(&mut self) -> Result<String, Failure> {
+        self.line()
+            .map(|line| String::from_utf8_lossy(line).to_string())
+    }
+
+    fn integer(&mut self) -> Result<i64, Failure> {
+        self.length(ProtocolError::InvalidInteger)
+    }
+
+    /// Reads an integer line, reporting `error` if it cannot be parsed.
+    fn length(&mut self, error: ProtocolError) -> Result<i64, Failure> {
+        Ok(self.text()?.parse::<i64>().map_err(|_| error)?)
+    }
+
+    fn byte(&mut self) -> Result<u8, Failure> {
+        let byte = *self.buf.get(self.position).ok_or(Failure::Incomplete)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn line(&mut self) -> Result<&[u8], Failure> {
+        let start = self.position;
+        let Some(end) = self.buf[start..]
+            .windows(CRLF.len())
+            .position(|window| window == CRLF)
+        else {
+            if self.buf.len() - start > PROTO_INLINE_MAX_SIZE {
+                return Err(ProtocolError::TooBigLine.into());
+            }
+            return Err(Failure::Incomplete);
+        };
+        if end > PROTO_INLINE_MAX_SIZE {
+            return Err(ProtocolError::TooBigLine.into());
+        }
+        let end = end + start;
+        self.position = end + CRLF.len();
+        Ok(&self.buf[start..end])
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn array_of(frame: Type) -> Vec<String> {
+        match frame {
+            Type::Array(array) => array
+                .to_bulkstrings()
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            _ => panic!("Expected an array frame"),
+        }
+    }
+
+    #[test]
+    fn test_decode_complete_array() {
+        let buf = b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n";
+        let Decoded::Frame(frame, consumed) = decode(buf).unwrap() else {
+            panic!("Expected a complete frame");
+        };
+        assert_eq!(consumed, buf.len());
+        assert_eq!(array_of(frame), vec!["ECHO", "hello"]);
+    }
+
+    #[test]
+    fn test_decode_incomplete_frames() {
+        let buf = b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n";
+        for end in 0..buf.len() {
+            assert!(
+                matches!(decode(&buf[..end]), Ok(Decoded::Incomplete)),
+                "{end} bytes should not be a complete frame"
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_binary_bulkstring() {
+        let Decoded::Frame(Type::BulkString(bulkstring), consumed) =
+            decode(b"$6\r\n\r\n\x00\xff\r\n\r\n").unwrap()
+        else {
+            panic!("Expected a bulkstring frame");
+        };
+        assert_eq!(bulkstring.data().as_ref(), b"\r\n\x00\xff\r\n");
+        assert_eq!(consumed, 12);
+    }
+
+    #[test]
+    fn test_decode_simplestring() {
+        let Decoded::Frame(Type::SimpleString(simplestring), consumed) =
+            decode(b"+PONG\r\n+OK").unwrap()
+        else {
+            panic!("Expected a simplestring frame");
+        };
+        assert_eq!(simplestring.data(), "PONG");
+        assert_eq!(consumed, 7);
+    }
+
+    #[test]
+    fn test_decode_errors_integers_and_nulls() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"-ERR unknown command\r\n:-42\r\n$-1\r\n*-1\r\n");
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Error(SimpleError::from("ERR unknown command")))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Integer(-42))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Null)
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::NullArray)
+        );
+        assert_eq!(decoder.next_frame().unwrap().map(|(frame, _)| frame), None);
+    }
+
+    #[test]
+    fn test_decode_resp3_types() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"%1\r\n+proto\r\n:3\r\n_\r\n#t\r\n,-1.5\r\n(123\r\n");
+        decoder.extend(b"=9\r\ntxt:hello\r\n~1\r\n:1\r\n>1\r\n!3\r\nERR\r\n");
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Map(vec![(
+                Type::SimpleString(SimpleString::from("proto")),
+                Type::Integer(3)
+            )]))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Null)
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Boolean(true))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Double(-1.5))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::BigNumber("123".to_string()))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::VerbatimString {
+                format: "txt".to_string(),
+                data: Bytes::from("hello")
+            })
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Set(vec![Type::Integer(1)]))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap().map(|(frame, _)| frame),
+            Some(Type::Push(vec![Type::BlobError(Bytes::from("ERR"))]))
+        );
+        assert_eq!(decoder.next_frame().unwrap().map(|(frame, _)| frame), None);
+    }
+
+    #[test]
+    fn test_decode_nested_array() {
+        let buf = b"*2\r\n*2\r\n:1\r\n$1\r\na\r\n*0\r\n";
+        let Decoded::Frame(frame, consumed) = decode(buf).unwrap() else {
+            panic!("Expected a complete frame");
+        };
+        assert_eq!(consumed, buf.len());
+        assert_eq!(
+            frame,
+            Type::Array(Array::from(vec![
+                Type::Array(Array::from(vec![
+                    Type::Integer(1),
+                    Type::BulkString(BulkString::from("a")),
+                ])),
+                Type::Array(Array::from(Vec::<Type>::new())),
+            ]))
+        );
+        assert!(matches!(
+            decode(&buf[..buf.len() - 1]),
+            Ok(Decoded::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn test_decode_inline_commands() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"\r\nSET foo \"hello world\"\r\nPING\nGET");
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["SET", "foo", "hello world"]
+        );
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.extend(b" foo\r\n*1\r\n$4\r\nPING\r\n");
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["GET", "foo"]
+        );
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+    }
+
+    #[test]
+    fn test_decode_protocol_errors() {
+        assert_eq!(
+            decode(b"*x\r\n").unwrap_err(),
+            ProtocolError::InvalidMultibulkLength
+        );
+        assert_eq!(
+            decode(b"$3\r\nfoobar\r\n").unwrap_err(),
+            ProtocolError::BulkLengthMismatch
+        );
+        assert_eq!(
+            decode(b"*1\r\n?\r\n").unwrap_err(),
+            ProtocolError::UnexpectedPrefix(b'?')
+        );
+        assert_eq!(
+            decode(b"SET \"foo\r\n").unwrap_err(),
+            ProtocolError::UnbalancedQuotes
+        );
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let nested = b"*1\r\n".repeat(100_000);
+        assert_eq!(decode(&nested).unwrap_err(), ProtocolError::TooDeeplyNested);
+        let mut nested = b"*1\r\n".repeat(MAX_NESTING);
+        nested.extend_from_slice(b":1\r\n");
+        assert!(matches!(decode(&nested), Ok(Decoded::Frame(_, _))));
+        assert_eq!(
+            decode(b"$536870913\r\n").unwrap_err(),
+            ProtocolError::InvalidBulkLength
+        );
+        assert_eq!(
+            decode(b"*4294967296\r\n").unwrap_err(),
+            ProtocolError::InvalidMultibulkLength
+        );
+        assert_eq!(
+            decode(&b"A".repeat(PROTO_INLINE_MAX_SIZE + 1)).unwrap_err(),
+            ProtocolError::TooBigInlineRequest
+        );
+        let mut line = b"+".to_vec();
+        line.extend_from_slice(&b"A".repeat(PROTO_INLINE_MAX_SIZE + 1));
+        assert_eq!(decode(&line).unwrap_err(), ProtocolError::TooBigLine);
+    }
+
+    #[test]
+    fn test_decoder_keeps_progress_between_reads() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"*2\r\n%1\r\n+key\r\n*2\r\n:1\r\n");
+        assert!(decoder.next_frame().unwrap().is_none());
+        assert_eq!(decoder.progress.pending.len(), 3);
+        let position = decoder.progress.position;
+        decoder.extend(b":2\r\n");
+        assert!(decoder.next_frame().unwrap().is_none());
+        assert!(decoder.progress.position > position);
+        decoder.extend(b"$3\r\nend\r\n+OK\r\n");
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap().0,
+            Type::Array(Array::from(vec![
+                Type::Map(vec![(
+                    Type::SimpleString(SimpleString::from("key")),
+                    Type::Array(Array::from(vec![Type::Integer(1), Type::Integer(2)])),
+                )]),
+                Type::BulkString(BulkString::from(Bytes::from("end"))),
+            ]))
+        );
+        assert_eq!(decoder.progress.position, 0);
+        assert!(decoder.progress.pending.is_empty());
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap().0,
+            Type::SimpleString(SimpleString::from("OK"))
+        );
+    }
+
+    #[test]
+    fn test_decoder_recovers_after_protocol_error() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"$x\r\nfoo\r\n");
+        assert!(decoder.next_frame().is_err());
+        decoder.extend(b"PING\r\n");
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+    }
+
+    #[test]
+    fn test_decoder_pipelined_commands() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n*1\r\n$4\r\nPI");
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["GET", "foo"]
+        );
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.extend(b"NG\r\n");
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+    }
+
+    #[test]
+    fn test_decoder_frame_split_across_reads() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo");
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.extend(b"\r\n$3\r\nba");
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.extend(b"r\r\n*1\r\n");
+        let (frame, raw) = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(array_of(frame), vec!["SET", "foo", "bar"]);
+        assert_eq!(
+            raw,
+            Bytes::from("*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        );
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decoder_rdb_payload_without_trailing_crlf() {
+        let mut decoder = Decoder::new();
+        decoder.extend(b"\n\n$5\r\nRE");
+        assert!(decoder.next_rdb_payload().unwrap().is_none());
+        decoder.extend(b"DIS*1\r\n$4\r\nPING\r\n");
+        assert_eq!(
+            decoder.next_rdb_payload().unwrap().unwrap(),
+            Bytes::from("REDIS")
+        );
+        assert_eq!(
+            array_of(decoder.next_frame().unwrap().unwrap().0),
+            vec!["PING"]
+        );
+        decoder.extend(b"+OK\r\n");
+        assert_eq!(
+            decoder.next_rdb_payload(),
+            Err(ProtocolError::UnexpectedPrefix(b'+'))
+        );
+    }
+}
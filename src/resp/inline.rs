@@ -0,0 +1,142 @@
+//! Inline commands, as typed by hand in `telnet` or `nc`: `SET foo "hello world"\r\n`.
+//!
+//! Arguments are separated by whitespace and may be quoted following the same rules as
+//! `redis-cli`: double quotes support escape sequences (`\n`, `\r`, `\t`, `\b`, `\a`,
+//! `\\`, `\"` and `\xHH`), single quotes only support `\'`.
+
+use bytes::Bytes;
+
+/// Splits an inline command line into its arguments.
+///
+/// Returns `None` if the line has unbalanced quotes, or a closing quote that is not
+/// followed by a space.
+pub fn split_arguments(line: &[u8]) -> Option<Vec<Bytes>> {
+    let mut arguments = vec![];
+    let mut position = 0;
+    loop {
+        while line.get(position).is_some_and(u8::is_ascii_whitespace) {
+            position += 1;
+        }
+        let Some(&first) = line.get(position) else {
+            return Some(arguments);
+        };
+        let mut argument = vec![];
+        match first {
+            b'"' => position = double_quoted(line, position + 1, &mut argument)?,
+            b'\'' => position = single_quoted(line, position + 1, &mut argument)?,
+            _ => {
+                while let Some(&byte) = line.get(position) {
+                    if byte.is_ascii_whitespace() {
+                        break;
+                    }
+                    argument.push(byte);
+                    position += 1;
+                }
+            }
+        }
+        arguments.push(Bytes::from(argument));
+    }
+}
+
+/// Reads a double quoted argument starting right after the opening quote and returns
+/// the position following the closing quote.
+fn double_quoted(line: &[u8], mut position: usize, argument: &mut Vec<u8>) -> Option<usize> {
+    loop {
+        match *line.get(position)? {
+            b'\\' => {
+                let escaped = *line.get(position + 1)?;
+                if escaped == b'x' {
+                    if let Some(byte) = line
+                        .get(position + 2..position + 4)
+                        .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    {
+                        argument.push(byte);
+                        position += 4;
+                        continue;
+                    }
+                }
+                argument.push(match escaped {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'b' => 0x08,
+                    b'a' => 0x07,
+                    other => other,
+                });
+                position += 2;
+            }
+            b'"' => return closing_quote(line, position),
+            byte => {
+                argument.push(byte);
+                position += 1;
+            }
+        }
+    }
+}
+
+/// Reads a single quoted argument starting right after the opening quote and returns
+/// the position following the closing quote.
+fn single_quoted(line: &[u8], mut position: usize, argument: &mut Vec<u8>) -> Option<usize> {
+    loop {
+        match *line.get(position)? {
+            b'\\' if line.get(position + 1) == Some(&b'\'') => {
+                argument.push(b'\'');
+                position += 2;
+            }
+            b'\'' => return closing_quote(line, position),
+            byte => {
+                argument.push(byte);
+                position += 1;
+            }
+        }
+    }
+}
+
+fn closing_quote(line: &[u8], position: usize) -> Option<usize> {
+    match line.get(position + 1) {
+        None => Some(position + 1),
+        Some(byte) if byte.is_ascii_whitespace() => Some(position + 1),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_plain_arguments() {
+        let arguments = split_arguments(b"  SET foo   bar ").unwrap();
+        assert_eq!(arguments, vec!["SET", "foo", "bar"]);
+    }
+
+    #[test]
+    fn test_split_quoted_arguments() {
+        let arguments = split_arguments(br#"SET "hello world\n\x41" 'it\'s' """#).unwrap();
+        assert_eq!(
+            arguments,
+            vec![
+                Bytes::from("SET"),
+                Bytes::from("hello world\nA"),
+                Bytes::from("it's"),
+                Bytes::new()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_invalid_hex_escapes() {
+        let arguments = split_arguments(br#""\x+f" "\x4g" "\x4""#).unwrap();
+        assert_eq!(arguments, vec!["x+f", "x4g", "x4"]);
+    }
+
+    #[test]
+    fn test_split_unbalanced_quotes() {
+        assert!(split_arguments(br#"SET "foo bar"#).is_none());
+        assert!(split_arguments(b"SET 'foo").is_none());
+        assert!(split_arguments(br#"SET "foo"bar"#).is_none());
+    }
+}
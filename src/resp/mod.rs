@@ -1,8 +1,16 @@
-pub use self::{array::Array, bulkstring::BulkString, simple_string::SimpleString};
+use bytes::Bytes;
+
+pub use self::{
+    array::Array, bulkstring::BulkString, decoder::Decoder, simple_error::SimpleError,
+    simple_string::SimpleString,
+};
 
 pub mod array;
 pub mod bulkstring;
+pub mod decoder;
+pub mod inline;
 pub mod redis_response;
+pub mod simple_error;
 pub mod simple_string;
 
 pub trait ToRedisBytes {
@@ -16,35 +24,177 @@ impl ToRedisBytes for String {
     }
 }
 
+/// Version of the RESP protocol spoken on a connection, negotiated with `HELLO`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+impl Protocol {
+    pub const fn version(self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
+}
+
+/// Every RESP frame, as read from or written to a connection.
+///
+/// RESP3 only variants are downgraded to their closest RESP2 equivalent when encoded for
+/// a RESP2 connection.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     Array(Array),
     BulkString(BulkString),
     SimpleString(SimpleString),
+    Error(SimpleError),
+    Integer(i64),
+    /// The null bulk string `$-1\r\n`, or `_\r\n` in RESP3.
+    Null,
+    /// The null array `*-1\r\n`, or `_\r\n` in RESP3.
+    NullArray,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+    BlobError(Bytes),
+    VerbatimString {
+        format: String,
+        data: Bytes,
+    },
+    Map(Vec<(Self, Self)>),
+    Set(Vec<Self>),
+    Attribute(Vec<(Self, Self)>),
+    Push(Vec<Self>),
 }
-
 impl Type {
-    pub fn from_bytes(buf: &[u8]) -> Self {
-        match buf[0] {
-            b'*' => Self::Array(Array::from_bytes(buf)),
-            b'$' => Self::BulkString(BulkString::_from_bytes(buf)),
-            b'+' => Self::SimpleString(SimpleString::from_bytes(buf)),
-            _ => panic!(
-                "Cannot define command type of '{}'",
-                String::from_utf8_lossy(buf)
+    /// Encodes the frame for a connection speaking `protocol`.
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        match (self, protocol) {
+            (Self::Array(array), _) => array.encode(protocol),
+            (Self::BulkString(bulkstring), _) => bulkstring.to_redis_bytes(),
+            (Self::SimpleString(simplestring), _) => simplestring.to_redis_bytes(),
+            (Self::Error(error), _) => error.to_redis_bytes(),
+            (Self::Integer(integer), _) => format!(":{integer}\r\n").into_bytes(),
+            (Self::Null | Self::NullArray, Protocol::Resp3) => b"_\r\n".to_vec(),
+            (Self::Null, Protocol::Resp2) => b"$-1\r\n".to_vec(),
+            (Self::NullArray, Protocol::Resp2) => b"*-1\r\n".to_vec(),
+            (Self::Boolean(boolean), Protocol::Resp3) => {
+                format!("#{}\r\n", if *boolean { 't' } else { 'f' }).into_bytes()
+            }
+            (Self::Boolean(boolean), Protocol::Resp2) => {
+                Self::Integer(i64::from(*boolean)).encode(protocol)
+            }
+            (Self::Double(double), Protocol::Resp3) => {
+                format!(",{}\r\n", format_double(*double)).into_bytes()
+            }
+            (Self::Double(double), Protocol::Resp2) => {
+                BulkString::from(format_double(*double).as_str()).to_redis_bytes()
+            }
+            (Self::BigNumber(number), Protocol::Resp3) => format!("({number}\r\n").into_bytes(),
+            (Self::BigNumber(number), Protocol::Resp2) => {
+                BulkString::from(number.as_str()).to_redis_bytes()
+            }
+            (Self::BlobError(error), Protocol::Resp3) => {
+                encode_blob(b'!', error.len(), [error.as_ref()])
+            }
+            (Self::BlobError(error), Protocol::Resp2) => {
+                let error = String::from_utf8_lossy(error).replace(['\r', '\n'], " ");
+                SimpleError::from(error.as_str()).to_redis_bytes()
+            }
+            (Self::VerbatimString { format, data }, Protocol::Resp3) => encode_blob(
+                b'=',
+                format.len() + 1 + data.len(),
+                [format.as_bytes(), b":", data.as_ref()],
             ),
+            (Self::VerbatimString { data, .. }, Protocol::Resp2) => {
+                BulkString::from(data.clone()).to_redis_bytes()
+            }
+            (Self::Map(pairs), Protocol::Resp3) => encode_pairs(b'%', pairs, protocol),
+            (Self::Attribute(pairs), Protocol::Resp3) => encode_pairs(b'|', pairs, protocol),
+            (Self::Map(pairs), Protocol::Resp2) => {
+                let mut buffer = format!("*{}\r\n", pairs.len() * 2).into_bytes();
+                for (key, value) in pairs {
+                    buffer.extend_from_slice(&key.encode(protocol));
+                    buffer.extend_from_slice(&value.encode(protocol));
+                }
+                buffer
+            }
+            (Self::Attribute(_), Protocol::Resp2) => vec![],
+            (Self::Set(elements), Protocol::Resp3) => encode_aggregate(b'~', elements, protocol),
+            (Self::Push(elements), Protocol::Resp3) => encode_aggregate(b'>', elements, protocol),
+            (Self::Set(elements) | Self::Push(elements), Protocol::Resp2) => {
+                encode_aggregate(b'*', elements, protocol)
+            }
+        }
+    }
+}
+impl ToRedisBytes for Type {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        self.encode(Protocol::Resp2)
+    }
+}
+
+fn format_double(double: f64) -> String {
+    if double.is_nan() {
+        "nan".to_string()
+    } else if double.is_infinite() {
+        if double.is_sign_positive() {
+            "inf"
+        } else {
+            "-inf"
         }
+        .to_string()
+    } else {
+        double.to_string()
+    }
+}
+
+fn encode_blob<'a>(
+    prefix: u8,
+    length: usize,
+    parts: impl IntoIterator<Item = &'a [u8]>,
+) -> Vec<u8> {
+    let mut buffer = vec![prefix];
+    buffer.extend_from_slice(format!("{length}\r\n").as_bytes());
+    for part in parts {
+        buffer.extend_from_slice(part);
+    }
+    buffer.extend_from_slice(b"\r\n");
+    buffer
+}
+
+fn encode_aggregate(prefix: u8, elements: &[Type], protocol: Protocol) -> Vec<u8> {
+    let mut buffer = vec![prefix];
+    buffer.extend_from_slice(format!("{}\r\n", elements.len()).as_bytes());
+    for element in elements {
+        buffer.extend_from_slice(&element.encode(protocol));
     }
+    buffer
 }
 
+fn encode_pairs(prefix: u8, pairs: &[(Type, Type)], protocol: Protocol) -> Vec<u8> {
+    let mut buffer = vec![prefix];
+    buffer.extend_from_slice(format!("{}\r\n", pairs.len()).as_bytes());
+    for (key, value) in pairs {
+        buffer.extend_from_slice(&key.encode(protocol));
+        buffer.extend_from_slice(&value.encode(protocol));
+    }
+    buffer
+}
+
+/// RDB payload sent to a replica after `FULLRESYNC`, framed as `$<length>\r\n<content>`
+/// without a trailing CRLF.
 pub struct RDBFile {
-    hex_content: String,
+    content: Bytes,
 }
 impl RDBFile {
-    pub fn empty_file() -> Self {
-        Self{hex_content: "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2".to_string()}
+    pub const fn new(content: Bytes) -> Self {
+        Self { content }
     }
-    pub fn length(&self) -> usize {
-        self.hex_content.len() / 2
+    pub const fn length(&self) -> usize {
+        self.content.len()
     }
 }
 impl ToRedisBytes for RDBFile {
@@ -53,40 +203,103 @@ impl ToRedisBytes for RDBFile {
         buffer.extend_from_slice(b"$");
         buffer.extend_from_slice(self.length().to_string().as_bytes());
         buffer.extend_from_slice(b"\r\n");
+        buffer.extend_from_slice(&self.content);
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let content: Vec<u8> = (0..self.hex_content.len())
+    /// Empty RDB file sent by Redis 7.2.
+    fn empty_file() -> RDBFile {
+        let hex_content = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+        let content: Vec<u8> = (0..hex_content.len())
             .step_by(2)
             .map(|i| {
-                u8::from_str_radix(&self.hex_content[i..i + 2], 16)
+                u8::from_str_radix(&hex_content[i..i + 2], 16)
                     .expect("Conversion hexadécimale invalide")
             })
             .collect();
+        RDBFile::new(Bytes::from(content))
+    }
 
-        buffer.extend_from_slice(&content);
-        buffer
+    #[test]
+    fn test_encode_resp3_types() {
+        let map = Type::Map(vec![(
+            Type::SimpleString(SimpleString::from("proto")),
+            Type::Integer(3),
+        )]);
+        assert_eq!(
+            map.encode(Protocol::Resp3),
+            b"%1\r\n+proto\r\n:3\r\n".to_vec()
+        );
+        assert_eq!(Type::Null.encode(Protocol::Resp3), b"_\r\n".to_vec());
+        assert_eq!(
+            Type::Boolean(true).encode(Protocol::Resp3),
+            b"#t\r\n".to_vec()
+        );
+        assert_eq!(
+            Type::Double(1.5).encode(Protocol::Resp3),
+            b",1.5\r\n".to_vec()
+        );
+        assert_eq!(
+            Type::Double(f64::NEG_INFINITY).encode(Protocol::Resp3),
+            b",-inf\r\n".to_vec()
+        );
+        assert_eq!(
+            Type::VerbatimString {
+                format: "txt".to_string(),
+                data: Bytes::from("Some string"),
+            }
+            .encode(Protocol::Resp3),
+            b"=15\r\ntxt:Some string\r\n".to_vec()
+        );
+        assert_eq!(
+            Type::Set(vec![Type::Integer(1)]).encode(Protocol::Resp3),
+            b"~1\r\n:1\r\n".to_vec()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_encode_resp3_types_downgraded_to_resp2() {
+        let map = Type::Map(vec![(
+            Type::SimpleString(SimpleString::from("proto")),
+            Type::Boolean(false),
+        )]);
+        assert_eq!(
+            map.encode(Protocol::Resp2),
+            b"*2\r\n+proto\r\n:0\r\n".to_vec()
+        );
+        assert_eq!(Type::Null.encode(Protocol::Resp2), b"$-1\r\n".to_vec());
+        assert_eq!(
+            Type::Double(1.5).encode(Protocol::Resp2),
+            b"$3\r\n1.5\r\n".to_vec()
+        );
+        assert_eq!(
+            Type::Push(vec![Type::Integer(1)]).encode(Protocol::Resp2),
+            b"*1\r\n:1\r\n".to_vec()
+        );
+    }
 
     #[test]
     fn test_empty_file() {
-        let rdb_file = RDBFile::empty_file();
-        assert_eq!(rdb_file.hex_content, "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2");
+        let rdb_file = empty_file();
+        assert_eq!(&rdb_file.content[..9], b"REDIS0011");
+        assert_eq!(rdb_file.content[rdb_file.length() - 9], 0xff);
     }
 
     #[test]
     fn test_length() {
-        let rdb_file = RDBFile::empty_file();
+        let rdb_file = empty_file();
         // La longueur attendue est la moitié de la longueur de la chaîne hexadécimale
         assert_eq!(rdb_file.length(), 88);
     }
 
     #[test]
     fn test_to_redis_bytes() {
-        let rdb_file = RDBFile::empty_file();
+        let rdb_file = empty_file();
         let bytes = rdb_file.to_redis_bytes();
         // Vérifie que les premiers éléments correspondent au format attendu avec le préfixe "$"
         assert_eq!(bytes[0], b'$');
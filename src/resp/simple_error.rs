@@ -0,0 +1,32 @@
+use std::fmt::Display;
+
+use super::ToRedisBytes;
+
+/// Represents a simple error in RESP protocol
+/// A simple error is a string prefixed with '-', whose first word is the error kind (e.g. `ERR`, `WRONGTYPE`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimpleError {
+    data: String,
+}
+impl SimpleError {
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+}
+impl From<&str> for SimpleError {
+    fn from(s: &str) -> Self {
+        Self {
+            data: s.to_string(),
+        }
+    }
+}
+impl Display for SimpleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.data)
+    }
+}
+impl ToRedisBytes for SimpleError {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        format!("-{}\r\n", self.data).into_bytes()
+    }
+}
@@ -1,6 +1,8 @@
+use super::ToRedisBytes;
+
 /// Represents a simple string in RESP protocol
 /// A simple string is a string prefixed with '+'
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SimpleString {
     data: String,
 }
@@ -20,3 +22,15 @@ impl SimpleString {
         }
     }
 }
+impl From<&str> for SimpleString {
+    fn from(s: &str) -> Self {
+        Self {
+            data: s.to_string(),
+        }
+    }
+}
+impl ToRedisBytes for SimpleString {
+    fn to_redis_bytes(&self) -> Vec<u8> {
+        format!("+{}\r\n", self.data).into_bytes()
+    }
+}
@@ -0,0 +1,440 @@
+//! The directives a server is configured with, read from a configuration file in the format
+//! of `redis.conf` and from `--name value` command line arguments.
+//!
+//! Each line of a file is a directive name followed by its arguments, separated by spaces.
+//! Arguments may be quoted: double quotes understand the `\n`, `\r`, `\t`, `\b`, `\a`, `\xHH`
+//! escapes and escape any other character with a backslash, while single quotes only
+//! understand `\'`. Lines starting with `#` are comments, and `include <path>` reads the
+//! directives of another file in its place.
+//!
+//! A directive given several times keeps its last value, so the command line arguments,
+//! read after the file, override it.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::resp::inline;
+
+/// The directives this server understands, with their minimum and maximum number of
+/// arguments.
+const DIRECTIVES: &[(&str, usize, usize)] = &[
+    ("bind", 1, usize::MAX),
+    ("port", 1, 1),
+    ("replicaof", 1, 2),
+    ("dir", 1, 1),
+    ("dbfilename", 1, 1),
+    ("save", 1, usize::MAX),
+    ("appendonly", 1, 1),
+    ("appendfilename", 1, 1),
+    ("appenddirname", 1, 1),
+    ("appendfsync", 1, 1),
+    ("aof-load-truncated", 1, 1),
+    ("aof-use-rdb-preamble", 1, 1),
+    ("auto-aof-rewrite-percentage", 1, 1),
+    ("auto-aof-rewrite-min-size", 1, 1),
+    ("repl-backlog-size", 1, 1),
+    ("repl-timeout", 1, 1),
+    ("replica-read-only", 1, 1),
+    ("maxmemory", 1, 1),
+];
+
+/// Former names of directives, still accepted.
+const ALIASES: &[(&str, &str)] = &[
+    ("slaveof", "replicaof"),
+    ("slave-read-only", "replica-read-only"),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Directive {
+    /// The lowercase name of the directive, aliases being replaced by the current name.
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// The directives read so far, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Directives {
+    directives: Vec<Directive>,
+    /// The configuration file the directives were read from, if any.
+    file: Option<PathBuf>,
+}
+impl Directives {
+    /// Reads the command line arguments of the server, without the program name: an
+    /// optional configuration file, followed by `--name value...` directives overriding it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DirectiveError` if the file cannot be read or holds an invalid line, or if
+    /// an argument is not a directive of this server.
+    pub fn from_args(args: &[&str]) -> Result<Self, DirectiveError> {
+        let (mut directives, overrides) = match args.split_first() {
+            Some((path, overrides)) if !path.starts_with("--") => {
+                (Self::read(Path::new(path))?, overrides)
+            }
+            _ => (Self::default(), args),
+        };
+        let mut line = 0;
+        let mut directive: Option<Vec<String>> = None;
+        for arg in overrides.iter().map(Some).chain([None]) {
+            if arg.is_none_or(|arg| arg.starts_with("--")) {
+                if let Some(mut args) = directive.take() {
+                    line += 1;
+                    let text = args.join(" ");
+                    let name = args.remove(0);
+                    directives.push(&name, args).ok_or_else(|| {
+                        DirectiveError::BadDirective(Location {
+                            origin: "the command line".to_string(),
+                            line,
+                            text,
+                        })
+                    })?;
+                }
+                directive = arg.map(|arg| vec![arg.trim_start_matches("--").to_string()]);
+            } else if let (Some(args), Some(arg)) = (&mut directive, arg) {
+                args.push((*arg).to_string());
+            } else {
+                return Err(DirectiveError::BadDirective(Location {
+                    origin: "the command line".to_string(),
+                    line: line + 1,
+                    text: arg.map_or_else(String::new, |arg| (*arg).to_string()),
+                }));
+            }
+        }
+        Ok(directives)
+    }
+
+    /// Reads the configuration file at `path`, along with the files it includes.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DirectiveError` if a file cannot be read, includes itself, or holds a line
+    /// that is not a directive of this server.
+    pub fn read(path: &Path) -> Result<Self, DirectiveError> {
+        let mut directives = Self::for_file(Some(path.to_path_buf()));
+        directives.include(path, &mut Vec::new())?;
+        Ok(directives)
+    }
+
+    /// Returns an empty list of directives, which belong to `file`.
+    pub(super) const fn for_file(file: Option<PathBuf>) -> Self {
+        Self {
+            directives: Vec::new(),
+            file,
+        }
+    }
+
+    /// Returns the configuration file the directives were read from.
+    #[must_use]
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Reads the directives of the file at `path`, `including` holding the files whose
+    /// `include` directives led to it.
+    fn include(&mut self, path: &Path, including: &mut Vec<PathBuf>) -> Result<(), DirectiveError> {
+        let io_error = |error| DirectiveError::Io(path.to_path_buf(), error);
+        let canonical = std::fs::canonicalize(path).map_err(io_error)?;
+        if including.contains(&canonical) {
+            return Err(DirectiveError::IncludeLoop(path.to_path_buf()));
+        }
+        let text = std::fs::read_to_string(path).map_err(io_error)?;
+        including.push(canonical);
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let location = || Location {
+                origin: path.display().to_string(),
+                line: index + 1,
+                text: line.to_string(),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut args =
+                split_args(line).ok_or_else(|| DirectiveError::UnbalancedQuotes(location()))?;
+            let name = args.remove(0);
+            if name.eq_ignore_ascii_case("include") {
+                let [file] = args.as_slice() else {
+                    return Err(DirectiveError::BadDirective(location()));
+                };
+                self.include(Path::new(file), including)?;
+            } else {
+                self.push(&name, args)
+                    .ok_or_else(|| DirectiveError::BadDirective(location()))?;
+            }
+        }
+        including.pop();
+        Ok(())
+    }
+
+    /// Appends a directive, `None` if this server does not know it or it has too few or too
+    /// many arguments.
+    fn push(&mut self, name: &str, args: Vec<String>) -> Option<()> {
+        let name = canonical_name(name);
+        let (name, min, max) = DIRECTIVES.iter().find(|(known, ..)| *known == name)?;
+        if args.len() < *min || args.len() > *max {
+            return None;
+        }
+        self.directives.push(Directive {
+            name: (*name).to_string(),
+            args,
+        });
+        Some(())
+    }
+
+    /// Replaces every `name` directive with one taking `args`, `None` if this server does
+    /// not know it or it has too few or too many arguments.
+    pub(super) fn set(&mut self, name: &str, args: Vec<String>) -> Option<()> {
+        let name = canonical_name(name);
+        self.directives.retain(|directive| directive.name != name);
+        self.push(&name, args)
+    }
+
+    /// Returns the arguments of the last `name` directive, the one overriding the others.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.directives
+            .iter()
+            .rev()
+            .find(|directive| directive.name == name)
+            .map(|directive| directive.args.as_slice())
+    }
+
+    /// Returns the arguments of every `name` directive, in order, for directives such as
+    /// `save` that add up rather than override each other.
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [String]> {
+        self.directives
+            .iter()
+            .filter(move |directive| directive.name == name)
+            .map(|directive| directive.args.as_slice())
+    }
+
+    /// Returns the first argument of the last `name` directive.
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.get(name)
+            .and_then(|args| args.first())
+            .map(String::as_str)
+    }
+
+    /// Parses the arguments of the last `name` directive, joined by spaces, with `parse`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DirectiveError::Invalid` holding the error of `parse` if it fails.
+    pub fn parse<T, E: Display>(
+        &self,
+        name: &str,
+        parse: impl FnOnce(&str) -> Result<T, E>,
+    ) -> Result<Option<T>, DirectiveError> {
+        self.get(name)
+            .map(|args| {
+                parse(&args.join(" ")).map_err(|error| DirectiveError::invalid(name, error))
+            })
+            .transpose()
+    }
+}
+
+/// Returns the lowercase name of a directive, its current one if `name` is an alias.
+pub(super) fn canonical_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| (*name).to_string())
+}
+
+/// Splits a line of a configuration file into its arguments, quoted like the arguments of
+/// inline commands. Returns `None` if a quote is not closed or is directly followed by
+/// another character than a space.
+pub(super) fn split_args(line: &str) -> Option<Vec<String>> {
+    let args = inline::split_arguments(line.as_bytes())?;
+    Some(
+        args.iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
+
+/// Parses a `yes` or `no` argument, case insensitively.
+pub(super) fn parse_yes_no(value: &str) -> Result<bool, &'static str> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'"),
+    }
+}
+
+/// Where a directive was read.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The file, or the command line.
+    pub origin: String,
+    pub line: usize,
+    pub text: String,
+}
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at line {} of {} >>> '{}'",
+            self.line, self.origin, self.text
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum DirectiveError {
+    Io(PathBuf, std::io::Error),
+    /// The file includes itself, directly or through other files.
+    IncludeLoop(PathBuf),
+    UnbalancedQuotes(Location),
+    /// The directive is unknown, or has too few or too many arguments.
+    BadDirective(Location),
+    /// The arguments of the named directive are not a valid value.
+    Invalid {
+        name: String,
+        reason: String,
+    },
+}
+impl DirectiveError {
+    pub(super) fn invalid(name: &str, reason: impl Display) -> Self {
+        Self::Invalid {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+impl Display for DirectiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, error) => write!(f, "Cannot read {}: {error}", path.display()),
+            Self::IncludeLoop(path) => write!(f, "{} includes itself", path.display()),
+            Self::UnbalancedQuotes(location) => {
+                write!(f, "Unbalanced quotes in configuration line {location}")
+            }
+            Self::BadDirective(location) => {
+                write!(f, "Bad directive or wrong number of arguments {location}")
+            }
+            Self::Invalid { name, reason } => write!(f, "Bad value for '{name}': {reason}"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("directives-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("  save 900   1 ").unwrap(), ["save", "900", "1"]);
+        assert_eq!(
+            split_args(r#"dir "/var/lib/my redis" dbfilename 'it\'s.rdb'"#).unwrap(),
+            ["dir", "/var/lib/my redis", "dbfilename", "it's.rdb"]
+        );
+        assert_eq!(
+            split_args(r#"a "\x41\x4g\t\"\\" '\n' """#).unwrap(),
+            ["a", "Ax4g\t\"\\", "\\n", ""]
+        );
+        assert_eq!(split_args(r#"dir "/tmp"#), None);
+        assert_eq!(split_args(r#"dir "/tmp"x"#), None);
+        assert_eq!(split_args("dir 'a"), None);
+    }
+
+    #[test]
+    fn test_read_with_include() {
+        let dir = temp_dir("include");
+        let included = dir.join("included.conf");
+        std::fs::write(&included, "port 7000\nslaveof localhost 6379\n").unwrap();
+        let main = dir.join("redis.conf");
+        std::fs::write(
+            &main,
+            format!(
+                "# Comment\n\nPORT 6380\ninclude \"{}\"\n  save 900 1\nsave 60 100\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let directives = Directives::read(&main).unwrap();
+        assert_eq!(directives.value("port"), Some("7000"));
+        assert_eq!(directives.get("replicaof").unwrap(), ["localhost", "6379"]);
+        assert_eq!(directives.all("save").count(), 2);
+        assert_eq!(directives.value("dir"), None);
+
+        std::fs::write(&included, format!("include {}\n", main.display())).unwrap();
+        assert!(matches!(
+            Directives::read(&main),
+            Err(DirectiveError::IncludeLoop(_))
+        ));
+        std::fs::write(&main, "port 6380\nprot 6381\n").unwrap();
+        let error = Directives::read(&main).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Bad directive or wrong number of arguments at line 2 of {} >>> 'prot 6381'",
+                main.display()
+            )
+        );
+        std::fs::write(&main, "dir \"/tmp\n").unwrap();
+        assert!(matches!(
+            Directives::read(&main),
+            Err(DirectiveError::UnbalancedQuotes(Location { line: 1, .. }))
+        ));
+        assert!(matches!(
+            Directives::read(&dir.join("missing.conf")),
+            Err(DirectiveError::Io(..))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_args_overrides_file() {
+        let dir = temp_dir("args");
+        let file = dir.join("redis.conf");
+        std::fs::write(&file, "port 6380\ndbfilename file.rdb\n").unwrap();
+        let path = file.display().to_string();
+
+        let directives = Directives::from_args(&[
+            &path,
+            "--port",
+            "6381",
+            "--save",
+            "",
+            "--replicaof",
+            "localhost 6379",
+        ])
+        .unwrap();
+        assert_eq!(directives.value("port"), Some("6381"));
+        assert_eq!(directives.value("dbfilename"), Some("file.rdb"));
+        assert_eq!(directives.get("save").unwrap(), [""]);
+        assert_eq!(directives.value("replicaof"), Some("localhost 6379"));
+        assert_eq!(
+            directives.parse("port", str::parse::<u16>).unwrap(),
+            Some(6381)
+        );
+        assert!(matches!(
+            directives.parse("dbfilename", str::parse::<u16>),
+            Err(DirectiveError::Invalid { .. })
+        ));
+
+        assert!(Directives::from_args(&[]).unwrap().get("port").is_none());
+        assert!(matches!(
+            Directives::from_args(&["--port"]),
+            Err(DirectiveError::BadDirective(Location { line: 1, .. }))
+        ));
+        assert!(matches!(
+            Directives::from_args(&["--port", "6380", "--maxclients", "10"]),
+            Err(DirectiveError::BadDirective(Location { line: 2, .. }))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,31 +1,44 @@
 //! This module provides the core functionality for a Redis replication client.
 //!
 //! It includes the following submodules:
+//! - `directives`: Contains the `Directives` struct, which reads a `redis.conf` file and the
+//!   command line arguments overriding it.
 //! - `host`: Contains the `Host` struct, which represents a Redis host.
+//! - `persistence`: Contains the `PersistenceConfig` struct, which locates the RDB and
+//!   append-only files.
 //! - `port`: Contains the `Port` struct, which represents a Redis port.
+//! - `registry`: Contains the `Parameter` struct and the parameters `CONFIG` gets, sets and
+//!   rewrites.
 //! - `replica`: Contains the `Replica` struct, which represents a Redis replica.
+//! - `replication`: Contains the `ReplicationConfig` struct, which sizes the backlog.
 //! - `server`: Contains the `Server` struct, which represents a Redis server.
 //!
 //! It also includes the following types:
-//! - `RedisStore`: A thread-safe `HashMap` that stores Redis values.
+//! - `RedisStore`: A thread-safe `Keyspace` that stores Redis values.
 //! - `ReplicationId`: Represents a replication ID in Redis.
 //! - `Offset`: Represents an offset in Redis.
 
 use std::{
-    collections::HashMap,
-    fmt::Display,
+    collections::hash_map::RandomState,
+    fmt::{Display, Write},
+    hash::{BuildHasher, Hasher},
     sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::RedisValue;
+use crate::Keyspace;
 
+pub mod directives;
 pub mod host;
+pub mod persistence;
 pub mod port;
+pub mod registry;
 pub mod replica;
+pub mod replication;
 pub mod server;
 
-/// A thread-safe `HashMap` that stores Redis values.
-pub type RedisStore = Arc<Mutex<HashMap<String, RedisValue>>>;
+/// A thread-safe `Keyspace` that stores Redis values under binary safe keys.
+pub type RedisStore = Arc<Mutex<Keyspace>>;
 
 /// Represents a replication ID in Redis.
 ///
@@ -34,7 +47,7 @@ pub type RedisStore = Arc<Mutex<HashMap<String, RedisValue>>>;
 pub struct ReplicationId(String);
 impl ReplicationId {
     /// Returns the length of the replication ID.
-    pub fn len(&self) -> usize {
+    pub const fn len(&self) -> usize {
         self.0.len()
     }
 
@@ -44,6 +57,32 @@ impl ReplicationId {
     pub fn parse(id: Option<String>) -> Self {
         id.map_or_else(|| Self("?".to_string()), Self)
     }
+
+    /// Generates a new ID of 40 hexadecimal characters, like the one Redis picks at startup
+    /// and whenever its history diverges from the one of its former master.
+    ///
+    /// The standard library seeds every `RandomState` with fresh random keys, which makes
+    /// it a source of randomness good enough for IDs.
+    pub fn random() -> Self {
+        let state = RandomState::new();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let mut id = String::with_capacity(48);
+        for part in 0..3u64 {
+            let mut hasher = state.build_hasher();
+            hasher.write_u64(part);
+            hasher.write_u128(nanos);
+            let _ = write!(id, "{:016x}", hasher.finish());
+        }
+        id.truncate(40);
+        Self(id)
+    }
+
+    /// Returns the all zeros ID Redis reports when there is no secondary replication ID.
+    pub fn zero() -> Self {
+        Self("0".repeat(40))
+    }
 }
 impl Display for ReplicationId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -53,9 +92,10 @@ impl Display for ReplicationId {
 
 /// Represents an offset in Redis.
 ///
-/// The offset is used in Redis for various operations, such as string manipulation and replication.
+/// The replication offset counts the bytes of the stream of writes a master sends to its
+/// replicas.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Offset(i8);
+pub struct Offset(i64);
 impl Offset {
     /// Returns the length of the offset.
     ///
@@ -64,12 +104,23 @@ impl Offset {
         self.0.to_string().len()
     }
 
-    /// Parses an offset from an optional i8.
+    /// Parses an offset from an optional i64.
     ///
-    /// If the i8 is `None`, a default offset of -1 is used.
-    pub fn parse(offset: Option<i8>) -> Self {
+    /// If the i64 is `None`, a default offset of -1 is used.
+    pub fn parse(offset: Option<i64>) -> Self {
         offset.map_or(Self(-1), Self)
     }
+
+    pub const fn value(&self) -> i64 {
+        self.0
+    }
+
+    /// Moves the offset past `bytes` more bytes of the replication stream.
+    pub fn advance(&mut self, bytes: usize) {
+        self.0 = self
+            .0
+            .saturating_add(i64::try_from(bytes).unwrap_or(i64::MAX));
+    }
 }
 impl Display for Offset {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -96,6 +147,14 @@ mod tests {
         assert_eq!(id.0, "?");
     }
 
+    #[test]
+    fn test_replication_id_random() {
+        let id = ReplicationId::random();
+        assert_eq!(id.len(), 40);
+        assert!(id.0.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, ReplicationId::random());
+    }
+
     #[test]
     fn test_replication_id_display() {
         let id = ReplicationId::parse(Some("12345".to_string()));
@@ -122,4 +181,12 @@ mod tests {
         let offset = Offset::parse(Some(123));
         assert_eq!(format!("{offset}"), "123");
     }
+
+    #[test]
+    fn test_offset_advance_past_i8() {
+        let mut offset = Offset::parse(Some(0));
+        offset.advance(100);
+        offset.advance(5_000_000_000);
+        assert_eq!(offset.value(), 5_000_000_100);
+    }
 }
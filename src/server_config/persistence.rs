@@ -0,0 +1,367 @@
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use super::{
+    directives::{parse_yes_no, DirectiveError, Directives},
+    replication::parse_memory,
+};
+
+/// Where the RDB snapshot and the append-only file are read from at startup and written
+/// to, and when they are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct PersistenceConfig {
+    dir: PathBuf,
+    dbfilename: String,
+    save_points: Vec<SavePoint>,
+    appendonly: bool,
+    /// Prefix of the names of the append-only files and of their manifest.
+    appendfilename: String,
+    /// Directory of `dir` holding the append-only files.
+    appenddirname: String,
+    appendfsync: AppendFsync,
+    /// Whether an append-only file whose last command was cut short is loaded anyway.
+    aof_load_truncated: bool,
+    /// Whether rewrites write the base file in the RDB format rather than as commands.
+    aof_use_rdb_preamble: bool,
+    /// Growth over the size after the last rewrite, in percent, triggering a rewrite.
+    auto_aof_rewrite_percentage: u64,
+    /// Size in bytes below which the append-only files are never rewritten automatically.
+    auto_aof_rewrite_min_size: u64,
+}
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save_points: SavePoint::parse_all("3600 1 300 100 60 10000")
+                .expect("Default save points are valid"),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            aof_load_truncated: true,
+            aof_use_rdb_preamble: true,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// When writes appended to the append-only file are flushed to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write, before replying to the client.
+    Always,
+    /// Once per second, losing at most a second of writes on a crash.
+    EverySec,
+    /// Whenever the operating system decides.
+    No,
+}
+impl AppendFsync {
+    /// Parses an `appendfsync` value, case insensitively.
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "everysec" => Some(Self::EverySec),
+            "no" => Some(Self::No),
+            _ => None,
+        }
+    }
+}
+impl Display for AppendFsync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "always"),
+            Self::EverySec => write!(f, "everysec"),
+            Self::No => write!(f, "no"),
+        }
+    }
+}
+
+/// A `save <seconds> <changes>` rule: a snapshot is taken once at least `changes` writes
+/// happened and `seconds` elapsed since the last save.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+impl SavePoint {
+    /// Parses a list of `<seconds> <changes>` pairs, an empty list disabling snapshots.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SavePointError` if a value is not a number or a pair is incomplete.
+    pub fn parse_all(rules: &str) -> Result<Vec<Self>, SavePointError> {
+        let values = rules
+            .split_whitespace()
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| SavePointError::Invalid(value.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.len() % 2 != 0 {
+            return Err(SavePointError::MissingChanges);
+        }
+        Ok(values
+            .chunks(2)
+            .map(|pair| Self {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect())
+    }
+    /// Returns whether a snapshot is due after `changes` writes and `elapsed` time since the
+    /// last save, Redis requiring strictly more than `seconds`.
+    #[must_use]
+    pub const fn is_due(&self, changes: u64, elapsed: Duration) -> bool {
+        changes >= self.changes && elapsed.as_secs() > self.seconds
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SavePointError {
+    Invalid(String),
+    MissingChanges,
+}
+impl Display for SavePointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(value) => write!(f, "Invalid save parameter: {value}"),
+            Self::MissingChanges => write!(f, "Invalid save parameters: expected pairs"),
+        }
+    }
+}
+impl PersistenceConfig {
+    /// Reads the `dir`, `dbfilename`, `save`, `appendonly`, `appendfilename`,
+    /// `appenddirname`, `appendfsync`, `aof-load-truncated`, `aof-use-rdb-preamble`,
+    /// `auto-aof-rewrite-percentage` and `auto-aof-rewrite-min-size` directives, defaulting to
+    /// `dump.rdb` in the working directory, the Redis save points and no append-only file.
+    ///
+    /// Like in Redis, the first `save` directive replaces the default save points and the
+    /// next ones add to it, `save ""` removing them all.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DirectiveError` if `save` is not a list of `<seconds> <changes>` pairs, or
+    /// if another value is invalid.
+    pub fn from_directives(directives: &Directives) -> Result<Self, DirectiveError> {
+        let mut config = Self::default();
+        if let Some(dir) = directives.value("dir") {
+            config.dir = PathBuf::from(dir);
+        }
+        if let Some(dbfilename) = directives.value("dbfilename") {
+            config.dbfilename = dbfilename.to_string();
+        }
+        for (index, save) in directives.all("save").enumerate() {
+            let save = save.join(" ");
+            if index == 0 || save.trim().is_empty() {
+                config.save_points.clear();
+            }
+            config.save_points.extend(
+                SavePoint::parse_all(&save)
+                    .map_err(|error| DirectiveError::invalid("save", error))?,
+            );
+        }
+        if let Some(appendonly) = directives.parse("appendonly", parse_yes_no)? {
+            config.appendonly = appendonly;
+        }
+        if let Some(appendfilename) = directives.value("appendfilename") {
+            config.appendfilename = appendfilename.to_string();
+        }
+        if let Some(appenddirname) = directives.value("appenddirname") {
+            config.appenddirname = appenddirname.to_string();
+        }
+        if let Some(appendfsync) = directives.parse("appendfsync", |appendfsync| {
+            AppendFsync::parse(appendfsync).ok_or("argument must be always, everysec or no")
+        })? {
+            config.appendfsync = appendfsync;
+        }
+        if let Some(load_truncated) = directives.parse("aof-load-truncated", parse_yes_no)? {
+            config.aof_load_truncated = load_truncated;
+        }
+        if let Some(preamble) = directives.parse("aof-use-rdb-preamble", parse_yes_no)? {
+            config.aof_use_rdb_preamble = preamble;
+        }
+        if let Some(percentage) =
+            directives.parse("auto-aof-rewrite-percentage", str::parse::<u64>)?
+        {
+            config.auto_aof_rewrite_percentage = percentage;
+        }
+        if let Some(min_size) = directives.parse("auto-aof-rewrite-min-size", parse_memory)? {
+            config.auto_aof_rewrite_min_size = min_size as u64;
+        }
+        Ok(config)
+    }
+    #[must_use]
+    pub const fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+    #[must_use]
+    pub fn dbfilename(&self) -> &str {
+        &self.dbfilename
+    }
+    #[must_use]
+    pub fn save_points(&self) -> &[SavePoint] {
+        &self.save_points
+    }
+    /// Returns the path of the RDB file.
+    #[must_use]
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+    /// Returns whether writes are logged to the append-only file, which is then loaded at
+    /// startup instead of the RDB file.
+    #[must_use]
+    pub const fn appendonly(&self) -> bool {
+        self.appendonly
+    }
+    #[must_use]
+    pub const fn appendfsync(&self) -> AppendFsync {
+        self.appendfsync
+    }
+    #[must_use]
+    pub const fn aof_load_truncated(&self) -> bool {
+        self.aof_load_truncated
+    }
+    #[must_use]
+    pub fn appendfilename(&self) -> &str {
+        &self.appendfilename
+    }
+    #[must_use]
+    pub fn appenddirname(&self) -> &str {
+        &self.appenddirname
+    }
+    /// Returns the path of the directory holding the append-only files.
+    #[must_use]
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.appenddirname)
+    }
+    #[must_use]
+    pub const fn aof_use_rdb_preamble(&self) -> bool {
+        self.aof_use_rdb_preamble
+    }
+    /// Returns the growth in percent and the minimum size in bytes at which the append-only
+    /// files are rewritten automatically, a percentage of 0 disabling automatic rewrites.
+    #[must_use]
+    pub const fn auto_aof_rewrite(&self) -> (u64, u64) {
+        (
+            self.auto_aof_rewrite_percentage,
+            self.auto_aof_rewrite_min_size,
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &[&str]) -> PersistenceConfig {
+        PersistenceConfig::from_directives(&Directives::from_args(args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_persistence_config_from_args() {
+        let config = from_args(&["--dir", "/tmp/redis-files", "--dbfilename", "snapshot.rdb"]);
+        assert_eq!(config.dir(), &PathBuf::from("/tmp/redis-files"));
+        assert_eq!(config.dbfilename(), "snapshot.rdb");
+        assert_eq!(
+            config.rdb_path(),
+            PathBuf::from("/tmp/redis-files/snapshot.rdb")
+        );
+    }
+
+    #[test]
+    fn test_persistence_config_defaults() {
+        let config = from_args(&["--port", "6380"]);
+        assert_eq!(config.rdb_path(), PathBuf::from("./dump.rdb"));
+        assert_eq!(config.save_points().len(), 3);
+        assert!(!config.appendonly());
+        assert_eq!(config.appendfsync(), AppendFsync::EverySec);
+        assert!(config.aof_load_truncated());
+        assert_eq!(config.aof_dir(), PathBuf::from("./appendonlydir"));
+        assert!(config.aof_use_rdb_preamble());
+        assert_eq!(config.auto_aof_rewrite(), (100, 64 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_append_only_config() {
+        let config = from_args(&[
+            "--dir",
+            "/data",
+            "--appendonly",
+            "yes",
+            "--appendfilename",
+            "jobs.aof",
+            "--appendfsync",
+            "Always",
+            "--aof-load-truncated",
+            "no",
+            "--appenddirname",
+            "jobs",
+            "--aof-use-rdb-preamble",
+            "no",
+            "--auto-aof-rewrite-percentage",
+            "50",
+            "--auto-aof-rewrite-min-size",
+            "1mb",
+        ]);
+        assert!(config.appendonly());
+        assert_eq!(config.appendfsync(), AppendFsync::Always);
+        assert!(!config.aof_load_truncated());
+        assert_eq!(config.appendfilename(), "jobs.aof");
+        assert_eq!(config.aof_dir(), PathBuf::from("/data/jobs"));
+        assert!(!config.aof_use_rdb_preamble());
+        assert_eq!(config.auto_aof_rewrite(), (50, 1024 * 1024));
+        assert_eq!(AppendFsync::parse("sometimes"), None);
+        let directives = Directives::from_args(&["--appendfsync", "sometimes"]).unwrap();
+        assert_eq!(
+            PersistenceConfig::from_directives(&directives)
+                .unwrap_err()
+                .to_string(),
+            "Bad value for 'appendfsync': argument must be always, everysec or no"
+        );
+    }
+
+    #[test]
+    fn test_save_points() {
+        let config = from_args(&["--save", "900 1 300 10"]);
+        assert_eq!(
+            config.save_points(),
+            [
+                SavePoint {
+                    seconds: 900,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 10
+                }
+            ]
+        );
+        let config = from_args(&["--save", ""]);
+        assert!(config.save_points().is_empty());
+        let config = from_args(&["--save", "900", "1", "--save", "300 10"]);
+        assert_eq!(config.save_points().len(), 2);
+        let config = from_args(&["--save", "900 1", "--save", ""]);
+        assert!(config.save_points().is_empty());
+
+        assert_eq!(
+            SavePoint::parse_all("900"),
+            Err(SavePointError::MissingChanges)
+        );
+        assert_eq!(
+            SavePoint::parse_all("900 x"),
+            Err(SavePointError::Invalid("x".to_string()))
+        );
+        let point = SavePoint {
+            seconds: 60,
+            changes: 100,
+        };
+        assert!(point.is_due(100, Duration::from_secs(61)));
+        assert!(!point.is_due(99, Duration::from_secs(601)));
+        assert!(!point.is_due(1000, Duration::from_millis(60_999)));
+    }
+}
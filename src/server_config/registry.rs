@@ -0,0 +1,548 @@
+//! The parameters `CONFIG GET`, `CONFIG SET` and `CONFIG REWRITE` work with.
+//!
+//! Each parameter renders its current value from a `Config` in the syntax of its directive,
+//! so that the value can be parsed back. Changing parameters renders the whole
+//! configuration as directives, replaces the changed ones and validates the result like a
+//! configuration file, so that a change either applies entirely or not at all.
+
+use std::{
+    fmt::{Display, Write},
+    io,
+    sync::LazyLock,
+};
+
+use crate::{atomic_file, glob, Config, ReplicaOf};
+
+use super::directives::{canonical_name, split_args, DirectiveError, Directives};
+
+/// A configuration parameter.
+#[derive(Debug)]
+pub struct Parameter {
+    pub name: &'static str,
+    /// Former name of the parameter, which `CONFIG GET` also reports.
+    pub alias: Option<&'static str>,
+    /// Whether the parameter can only be set at startup.
+    pub immutable: bool,
+    /// Whether the value is a list of arguments, written unquoted by `CONFIG REWRITE`.
+    list: bool,
+    get: fn(&Config) -> String,
+}
+impl Parameter {
+    const fn new(name: &'static str, get: fn(&Config) -> String) -> Self {
+        Self {
+            name,
+            alias: None,
+            immutable: false,
+            list: false,
+            get,
+        }
+    }
+    const fn immutable(self) -> Self {
+        Self {
+            immutable: true,
+            ..self
+        }
+    }
+    const fn list(self) -> Self {
+        Self { list: true, ..self }
+    }
+    const fn alias(self, alias: &'static str) -> Self {
+        Self {
+            alias: Some(alias),
+            ..self
+        }
+    }
+
+    /// Returns the current value of the parameter.
+    #[must_use]
+    pub fn get(&self, config: &Config) -> String {
+        (self.get)(config)
+    }
+
+    /// Returns the line of a configuration file setting the parameter to its value in
+    /// `config`.
+    fn line(&self, config: &Config) -> String {
+        let value = self.get(config);
+        let args = if self.list && !value.trim().is_empty() {
+            value.split_whitespace().map(quote).collect::<Vec<_>>()
+        } else {
+            vec![quote(&value)]
+        };
+        format!("{} {}", self.name, args.join(" "))
+    }
+}
+
+const fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// The parameters, in the order `CONFIG REWRITE` appends them.
+pub const PARAMETERS: &[Parameter] = &[
+    Parameter::new("bind", |config| config.bind_addresses().join(" "))
+        .immutable()
+        .list(),
+    Parameter::new("port", |config| config.port().to_string()).immutable(),
+    Parameter::new("dir", |config| {
+        config.persistence().dir().display().to_string()
+    })
+    .immutable(),
+    Parameter::new("dbfilename", |config| {
+        config.persistence().dbfilename().to_string()
+    }),
+    Parameter::new("save", |config| {
+        config
+            .persistence()
+            .save_points()
+            .iter()
+            .map(|point| format!("{} {}", point.seconds, point.changes))
+            .collect::<Vec<_>>()
+            .join(" ")
+    })
+    .list(),
+    Parameter::new("appendonly", |config| {
+        yes_no(config.persistence().appendonly()).to_string()
+    }),
+    Parameter::new("appendfilename", |config| {
+        config.persistence().appendfilename().to_string()
+    })
+    .immutable(),
+    Parameter::new("appenddirname", |config| {
+        config.persistence().appenddirname().to_string()
+    })
+    .immutable(),
+    Parameter::new("appendfsync", |config| {
+        config.persistence().appendfsync().to_string()
+    }),
+    Parameter::new("aof-load-truncated", |config| {
+        yes_no(config.persistence().aof_load_truncated()).to_string()
+    }),
+    Parameter::new("aof-use-rdb-preamble", |config| {
+        yes_no(config.persistence().aof_use_rdb_preamble()).to_string()
+    }),
+    Parameter::new("auto-aof-rewrite-percentage", |config| {
+        config.persistence().auto_aof_rewrite().0.to_string()
+    }),
+    Parameter::new("auto-aof-rewrite-min-size", |config| {
+        config.persistence().auto_aof_rewrite().1.to_string()
+    }),
+    Parameter::new("repl-backlog-size", |config| {
+        config.replication().backlog_size().to_string()
+    }),
+    Parameter::new("repl-timeout", |config| {
+        config.replication().timeout().as_secs().to_string()
+    }),
+    Parameter::new("replica-read-only", |config| {
+        yes_no(config.replication().read_only()).to_string()
+    })
+    .alias("slave-read-only"),
+    Parameter::new("maxmemory", |config| config.maxmemory().to_string()),
+];
+
+/// Returns the parameter called `name`, or one of its aliases, case insensitively.
+#[must_use]
+pub fn lookup(name: &str) -> Option<&'static Parameter> {
+    let name = canonical_name(name);
+    PARAMETERS.iter().find(|parameter| parameter.name == name)
+}
+
+/// Returns the names and values of the parameters whose name or alias matches one of the
+/// glob-style `patterns`, case insensitively, each parameter once.
+#[must_use]
+pub fn get(config: &Config, patterns: &[String]) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    for parameter in PARAMETERS {
+        for name in std::iter::once(parameter.name).chain(parameter.alias) {
+            let matched = patterns
+                .iter()
+                .any(|pattern| glob::matches(pattern.as_bytes(), name.as_bytes(), true));
+            if matched {
+                values.push((name.to_string(), parameter.get(config)));
+            }
+        }
+    }
+    values
+}
+
+/// Returns `config` with the parameters of `changes` set to their new value.
+///
+/// # Errors
+///
+/// Returns a `SetError` if a parameter is unknown, immutable or given twice, or if a value
+/// is invalid, in which case none of the changes apply.
+pub fn set(config: &Config, changes: &[(String, String)]) -> Result<Config, SetError> {
+    let mut directives = Directives::for_file(config.file().map(Into::into));
+    if let Config::Replica(replica) = config {
+        let replica_of = replica.replica_of();
+        directives.set(
+            "replicaof",
+            vec![
+                replica_of.host_address().to_string(),
+                replica_of.port().to_string(),
+            ],
+        );
+    }
+    for parameter in PARAMETERS {
+        directives.set(parameter.name, vec![parameter.get(config)]);
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for (name, value) in changes {
+        let Some(parameter) = lookup(name) else {
+            return Err(SetError::Unknown(name.clone()));
+        };
+        if parameter.immutable {
+            return Err(SetError::Failed {
+                name: name.clone(),
+                reason: "can't set immutable config".to_string(),
+            });
+        }
+        if seen.contains(&parameter.name) {
+            return Err(SetError::Failed {
+                name: name.clone(),
+                reason: "duplicate parameter".to_string(),
+            });
+        }
+        seen.push(parameter.name);
+        directives.set(parameter.name, vec![value.clone()]);
+    }
+    Config::from_directives(&directives).map_err(|error| {
+        let (name, reason) = match error.directive() {
+            Some(DirectiveError::Invalid { name, reason }) => (name.clone(), reason.clone()),
+            _ => (
+                changes
+                    .first()
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default(),
+                error.to_string(),
+            ),
+        };
+        SetError::Failed { name, reason }
+    })
+}
+
+/// Rewrites the configuration file the server was started with so that it holds the current
+/// values of the parameters, and the `master` this instance currently replicates, if any.
+///
+/// # Errors
+///
+/// Returns a `RewriteError` if the server was started without a configuration file, or if
+/// the file cannot be read or written.
+pub fn rewrite(config: &Config, master: Option<&ReplicaOf>) -> Result<(), RewriteError> {
+    let path = config.file().ok_or(RewriteError::NoFile)?;
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(RewriteError::Io(error)),
+    };
+    atomic_file::write(path, rewritten(&text, config, master).as_bytes()).map_err(RewriteError::Io)
+}
+
+/// The comment preceding the parameters appended by `CONFIG REWRITE`.
+const SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+/// The configuration of a server started without directives, which `CONFIG REWRITE` leaves
+/// out of the parameters it appends.
+static DEFAULTS: LazyLock<Config> = LazyLock::new(|| {
+    Config::from_directives(&Directives::default()).expect("The default configuration is valid")
+});
+
+/// Returns the `replicaof` line of a replica of `master`.
+fn replica_of_line(master: &ReplicaOf) -> String {
+    format!("replicaof {} {}", master.host_address(), master.port())
+}
+
+/// Returns the configuration file `text` with the current values of the parameters.
+///
+/// Comments and the lines that do not set a parameter are kept as they are. The first line
+/// setting a parameter gets its current value and the following ones are dropped, while the
+/// parameters the file does not set are appended if they differ from their default, after
+/// the `SIGNATURE` of the first rewrite. The `replicaof` directive follows the same rules
+/// with `master` as its value, and is dropped when there is no master.
+fn rewritten(text: &str, config: &Config, master: Option<&ReplicaOf>) -> String {
+    let mut lines = Vec::new();
+    let mut written = Vec::new();
+    let mut replica_of_written = false;
+    for line in text.lines() {
+        let name = split_args(line)
+            .and_then(|args| args.first().map(|name| canonical_name(name)))
+            .filter(|_| !line.trim_start().starts_with('#'));
+        if name.as_deref() == Some("replicaof") {
+            if !replica_of_written {
+                replica_of_written = true;
+                lines.extend(master.map(replica_of_line));
+            }
+            continue;
+        }
+        match name.as_deref().and_then(lookup) {
+            Some(parameter) if written.contains(&parameter.name) => {}
+            Some(parameter) => {
+                written.push(parameter.name);
+                lines.push(parameter.line(config));
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    let mut generated = master
+        .filter(|_| !replica_of_written)
+        .map(replica_of_line)
+        .into_iter()
+        .chain(
+            PARAMETERS
+                .iter()
+                .filter(|parameter| !written.contains(&parameter.name))
+                .filter(|parameter| parameter.get(config) != parameter.get(&DEFAULTS))
+                .map(|parameter| parameter.line(config)),
+        )
+        .peekable();
+    if generated.peek().is_some() {
+        if !lines.iter().any(|line| line == SIGNATURE) {
+            lines.push(SIGNATURE.to_string());
+        }
+        lines.extend(generated);
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+/// Quotes an argument of a configuration file line if it is empty or holds spaces, quotes,
+/// backslashes or control characters.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && !matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(quoted, "\\x{:02x}", c as u8);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SetError {
+    Unknown(String),
+    /// The parameter cannot be set, or not to the given value.
+    Failed {
+        name: String,
+        reason: String,
+    },
+}
+impl Display for SetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(
+                f,
+                "Unknown option or number of arguments for CONFIG SET - '{name}'"
+            ),
+            Self::Failed { name, reason } => write!(
+                f,
+                "CONFIG SET failed (possibly related to argument '{name}') - {reason}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RewriteError {
+    NoFile,
+    Io(io::Error),
+}
+impl Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoFile => write!(f, "The server is running without a config file"),
+            Self::Io(error) => write!(f, "Rewriting config file: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn changes(changes: &[(&str, &str)]) -> Vec<(String, String)> {
+        changes
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_get() {
+        let config = Config::from_args(&["--maxmemory", "1mb", "--save", ""]).unwrap();
+        assert_eq!(
+            get(&config, &["MAXMEM*".to_string(), "save".to_string()]),
+            vec![
+                ("save".to_string(), String::new()),
+                ("maxmemory".to_string(), "1048576".to_string())
+            ]
+        );
+        assert_eq!(
+            get(&config, &["*read-only".to_string()]),
+            vec![
+                ("replica-read-only".to_string(), "yes".to_string()),
+                ("slave-read-only".to_string(), "yes".to_string())
+            ]
+        );
+        assert_eq!(get(&config, &["*".to_string()]).len(), PARAMETERS.len() + 1);
+        assert!(get(&config, &["nothing".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_set() {
+        let config = Config::from_args(&["--port", "6390"]).unwrap();
+        let updated = set(
+            &config,
+            &changes(&[
+                ("maxmemory", "100mb"),
+                ("SAVE", "900 1 300 10"),
+                ("slave-read-only", "no"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(updated.maxmemory(), 100 * 1024 * 1024);
+        assert_eq!(updated.persistence().save_points().len(), 2);
+        assert!(!updated.replication().read_only());
+        assert_eq!(updated.port().get(), 6390);
+        assert_eq!(
+            lookup("save").unwrap().get(&updated),
+            "900 1 300 10".to_string()
+        );
+
+        let replica = Config::from_args(&["--replicaof", "127.0.0.1 6380"]).unwrap();
+        let updated = set(&replica, &changes(&[("repl-timeout", "5")])).unwrap();
+        assert!(matches!(updated, Config::Replica(_)));
+        assert_eq!(updated.replication().timeout().as_secs(), 5);
+    }
+
+    #[test]
+    fn test_set_errors() {
+        let config = Config::from_args(&[]).unwrap();
+        for (changes, error) in [
+            (
+                changes(&[("nothing", "1")]),
+                "Unknown option or number of arguments for CONFIG SET - 'nothing'",
+            ),
+            (
+                changes(&[("port", "6390")]),
+                "CONFIG SET failed (possibly related to argument 'port') - can't set immutable config",
+            ),
+            (
+                changes(&[("maxmemory", "1"), ("MAXMEMORY", "2")]),
+                "CONFIG SET failed (possibly related to argument 'MAXMEMORY') - duplicate parameter",
+            ),
+            (
+                changes(&[("maxmemory", "1"), ("appendfsync", "sometimes")]),
+                "CONFIG SET failed (possibly related to argument 'appendfsync') - argument must be always, everysec or no",
+            ),
+        ] {
+            assert_eq!(set(&config, &changes).unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn test_rewritten() {
+        let config = Config::from_args(&[
+            "--port",
+            "6390",
+            "--maxmemory",
+            "1mb",
+            "--dbfilename",
+            "my dump.rdb",
+            "--save",
+            "",
+        ])
+        .unwrap();
+        let text = "# A comment\n\
+                    port 6379\n\
+                    \n\
+                    maxmemory 10\n\
+                    include other.conf\n\
+                    maxmemory 20\n";
+        assert_eq!(
+            rewritten(text, &config, None),
+            "# A comment\n\
+             port 6390\n\
+             \n\
+             maxmemory 1048576\n\
+             include other.conf\n\
+             # Generated by CONFIG REWRITE\n\
+             dbfilename \"my dump.rdb\"\n\
+             save \"\"\n"
+        );
+        let config = Config::from_args(&["--bind", "127.0.0.1 -::1"]).unwrap();
+        assert_eq!(
+            rewritten("bind 127.0.0.1 -::1\n", &config, None),
+            "bind 127.0.0.1 -::1\n"
+        );
+        assert_eq!(
+            rewritten(&rewritten(text, &config, None), &config, None),
+            rewritten(text, &config, None)
+        );
+        let directives =
+            Directives::from_args(&["--dbfilename", "my dump.rdb", "--save", "60 1 10 2"]).unwrap();
+        let config = Config::from_directives(&directives).unwrap();
+        assert_eq!(
+            rewritten("", &config, None),
+            "# Generated by CONFIG REWRITE\n\
+             dbfilename \"my dump.rdb\"\n\
+             save 60 1 10 2\n"
+        );
+        assert_eq!(
+            rewritten(
+                "# Generated by CONFIG REWRITE\ndbfilename old.rdb\n",
+                &config,
+                None
+            ),
+            rewritten("", &config, None)
+        );
+    }
+
+    #[test]
+    fn test_rewritten_replica_of() {
+        let config = Config::from_args(&[]).unwrap();
+        let master = ReplicaOf::new("127.0.0.1".parse().unwrap(), "6380".parse().unwrap());
+        let text = "slaveof 10.0.0.1 6379\n\
+                    port 6379\n\
+                    replicaof 10.0.0.2 6379\n";
+        assert_eq!(
+            rewritten(text, &config, Some(&master)),
+            "replicaof 127.0.0.1 6380\n\
+             port 6379\n"
+        );
+        assert_eq!(rewritten(text, &config, None), "port 6379\n");
+        assert_eq!(
+            rewritten("port 6379\n", &config, Some(&master)),
+            "port 6379\n\
+             # Generated by CONFIG REWRITE\n\
+             replicaof 127.0.0.1 6380\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_without_file() {
+        let config = Config::from_args(&[]).unwrap();
+        assert!(matches!(rewrite(&config, None), Err(RewriteError::NoFile)));
+    }
+}
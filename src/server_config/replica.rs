@@ -4,7 +4,7 @@ use crate::Port;
 
 use super::host::Host;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[allow(clippy::module_name_repetitions)]
 pub struct ReplicaOf {
     host_address: Host,
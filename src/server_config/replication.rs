@@ -0,0 +1,158 @@
+use std::{fmt::Display, time::Duration};
+
+use super::directives::{parse_yes_no, DirectiveError, Directives};
+
+/// Default size of the replication backlog, 1mb like Redis.
+const DEFAULT_BACKLOG_SIZE: usize = 1024 * 1024;
+/// Default `repl-timeout`, in seconds.
+const DEFAULT_TIMEOUT: u64 = 60;
+
+/// How this instance keeps the replication stream around for replicas that reconnect, how
+/// long a replica waits on its master before giving up on the link, and whether clients may
+/// write to a replica.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReplicationConfig {
+    backlog_size: usize,
+    timeout: Duration,
+    read_only: bool,
+}
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            backlog_size: DEFAULT_BACKLOG_SIZE,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            read_only: true,
+        }
+    }
+}
+impl ReplicationConfig {
+    /// Reads the `repl-backlog-size`, `repl-timeout` and `replica-read-only` directives.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DirectiveError` if `repl-backlog-size` is not a size such as `1048576`,
+    /// `512kb` or `1mb`, `repl-timeout` is not a positive number of seconds, or
+    /// `replica-read-only` is neither `yes` nor `no`.
+    pub fn from_directives(directives: &Directives) -> Result<Self, DirectiveError> {
+        let mut config = Self::default();
+        if let Some(size) = directives.parse("repl-backlog-size", parse_memory)? {
+            config.backlog_size = size;
+        }
+        if let Some(timeout) = directives.parse("repl-timeout", |timeout| {
+            timeout
+                .parse()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs)
+                .ok_or("argument must be a positive number of seconds")
+        })? {
+            config.timeout = timeout;
+        }
+        if let Some(read_only) = directives.parse("replica-read-only", parse_yes_no)? {
+            config.read_only = read_only;
+        }
+        Ok(config)
+    }
+    /// Returns the number of bytes of the replication stream kept in the backlog.
+    #[must_use]
+    pub const fn backlog_size(&self) -> usize {
+        self.backlog_size
+    }
+    /// Returns how long a replica waits on its master, during the synchronisation or on an
+    /// idle link, before dropping the connection.
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    /// Returns whether a replica refuses writes from its clients, only applying those of its
+    /// master.
+    #[must_use]
+    pub const fn read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Parses an amount of memory, optionally followed by a unit: `k`, `m` and `g` are powers of
+/// 1000 while `kb`, `mb` and `gb` are powers of 1024, case insensitively.
+///
+/// # Errors
+///
+/// Returns a `MemoryError` if the amount is not a number or the unit is unknown.
+pub fn parse_memory(memory: &str) -> Result<usize, MemoryError> {
+    let lowercase = memory.to_lowercase();
+    let split = lowercase
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lowercase.len());
+    let (amount, unit) = lowercase.split_at(split);
+    let multiplier: usize = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(MemoryError(memory.to_string())),
+    };
+    amount
+        .parse::<usize>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| MemoryError(memory.to_string()))
+}
+
+/// An amount of memory that cannot be parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MemoryError(String);
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid memory amount: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_units() {
+        assert_eq!(parse_memory("1024"), Ok(1024));
+        assert_eq!(parse_memory("1k"), Ok(1000));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("2MB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Ok(1_000_000_000));
+        assert_eq!(parse_memory("1tb"), Err(MemoryError("1tb".to_string())));
+        assert_eq!(parse_memory("mb"), Err(MemoryError("mb".to_string())));
+    }
+
+    #[test]
+    fn test_replication_config_from_directives() {
+        let config = |args: &[&str]| {
+            ReplicationConfig::from_directives(&Directives::from_args(args).unwrap())
+        };
+        assert_eq!(
+            config(&["--port", "6380"]).unwrap().backlog_size(),
+            DEFAULT_BACKLOG_SIZE
+        );
+        assert_eq!(
+            config(&["--repl-backlog-size", "16kb"])
+                .unwrap()
+                .backlog_size(),
+            16 * 1024
+        );
+        assert_eq!(
+            config(&["--repl-timeout", "5"]).unwrap().timeout(),
+            Duration::from_secs(5)
+        );
+        assert!(config(&[]).unwrap().read_only());
+        assert!(!config(&["--replica-read-only", "no"]).unwrap().read_only());
+        assert!(!config(&["--slave-read-only", "NO"]).unwrap().read_only());
+        assert_eq!(
+            config(&["--repl-timeout", "0"]).unwrap_err().to_string(),
+            "Bad value for 'repl-timeout': argument must be a positive number of seconds"
+        );
+        assert!(config(&["--repl-backlog-size", "1tb"]).is_err());
+    }
+}
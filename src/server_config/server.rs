@@ -1,45 +1,28 @@
-/// This module contains the implementation of the `ServerConfig` enum and its associated types and functions.
-/// The `ServerConfig` enum represents the configuration of a Redis server, which can be either a master or a replica.
-/// It provides methods for creating a `ServerConfig` from command line arguments, retrieving the port of the server,
-/// and handling errors related to server configuration.
-///
-/// The `ServerConfig` enum has two variants:
-/// - `Master`: Represents the configuration of a master server.
-/// - `Replica`: Represents the configuration of a replica server.
-///
-/// The `MasterConfig` struct represents the configuration of a master server and contains the port of the server.
-/// It provides methods for retrieving the port of the server and creating a `MasterConfig` from command line arguments
-/// or from a `ServerConfig` enum.
-///
-/// The `ReplicaConfig` struct represents the configuration of a replica server and contains the port of the server and the
-/// information about the master server it replicates. It provides methods for retrieving the port and the replica information
-/// of the server, creating a `ReplicaConfig` from command line arguments or from a `ServerConfig` enum.
-///
-/// The `ServerConfigError` enum represents the possible errors that can occur during server configuration.
-/// It has two variants:
-/// - `Master`: Represents an error related to the configuration of a master server.
-/// - `Replica`: Represents an error related to the configuration of a replica server.
-///
-/// The `MasterConfigError` enum represents the possible errors that can occur during the configuration of a master server.
-/// It has two variants:
-/// - `MissingPort`: Indicates that the port argument is missing.
-/// - `InvalidPort`: Indicates that the port argument is invalid.
-///
-/// The `ReplicaConfigError` enum represents the possible errors that can occur during the configuration of a replica server.
-/// It has five variants:
-/// - `MissingReplicaOf`: Indicates that the replicaof argument is missing.
-/// - `MissingReplicaOfHost`: Indicates that the host argument of the replicaof command is missing.
-/// - `MissingReplicaOfPort`: Indicates that the port argument of the replicaof command is missing.
-/// - `InvalidReplicaOfHost`: Indicates that the host argument of the replicaof command is invalid.
-/// - `InvalidReplicaOfPort`: Indicates that the port argument of the replicaof command is invalid.
-///
-/// The `parse_port` function is a helper function that parses the port argument from the command line arguments.
-/// If the port argument is not provided, it defaults to "6379".
-use std::fmt::Display;
+//! The configuration of a server, which is either a master or a replica of another server.
+//!
+//! A [`Config`] is read from the directives of a `redis.conf` file and the command line
+//! arguments. Both roles share the listening address, persistence, replication and memory
+//! settings; a replica also needs a `replicaof` directive. Without a `port` or `bind`
+//! directive, the server listens on port 6379 of 127.0.0.1.
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
 use crate::{Port, ReplicaOf};
 
-use super::{host::Host, port::Error};
+use super::{
+    directives::{DirectiveError, Directives},
+    host::Host,
+    persistence::PersistenceConfig,
+    port::Error,
+    replication::{parse_memory, ReplicationConfig},
+};
+
+/// The port listened on without a `port` directive.
+const DEFAULT_PORT: &str = "6379";
+/// The address listened on without a `bind` directive.
+const DEFAULT_BIND: &str = "127.0.0.1";
 
 #[derive(Clone, Debug)]
 pub enum Config {
@@ -51,7 +34,8 @@ impl Config {
     ///
     /// # Arguments
     ///
-    /// * `args` - The command line arguments.
+    /// * `args` - The command line arguments, without the program name: an optional
+    ///   configuration file followed by `--directive value` overrides.
     ///
     /// # Returns
     ///
@@ -59,13 +43,22 @@ impl Config {
     ///
     /// # Errors
     ///
-    /// Returns an error if the arguments are invalid or missing.
-    #[must_use]
-    pub fn from_args(args: &[&str]) -> Self {
-        if args.iter().any(|arg| *arg == "--replicaof") {
-            Self::Replica(ReplicaConfig::from_args(args))
+    /// Returns an error if the configuration file cannot be read, or if the directives are invalid.
+    pub fn from_args(args: &[&str]) -> Result<Self, ConfigError> {
+        Self::from_directives(&Directives::from_args(args)?)
+    }
+
+    /// Creates a `ServerConfig` from directives, the one of a replica if there is a
+    /// `replicaof` directive other than `replicaof no one`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a directive is invalid.
+    pub fn from_directives(directives: &Directives) -> Result<Self, ConfigError> {
+        if replica_of(directives).is_some() {
+            Ok(Self::Replica(ReplicaConfig::from_directives(directives)?))
         } else {
-            Self::Master(MasterConfig::from_args(args))
+            Ok(Self::Master(MasterConfig::from_directives(directives)?))
         }
     }
 
@@ -81,13 +74,84 @@ impl Config {
             Self::Replica(config) => config.port(),
         }
     }
+
+    /// Returns the address the server listens on.
+    #[must_use]
+    pub const fn bind(&self) -> &Host {
+        match self {
+            Self::Master(config) => config.bind(),
+            Self::Replica(config) => config.bind(),
+        }
+    }
+
+    /// Returns the addresses of the `bind` directive as given, of which the server listens
+    /// on the first.
+    #[must_use]
+    pub fn bind_addresses(&self) -> &[String] {
+        &self.common().bind_addresses
+    }
+
+    /// Returns where the server persists its dataset.
+    #[must_use]
+    pub const fn persistence(&self) -> &PersistenceConfig {
+        match self {
+            Self::Master(config) => config.persistence(),
+            Self::Replica(config) => config.persistence(),
+        }
+    }
+
+    /// Returns how the server keeps the replication stream.
+    #[must_use]
+    pub const fn replication(&self) -> &ReplicationConfig {
+        match self {
+            Self::Master(config) => config.replication(),
+            Self::Replica(config) => config.replication(),
+        }
+    }
+
+    /// Returns the memory limit of the dataset in bytes, 0 for no limit.
+    #[must_use]
+    pub const fn maxmemory(&self) -> usize {
+        self.common().maxmemory
+    }
+
+    /// Returns the configuration file the server was started with, if any.
+    #[must_use]
+    pub fn file(&self) -> Option<&Path> {
+        self.common().file.as_deref()
+    }
+
+    const fn common(&self) -> &Common {
+        match self {
+            Self::Master(config) => &config.common,
+            Self::Replica(config) => &config.common,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ConfigError {
+    Directive(DirectiveError),
     Master(MasterConfigError),
     Replica(ReplicaConfigError),
 }
+impl ConfigError {
+    /// Returns the error of the directive with an invalid value, if that is the cause.
+    #[must_use]
+    pub const fn directive(&self) -> Option<&DirectiveError> {
+        match self {
+            Self::Directive(err)
+            | Self::Master(MasterConfigError::Directive(err))
+            | Self::Replica(ReplicaConfigError::Directive(err)) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<DirectiveError> for ConfigError {
+    fn from(err: DirectiveError) -> Self {
+        Self::Directive(err)
+    }
+}
 impl From<MasterConfigError> for ConfigError {
     fn from(err: MasterConfigError) -> Self {
         Self::Master(err)
@@ -101,6 +165,7 @@ impl From<ReplicaConfigError> for ConfigError {
 impl Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Directive(err) => write!(f, "{err}"),
             Self::Master(err) => write!(f, "{err}"),
             Self::Replica(err) => write!(f, "{err}"),
         }
@@ -109,23 +174,35 @@ impl Display for ConfigError {
 
 #[derive(Clone, Debug)]
 pub struct ReplicaConfig {
-    port: Port,
     replica_of: ReplicaOf,
+    common: Common,
 }
 impl ReplicaConfig {
+    #[must_use]
+    pub const fn persistence(&self) -> &PersistenceConfig {
+        &self.common.persistence
+    }
+    #[must_use]
+    pub const fn replication(&self) -> &ReplicationConfig {
+        &self.common.replication
+    }
     #[must_use]
     pub const fn replica_of(&self) -> &ReplicaOf {
         &self.replica_of
     }
     #[must_use]
     pub const fn port(&self) -> &Port {
-        &self.port
+        &self.common.port
+    }
+    #[must_use]
+    pub const fn bind(&self) -> &Host {
+        &self.common.bind
     }
     /// Parses the command line arguments and creates a `ReplicaConfig` from them.
     ///
     /// # Arguments
     ///
-    /// * `args` - The command line arguments.
+    /// * `args` - The command line arguments, without the program name.
     ///
     /// # Returns
     ///
@@ -134,55 +211,40 @@ impl ReplicaConfig {
     /// # Errors
     ///
     /// Returns an error if the arguments are invalid or missing.
-    #[must_use]
-    pub fn from_args(args: &[&str]) -> Self {
-        let replica_arg_position = args
-            .iter()
-            .position(|arg| *arg == "--replicaof")
-            .expect("Replica config missing --replicaof");
-
-        let replica_command = args
-            .get(replica_arg_position + 1)
-            .expect("Replica config missing host of replica");
+    pub fn from_args(args: &[&str]) -> Result<Self, ReplicaConfigError> {
+        Self::from_directives(&Directives::from_args(args)?)
+    }
 
-        let (host_of_replica, port_of_host) = match replica_command
+    /// Creates a `ReplicaConfig` from directives.
+    ///
+    /// The master is given by `replicaof <host> <port>`, or `replicaof "<host> <port>"`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `replicaof` is missing or its host or port cannot be parsed, or if
+    /// another directive is invalid.
+    pub fn from_directives(directives: &Directives) -> Result<Self, ReplicaConfigError> {
+        let replica_of = replica_of(directives).ok_or(ReplicaConfigError::MissingReplicaOf)?;
+        let (host_of_replica, port_of_host) = match replica_of
             .split_whitespace()
             .collect::<Vec<&str>>()
             .as_slice()
         {
-            [host] => {
-                let host_of_replica = host
-                    .parse::<Host>()
-                    .expect("Cannot parse replica host as Host");
-
-                let port_of_host = args
-                    .get(replica_arg_position + 2)
-                    .expect("Missing port of host of replica")
-                    .parse::<Port>()
-                    .expect("Port of host of replica cannot be parsed as Port");
-
-                (host_of_replica, port_of_host)
-            }
-            [host, port] => {
-                let host_of_replica = host
-                    .parse::<Host>()
-                    .expect("Cannot parse replica host as Host");
-
-                let port_of_host = port
-                    .parse::<Port>()
-                    .expect("Port of host of replica cannot be parsed as Port");
-
-                (host_of_replica, port_of_host)
-            }
-            _ => panic!("Invalid replica command format"),
+            [] => return Err(ReplicaConfigError::MissingReplicaOfHost),
+            [_] => return Err(ReplicaConfigError::MissingReplicaOfPort),
+            [host, port] => (
+                host.parse::<Host>()
+                    .map_err(|_| ReplicaConfigError::InvalidReplicaOfHost)?,
+                port.parse::<Port>()
+                    .map_err(|_| ReplicaConfigError::InvalidReplicaOfPort)?,
+            ),
+            _ => return Err(ReplicaConfigError::InvalidReplicaOfPort),
         };
 
-        let replica_of = ReplicaOf::new(host_of_replica, port_of_host);
-
-        Self {
-            port: parse_port(args).expect("Cannot parse port as Port"),
-            replica_of,
-        }
+        Ok(Self {
+            replica_of: ReplicaOf::new(host_of_replica, port_of_host),
+            common: Common::read::<ReplicaConfigError>(directives)?,
+        })
     }
 
     /// Creates a `ReplicaConfig` from a `Config` enum.
@@ -198,7 +260,7 @@ impl ReplicaConfig {
     /// # Errors
     ///
     /// Returns an error if the `Config` is not a `Replica`.
-    pub const fn from_server_config(config: Config) -> Result<Self, ReplicaConfigError> {
+    pub fn from_server_config(config: Config) -> Result<Self, ReplicaConfigError> {
         match config {
             Config::Replica(config) => Ok(config),
             Config::Master(_) => Err(ReplicaConfigError::MissingReplicaOf),
@@ -214,12 +276,18 @@ pub enum ReplicaConfigError {
     InvalidReplicaOfHost,
     InvalidReplicaOfPort,
     InvalidPort(Error),
+    Directive(DirectiveError),
 }
 impl From<Error> for ReplicaConfigError {
     fn from(err: Error) -> Self {
         Self::InvalidPort(err)
     }
 }
+impl From<DirectiveError> for ReplicaConfigError {
+    fn from(err: DirectiveError) -> Self {
+        Self::Directive(err)
+    }
+}
 impl Display for ReplicaConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -229,24 +297,37 @@ impl Display for ReplicaConfigError {
             Self::InvalidReplicaOfHost => write!(f, "Invalid replicaof host"),
             Self::InvalidReplicaOfPort => write!(f, "Invalid replicaof port"),
             Self::InvalidPort(err) => write!(f, "Invalid port: {err}"),
+            Self::Directive(err) => write!(f, "{err}"),
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct MasterConfig {
-    port: Port,
+    common: Common,
 }
 impl MasterConfig {
+    #[must_use]
+    pub const fn persistence(&self) -> &PersistenceConfig {
+        &self.common.persistence
+    }
+    #[must_use]
+    pub const fn replication(&self) -> &ReplicationConfig {
+        &self.common.replication
+    }
     #[must_use]
     pub const fn port(&self) -> &Port {
-        &self.port
+        &self.common.port
+    }
+    #[must_use]
+    pub const fn bind(&self) -> &Host {
+        &self.common.bind
     }
     /// Parses the command line arguments and creates a `MasterConfig` from them.
     ///
     /// # Arguments
     ///
-    /// * `args` - The command line arguments.
+    /// * `args` - The command line arguments, without the program name.
     ///
     /// # Returns
     ///
@@ -255,11 +336,19 @@ impl MasterConfig {
     /// # Errors
     ///
     /// Returns an error if the arguments are invalid or missing.
-    #[must_use]
-    pub fn from_args(args: &[&str]) -> Self {
-        Self {
-            port: parse_port(args).expect("Cannot parse master port"),
-        }
+    pub fn from_args(args: &[&str]) -> Result<Self, MasterConfigError> {
+        Self::from_directives(&Directives::from_args(args)?)
+    }
+
+    /// Creates a `MasterConfig` from directives, ignoring `replicaof`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the port cannot be parsed, or if another directive is invalid.
+    pub fn from_directives(directives: &Directives) -> Result<Self, MasterConfigError> {
+        Ok(Self {
+            common: Common::read::<MasterConfigError>(directives)?,
+        })
     }
 
     /// Creates a `MasterConfig` from a `Config` enum.
@@ -275,7 +364,7 @@ impl MasterConfig {
     /// # Errors
     ///
     /// Returns an error if the `Config` is not a `Master`.
-    pub const fn from_server_config(config: Config) -> Result<Self, MasterConfigError> {
+    pub fn from_server_config(config: Config) -> Result<Self, MasterConfigError> {
         match config {
             Config::Master(config) => Ok(config),
             Config::Replica(_) => Err(MasterConfigError::MissingPort),
@@ -287,29 +376,152 @@ impl MasterConfig {
 pub enum MasterConfigError {
     MissingPort,
     InvalidPort(Error),
+    Directive(DirectiveError),
 }
 impl From<Error> for MasterConfigError {
     fn from(err: Error) -> Self {
         Self::InvalidPort(err)
     }
 }
+impl From<DirectiveError> for MasterConfigError {
+    fn from(err: DirectiveError) -> Self {
+        Self::Directive(err)
+    }
+}
 impl Display for MasterConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::MissingPort => write!(f, "Missing port"),
             Self::InvalidPort(err) => write!(f, "Invalid port: {err}"),
+            Self::Directive(err) => write!(f, "{err}"),
         }
     }
 }
 
-fn parse_port(args: &[&str]) -> Result<Port, Error> {
-    let mut port = "6379"; // default port
+/// Returns the master given by the `replicaof` directive, `None` if there is none or it is
+/// `replicaof no one`.
+fn replica_of(directives: &Directives) -> Option<String> {
+    directives
+        .get("replicaof")
+        .map(|args| args.join(" "))
+        .filter(|master| !master.eq_ignore_ascii_case("no one"))
+}
 
-    if let Some(port_arg_position) = args.iter().position(|arg| *arg == "--port") {
-        if args.len() > port_arg_position + 1 {
-            port = args[port_arg_position + 1];
+/// The settings masters and replicas share.
+#[derive(Clone, Debug)]
+struct Common {
+    port: Port,
+    bind: Host,
+    bind_addresses: Vec<String>,
+    persistence: PersistenceConfig,
+    replication: ReplicationConfig,
+    maxmemory: usize,
+    file: Option<PathBuf>,
+}
+impl Common {
+    /// Reads the address, the persistence and replication settings and the memory limit.
+    ///
+    /// `bind` takes a list of addresses, of which the server listens on the first, and
+    /// understands the `*` and `::*` wildcards and the `-` prefix of `redis.conf`.
+    fn read<E: From<Error> + From<DirectiveError>>(directives: &Directives) -> Result<Self, E> {
+        let bind = directives
+            .parse("bind", |addresses| {
+                let address = addresses
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches('-');
+                match address {
+                    "*" => "0.0.0.0",
+                    "::*" => "::",
+                    address => address,
+                }
+                .parse::<Host>()
+            })?
+            .unwrap_or_else(|| DEFAULT_BIND.parse().expect("Default bind address is valid"));
+        let bind_addresses = directives
+            .value("bind")
+            .unwrap_or(DEFAULT_BIND)
+            .split_whitespace()
+            .map(ToString::to_string)
+            .collect();
+        Ok(Self {
+            port: directives.value("port").unwrap_or(DEFAULT_PORT).parse()?,
+            bind,
+            bind_addresses,
+            persistence: PersistenceConfig::from_directives(directives)?,
+            replication: ReplicationConfig::from_directives(directives)?,
+            maxmemory: directives.parse("maxmemory", parse_memory)?.unwrap_or(0),
+            file: directives.file().map(Path::to_path_buf),
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::*;
+
+    #[test]
+    fn test_config_from_args() {
+        let config = Config::from_args(&[]).unwrap();
+        assert!(matches!(config, Config::Master(_)));
+        assert_eq!(config.port().get(), 6379);
+        assert_eq!(config.bind().get(), &IpAddr::from([127, 0, 0, 1]));
+
+        let config = Config::from_args(&["--port", "6380", "--bind", "* -::*"]).unwrap();
+        assert_eq!(config.port().get(), 6380);
+        assert_eq!(config.bind().get(), &IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.bind_addresses(), ["*", "-::*"]);
+
+        for args in [
+            &["--replicaof", "localhost 6379"][..],
+            &["--slaveof", "127.0.0.1", "6379"],
+        ] {
+            let Config::Replica(config) = Config::from_args(args).unwrap() else {
+                panic!("{args:?} is not the configuration of a replica");
+            };
+            assert_eq!(config.replica_of().port().get(), 6379);
         }
+        let config = Config::from_args(&["--replicaof", "no", "one"]).unwrap();
+        assert!(matches!(config, Config::Master(_)));
     }
 
-    port.parse()
+    #[test]
+    fn test_config_errors() {
+        assert!(matches!(
+            Config::from_args(&["--port", "80"]),
+            Err(ConfigError::Master(MasterConfigError::InvalidPort(
+                Error::Reserved
+            )))
+        ));
+        assert!(matches!(
+            Config::from_args(&["--replicaof", "localhost"]),
+            Err(ConfigError::Replica(
+                ReplicaConfigError::MissingReplicaOfPort
+            ))
+        ));
+        assert!(matches!(
+            Config::from_args(&["--replicaof", "nowhere", "6379"]),
+            Err(ConfigError::Replica(
+                ReplicaConfigError::InvalidReplicaOfHost
+            ))
+        ));
+        assert!(matches!(
+            Config::from_args(&["--appendonly", "maybe"]),
+            Err(ConfigError::Master(MasterConfigError::Directive(
+                DirectiveError::Invalid { .. }
+            )))
+        ));
+        assert!(matches!(
+            Config::from_args(&["/nonexistent/redis.conf"]),
+            Err(ConfigError::Directive(DirectiveError::Io(..)))
+        ));
+        assert!(matches!(
+            MasterConfig::from_args(&["--bind", "somewhere"]),
+            Err(MasterConfigError::Directive(_))
+        ));
+    }
 }
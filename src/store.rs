@@ -1,39 +1,290 @@
 use std::{
+    collections::HashMap,
     fmt::Display,
-    time::{Duration, Instant},
+    hash::{BuildHasher, RandomState},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use bytes::Bytes;
+
 #[derive(Clone)]
 pub struct RedisValue {
-    value: String,
+    value: Bytes,
     expiration: Option<Instant>,
 }
 impl RedisValue {
     #[must_use]
-    pub fn new(value: String, expiration: Option<Duration>) -> Self {
+    pub fn new(value: Bytes, expiration: Option<Duration>) -> Self {
         let expiration = expiration.map(|expiration| Instant::now() + expiration);
         Self { value, expiration }
     }
+    /// Creates a value expiring at the given instant, if any.
+    #[must_use]
+    pub const fn expiring_at(value: Bytes, expiration: Option<Instant>) -> Self {
+        Self { value, expiration }
+    }
     #[must_use]
-    pub fn value(&self) -> String {
-        self.value.to_string()
+    pub const fn value(&self) -> &Bytes {
+        &self.value
     }
     #[must_use]
     pub const fn expiration(&self) -> Option<Instant> {
         self.expiration
     }
+    pub const fn set_expiration(&mut self, expiration: Option<Instant>) {
+        self.expiration = expiration;
+    }
+    /// Returns the expiration as a Unix time in milliseconds, as reported by `PEXPIRETIME`.
+    #[must_use]
+    pub fn expiration_unix_millis(&self) -> Option<i64> {
+        let remaining = self.time_to_live()?;
+        Some(
+            unix_time_millis()
+                .saturating_add(i64::try_from(remaining.as_millis()).unwrap_or(i64::MAX)),
+        )
+    }
+    /// Returns the time left before the value expires, `None` if it never expires.
+    #[must_use]
+    pub fn time_to_live(&self) -> Option<Duration> {
+        self.expiration
+            .map(|expiration| expiration.saturating_duration_since(Instant::now()))
+    }
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expiration
+            .is_some_and(|expiration| Instant::now() > expiration)
+    }
+}
+
+/// Returns the current Unix time in milliseconds.
+#[must_use]
+pub fn unix_time_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| i64::try_from(now.as_millis()).unwrap_or(i64::MAX))
+}
+
+/// Converts a Unix time in milliseconds into an `Instant`, `None` if it is already past.
+#[must_use]
+pub fn instant_from_unix_millis(unix_millis: i64) -> Option<Instant> {
+    let remaining = u64::try_from(unix_millis.saturating_sub(unix_time_millis())).ok()?;
+    (remaining > 0).then(|| Instant::now() + Duration::from_millis(remaining))
 }
 
 impl Display for RedisValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = String::from_utf8_lossy(&self.value);
         match self.expiration {
             Some(expiration) => write!(
                 f,
-                "{}:{}ms",
-                self.value,
+                "{value}:{}ms",
                 expiration.duration_since(Instant::now()).as_millis()
             ),
-            None => write!(f, "{}", self.value),
+            None => write!(f, "{value}"),
+        }
+    }
+}
+
+/// The keys of the server and their values.
+///
+/// Keys with an expiration are also indexed in `volatile` so that the active expiration
+/// cycle can sample them at random without walking the whole keyspace.
+///
+/// Like Redis, only a master deletes expired keys, and the deletion is propagated as a
+/// `DEL` so that its replicas and append-only file stay consistent. A replica hides them
+/// from reads until its master's `DEL` arrives.
+#[derive(Default)]
+pub struct Keyspace {
+    values: HashMap<Bytes, RedisValue>,
+    volatile: Vec<Bytes>,
+    /// Position of each key of `volatile`, to untrack it in constant time.
+    volatile_positions: HashMap<Bytes, usize>,
+    expired_keys: u64,
+    expired_stale_perc: f64,
+    /// Number of changes since the last successful save.
+    dirty: u64,
+    /// Whether this is the dataset of a replica, which keeps the expired keys.
+    replica: bool,
+    /// Keys deleted because they expired, whose deletion has not been propagated yet.
+    expired: Vec<Bytes>,
+}
+impl Keyspace {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of a key, deleting it first if it has expired.
+    pub fn get(&mut self, key: &[u8]) -> Option<&RedisValue> {
+        self.expire_if_needed(key);
+        self.values.get(key).filter(|value| !value.is_expired())
+    }
+
+    pub fn insert(&mut self, key: Bytes, value: RedisValue) {
+        if value.expiration().is_some() {
+            self.track(key.clone());
+        } else {
+            self.untrack(&key);
+        }
+        self.values.insert(key, value);
+        self.dirty += 1;
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<RedisValue> {
+        self.untrack(key);
+        let removed = self.values.remove(key);
+        if removed.is_some() {
+            self.dirty += 1;
+        }
+        removed
+    }
+
+    /// Returns whether the key is present, expired or not, without deleting it if it
+    /// expired.
+    #[must_use]
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Changes the expiration of a key, returns `false` if the key does not exist.
+    pub fn set_expiration(&mut self, key: &Bytes, expiration: Option<Instant>) -> bool {
+        self.expire_if_needed(key);
+        let Some(value) = self.values.get_mut(key) else {
+            return false;
+        };
+        value.set_expiration(expiration);
+        if expiration.is_some() {
+            self.track(key.clone());
+        } else {
+            self.untrack(key);
+        }
+        self.dirty += 1;
+        true
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the keys that have not expired, with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &RedisValue)> {
+        self.values.iter().filter(|(_, value)| !value.is_expired())
+    }
+
+    /// Makes expired keys be kept until the master deletes them, or deleted again.
+    pub const fn set_replica(&mut self, replica: bool) {
+        self.replica = replica;
+    }
+
+    #[must_use]
+    pub const fn is_replica(&self) -> bool {
+        self.replica
+    }
+
+    /// Returns the keys deleted because they expired since the last call, for their
+    /// deletion to be propagated.
+    pub fn take_expired(&mut self) -> Vec<Bytes> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Returns the number of changes since the last successful save.
+    #[must_use]
+    pub const fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// Forgets the `saved` changes once they have been written to disk, keeping those made
+    /// while the snapshot was being written.
+    pub const fn mark_saved(&mut self, saved: u64) {
+        self.dirty = self.dirty.saturating_sub(saved);
+    }
+
+    /// Returns the number of keys deleted because they expired, lazily or actively.
+    #[must_use]
+    pub const fn expired_keys(&self) -> u64 {
+        self.expired_keys
+    }
+
+    /// Returns the estimated percentage of keys that are expired but not deleted yet.
+    #[must_use]
+    pub fn expired_stale_perc(&self) -> f64 {
+        self.expired_stale_perc * 100.0
+    }
+
+    /// Clears the expiration statistics, for `CONFIG RESETSTAT`.
+    pub const fn reset_stats(&mut self) {
+        self.expired_keys = 0;
+        self.expired_stale_perc = 0.0;
+    }
+
+    /// Samples up to `count` keys with an expiration at random and deletes the expired ones.
+    ///
+    /// Returns the number of sampled keys and the number of deleted keys.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let random = RandomState::new();
+        let sampled = count.min(self.volatile.len());
+        let mut expired = 0;
+        for draw in 0..sampled {
+            let Ok(len) = u64::try_from(self.volatile.len()) else {
+                break;
+            };
+            if len == 0 {
+                break;
+            }
+            let index = usize::try_from(random.hash_one(draw) % len).unwrap_or_default();
+            let key = self.volatile[index].clone();
+            if self.expire_if_needed(&key) {
+                expired += 1;
+            }
+        }
+        (sampled, expired)
+    }
+
+    /// Folds the outcome of an active expiration cycle into the estimated percentage of
+    /// stale keys, giving 5% of the weight to the latest cycle.
+    pub fn record_expire_cycle(&mut self, sampled: usize, expired: usize) {
+        let ratio = |count: usize| f64::from(u32::try_from(count).unwrap_or(u32::MAX));
+        let current = if sampled == 0 {
+            0.0
+        } else {
+            ratio(expired) / ratio(sampled)
+        };
+        self.expired_stale_perc = current.mul_add(0.05, self.expired_stale_perc * 0.95);
+    }
+
+    /// Deletes a key if it has expired and returns whether it did, a replica never
+    /// deleting any.
+    fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        if self.replica || !self.values.get(key).is_some_and(RedisValue::is_expired) {
+            return false;
+        }
+        self.remove(key);
+        self.expired_keys += 1;
+        self.expired.push(Bytes::copy_from_slice(key));
+        true
+    }
+
+    fn track(&mut self, key: Bytes) {
+        if !self.volatile_positions.contains_key(&key) {
+            self.volatile_positions
+                .insert(key.clone(), self.volatile.len());
+            self.volatile.push(key);
+        }
+    }
+
+    fn untrack(&mut self, key: &[u8]) {
+        let Some(position) = self.volatile_positions.remove(key) else {
+            return;
+        };
+        self.volatile.swap_remove(position);
+        if let Some(moved) = self.volatile.get(position) {
+            self.volatile_positions.insert(moved.clone(), position);
         }
     }
 }
@@ -45,43 +296,153 @@ mod tests {
 
     use super::*;
     use std::{
-        collections::HashMap,
         sync::{Arc, Mutex},
         thread::sleep,
     };
 
     #[test]
     fn test_redis_value_new() {
-        let value = "test".to_string();
+        let value = Bytes::from("test");
         let expiration = Some(Duration::from_secs(1));
         let redis_value = RedisValue::new(value.clone(), expiration);
-        assert_eq!(redis_value.value(), value);
+        assert_eq!(redis_value.value(), &value);
         assert!(redis_value.expiration().is_some());
     }
 
     #[test]
     fn test_redis_value_no_expiration() {
-        let value = "test".to_string();
+        let value = Bytes::from("test");
         let redis_value = RedisValue::new(value.clone(), None);
-        assert_eq!(redis_value.value(), value);
+        assert_eq!(redis_value.value(), &value);
         assert!(redis_value.expiration().is_none());
     }
 
     #[test]
     fn test_redis_value_expiration() {
-        let value = "test".to_string();
+        let value = Bytes::from("test");
         let expiration = Some(Duration::from_secs(1));
         let redis_value = RedisValue::new(value, expiration);
         sleep(Duration::from_secs(2));
         assert!(Instant::now() >= redis_value.expiration().unwrap());
+        assert!(redis_value.is_expired());
+        assert!(!RedisValue::new(Bytes::from("test"), None).is_expired());
+    }
+
+    #[test]
+    fn test_redis_value_unix_expiration() {
+        let now = unix_time_millis();
+        let deadline = instant_from_unix_millis(now + 10_000).unwrap();
+        let redis_value = RedisValue::expiring_at(Bytes::from("test"), Some(deadline));
+        let expiration = redis_value.expiration_unix_millis().unwrap();
+        assert!((now + 9_900..=now + 10_100).contains(&expiration));
+        assert!(instant_from_unix_millis(now - 1).is_none());
+        assert!(RedisValue::new(Bytes::from("test"), None)
+            .expiration_unix_millis()
+            .is_none());
     }
 
     #[test]
     fn test_redis_store() {
-        let store: RedisStore = Arc::new(Mutex::new(HashMap::new()));
+        let store: RedisStore = Arc::new(Mutex::new(Keyspace::new()));
         let mut store = store.lock().unwrap();
-        let value = RedisValue::new("test".to_string(), None);
-        store.insert("key".to_string(), value.clone());
-        assert_eq!(store.get("key").unwrap().value(), value.value());
+        let value = RedisValue::new(Bytes::from("test"), None);
+        store.insert(Bytes::from("key"), value.clone());
+        assert_eq!(store.get(b"key".as_slice()).unwrap().value(), value.value());
+    }
+
+    #[test]
+    fn test_keyspace_lazy_expiration() {
+        let mut keyspace = Keyspace::new();
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        keyspace.insert(
+            Bytes::from("gone"),
+            RedisValue::expiring_at(Bytes::from("v"), Some(past)),
+        );
+        assert_eq!(keyspace.len(), 1);
+        assert!(keyspace.get(b"gone").is_none());
+        assert!(keyspace.is_empty());
+        assert_eq!(keyspace.expired_keys(), 1);
+        assert_eq!(keyspace.take_expired(), vec![Bytes::from("gone")]);
+        assert!(keyspace.take_expired().is_empty());
+    }
+
+    #[test]
+    fn test_replica_keeps_expired_keys() {
+        let mut keyspace = Keyspace::new();
+        keyspace.set_replica(true);
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        keyspace.insert(
+            Bytes::from("stale"),
+            RedisValue::expiring_at(Bytes::from("v"), Some(past)),
+        );
+        assert!(keyspace.get(b"stale").is_none());
+        assert_eq!(keyspace.expire_sample(20), (1, 0));
+        assert_eq!(keyspace.len(), 1);
+        assert_eq!(keyspace.expired_keys(), 0);
+        assert!(keyspace.take_expired().is_empty());
+        assert!(keyspace.contains_key(b"stale"));
+        keyspace.remove(b"stale");
+        assert!(!keyspace.contains_key(b"stale"));
+        assert!(keyspace.is_empty());
+    }
+
+    #[test]
+    fn test_keyspace_expire_sample() {
+        let mut keyspace = Keyspace::new();
+        let past = Instant::now()
+            .checked_sub(Duration::from_millis(1))
+            .unwrap();
+        for i in 0..50 {
+            let value = RedisValue::expiring_at(Bytes::from("v"), Some(past));
+            keyspace.insert(Bytes::from(format!("expired:{i}")), value);
+        }
+        keyspace.insert(
+            Bytes::from("volatile"),
+            RedisValue::new(Bytes::from("v"), Some(Duration::from_secs(90))),
+        );
+        keyspace.insert(
+            Bytes::from("persistent"),
+            RedisValue::new(Bytes::from("v"), None),
+        );
+
+        let mut expired = 0;
+        while keyspace.len() > 2 {
+            let (sampled, deleted) = keyspace.expire_sample(20);
+            assert!(sampled <= 20);
+            expired += deleted;
+        }
+        assert_eq!(expired, 50);
+        assert_eq!(keyspace.expired_keys(), 50);
+        assert!(keyspace.get(b"volatile").is_some());
+        assert_eq!(keyspace.expire_sample(20), (1, 0));
+
+        assert!(keyspace.set_expiration(&Bytes::from("volatile"), None));
+        assert_eq!(keyspace.expire_sample(20), (0, 0));
+    }
+
+    #[test]
+    fn test_keyspace_dirty_counter() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(Bytes::from("a"), RedisValue::new(Bytes::from("v"), None));
+        keyspace.insert(Bytes::from("b"), RedisValue::new(Bytes::from("v"), None));
+        assert!(keyspace.set_expiration(&Bytes::from("a"), None));
+        assert!(keyspace.remove(b"missing").is_none());
+        assert_eq!(keyspace.dirty(), 3);
+        let saved = keyspace.dirty();
+        keyspace.remove(b"b");
+        keyspace.mark_saved(saved);
+        assert_eq!(keyspace.dirty(), 1);
+        assert_eq!(keyspace.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_keyspace_expired_stale_perc() {
+        let mut keyspace = Keyspace::new();
+        keyspace.record_expire_cycle(20, 10);
+        assert!((keyspace.expired_stale_perc() - 2.5).abs() < f64::EPSILON);
     }
 }